@@ -36,7 +36,9 @@ local defaults = {
   -- Optional MultiappV1 Tauri Sidecar integration
   sidecar_enabled = false,
   sidecar_url = 'http://localhost:8765',
-  sidecar_auto_approve_read_ops = true
+  sidecar_auto_approve_read_ops = true,
+  -- Run terminal tool commands in a visible sidecar terminal instead of io.popen
+  sidecar_terminal = false
 }
 
 local function expanduser(path)
@@ -42,8 +42,39 @@ local function check_sidecar_availability()
     return sidecar_available
 end
 
--- Send an HTTP POST request to the sidecar
-local function send_sidecar_request(endpoint, data)
+-- Token the sidecar writes to its app data dir at each launch; terminal
+-- requests must carry it
+local function terminal_token_path()
+    local data_home = os.getenv('XDG_DATA_HOME') or ((os.getenv('HOME') or '') .. '/.local/share')
+    return data_home .. '/com.multiappv1.sidecar/terminal-token'
+end
+
+-- Write the token header to a private temp file (os.tmpname creates it 0600),
+-- so the token stays out of the process list; returns its path
+local function terminal_header_file()
+    local f = io.open(terminal_token_path(), 'r')
+    if not f then
+        return nil, 'Sidecar terminal token not found at ' .. terminal_token_path()
+    end
+    local token = f:read('*l')
+    f:close()
+    if not token or token == '' then
+        return nil, 'Sidecar terminal token is empty'
+    end
+    
+    local path = os.tmpname()
+    local h = io.open(path, 'w')
+    if not h then
+        return nil, 'Failed to create temporary file'
+    end
+    h:write('X-Sidecar-Token: ' .. token .. '\n')
+    h:close()
+    return path
+end
+
+-- Send an HTTP POST request to the sidecar; max_time defaults to 5 seconds.
+-- header_file, if given, holds extra request headers.
+local function send_sidecar_request(endpoint, data, max_time, header_file)
     if not check_sidecar_availability() then
         return false, 'Sidecar not available'
     end
@@ -62,8 +93,8 @@ local function send_sidecar_request(endpoint, data)
     
     -- Make POST request using curl
     local cmd = string.format(
-        'curl -s --connect-timeout 2 --max-time 5 -X POST -H "Content-Type: application/json" --data @%s "%s" 2>/dev/null',
-        temp_file, url
+        'curl -s --connect-timeout 2 --max-time %d -X POST -H "Content-Type: application/json"%s --data @%s "%s" 2>/dev/null',
+        max_time or 5, header_file and (' -H @' .. header_file) or '', temp_file, url
     )
     
     local success, exit_code, output = utils.execute_command(cmd, true)
@@ -152,6 +183,108 @@ function M.get_pending_operations()
     return operations or {}
 end
 
+-- POST to a terminal endpoint, with the terminal token
+local function send_terminal_request(endpoint, data, max_time)
+    local header_file, err = terminal_header_file()
+    if not header_file then
+        return false, err
+    end
+    local success, response = send_sidecar_request(endpoint, data, max_time, header_file)
+    os.remove(header_file)
+    return success, response
+end
+
+-- Terminal session shared with the sidecar UI, opened on first use
+local terminal_session_id = nil
+
+-- Open a terminal session in the sidecar running the user's configured shell;
+-- options may set cwd, rows and cols. Returns its id.
+function M.open_terminal(options)
+    local success, response = send_terminal_request('/terminal/sessions', options or {})
+    if not success then
+        return nil, response
+    end
+    
+    local session = json.decode(response)
+    if not session or not session.id then
+        return nil, 'Unexpected response from sidecar: ' .. response
+    end
+    terminal_session_id = session.id
+    return session.id
+end
+
+-- Run a command in the shared sidecar terminal, where the user can watch and
+-- interrupt it. Returns the exec result: status, exit_code, output, truncated.
+function M.run_in_terminal(command, timeout_secs)
+    if not config.get().sidecar_enabled then
+        return nil, 'Sidecar integration disabled'
+    end
+    timeout_secs = timeout_secs or 120
+    
+    for _ = 1, 2 do
+        if not terminal_session_id then
+            local _, err = M.open_terminal()
+            if err then
+                return nil, err
+            end
+        end
+        
+        local success, response = send_terminal_request(
+            '/terminal/' .. terminal_session_id .. '/exec',
+            { command = command, timeout_secs = timeout_secs },
+            timeout_secs + 5
+        )
+        if not success then
+            return nil, response
+        end
+        
+        local result = json.decode(response)
+        if result and result.status then
+            return result
+        end
+        if response ~= 'Terminal session not found' then
+            return nil, response
+        end
+        -- The shell was closed; open a new one and try once more
+        terminal_session_id = nil
+    end
+    
+    return nil, 'Failed to open a sidecar terminal'
+end
+
+-- Recent commands of the shared sidecar terminal, from its shell integration:
+-- command, output, exit_code and duration_ms for each, oldest first
+function M.get_terminal_commands(limit)
+    if not config.get().sidecar_enabled then
+        return nil, 'Sidecar integration disabled'
+    end
+    if not terminal_session_id then
+        return nil, 'No sidecar terminal session is open'
+    end
+    
+    local header_file, err = terminal_header_file()
+    if not header_file then
+        return nil, err
+    end
+    local url = string.format('%s/terminal/%s/commands?limit=%d', SIDECAR_BASE_URL, terminal_session_id, limit or 10)
+    local cmd = string.format('curl -s --connect-timeout 2 --max-time 5 -H @%s "%s" 2>/dev/null', header_file, url)
+    
+    local success, exit_code, output = utils.execute_command(cmd, true)
+    os.remove(header_file)
+    if not success or exit_code ~= 0 then
+        return nil, 'HTTP request failed: ' .. (output or 'unknown error')
+    end
+    
+    local result = json.decode(output)
+    if not result or not result.commands then
+        if output == 'Terminal session not found' then
+            terminal_session_id = nil
+        end
+        return nil, output
+    end
+    return result
+end
+
 -- Check if sidecar is enabled and available
 function M.is_available()
     local cfg = config.get()
@@ -217,6 +217,22 @@ M.registry = {
     end
   },
 
+  terminal_history = {
+    description = 'List commands recently run in the sidecar terminal with their output, exit code and duration',
+    input_schema = {
+      type = 'object',
+      properties = {
+        limit = {
+          type = 'integer',
+          description = 'Maximum number of commands to return, most recent last (default 10)'
+        }
+      }
+    },
+    handler = function(args)
+      return terminal.recent_commands(args.limit or 10)
+    end
+  },
+
   -- Editor operations
   open_in_editor = {
     description = 'Open a file in the active Geany editor buffer',
@@ -68,6 +68,28 @@ function M.run_command(cmd, cwd_rel)
     prefix = 'cd "' .. cwd_abs .. '" && '
   end
   
+  -- Prefer a sidecar terminal the user can watch and interrupt
+  if config.get().sidecar_terminal then
+    local sidecar = require('sidecar_connector')
+    if sidecar.is_available() then
+      -- A subshell keeps the cd from moving the shared shell; the newline ends any trailing comment
+      local result, err = sidecar.run_in_terminal('(' .. prefix .. cmd .. '\n)')
+      if result then
+        local res = { exit_code = result.exit_code or -1, stdout = result.output or '' }
+        if result.status == 'interrupted' then
+          res.error = 'command interrupted by the user'
+        elseif result.status == 'timed_out' then
+          res.error = 'command timed out and was interrupted in the sidecar terminal'
+        elseif result.status == 'shell_exited' then
+          res.error = 'the sidecar terminal exited'
+        end
+        return res
+      end
+      -- Fall back to running the command here
+      require('utils').log_warning('Sidecar terminal unavailable: ' .. tostring(err))
+    end
+  end
+  
   -- Execute command
   local handle = io.popen(prefix .. cmd)
   if not handle then 
@@ -88,4 +110,21 @@ function M.run_command(cmd, cwd_rel)
   }
 end
 
+-- Commands recently run in the sidecar terminal, with their output and exit codes
+function M.recent_commands(limit)
+  if not config.get().sidecar_terminal then
+    return { error = 'sidecar terminal is disabled' }
+  end
+  
+  local result, err = require('sidecar_connector').get_terminal_commands(limit)
+  if not result then
+    return { error = err }
+  end
+  if not result.shell_integration then
+    return { error = 'the sidecar terminal shell does not report its commands' }
+  end
+  
+  return { commands = result.commands }
+end
+
 return M
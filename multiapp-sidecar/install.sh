@@ -67,7 +67,7 @@ echo "  http://localhost:8765  # HTTP API"
 echo "  GUI Application        # Tauri desktop app"
 echo
 echo "To enable sidecar integration in GeanyLua:"
-echo "  1. Set MULTIAPP_PASSPHRASE environment variable (optional, for encryption)"
+echo "  1. Set MULTIAPP_PASSPHRASE_FILE to a mode 600 passfile (optional, for encryption)"
 echo "  2. Add 'sidecar_enabled = true' to your CodingBuddy config.json"
 echo "  3. Restart Geany"
 echo
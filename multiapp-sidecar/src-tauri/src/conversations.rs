@@ -0,0 +1,179 @@
+// CodingBuddy conversation files for MultiappV1 Sidecar
+// Lists, opens and re-encrypts the plugin's conversations (plain `.json` or openssl `.json.enc`)
+
+use crate::openssl_enc;
+use crate::passphrase::Secret;
+use crate::persistence;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Environment variable `codingbuddy/crypto.lua` reads its passphrase from
+pub const PASSPHRASE_ENV: &str = "CODINGBUDDY_PASSPHRASE";
+
+const PLAIN_SUFFIX: &str = ".json";
+const ENCRYPTED_SUFFIX: &str = ".json.enc";
+
+#[derive(Debug, Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub encrypted: bool,
+    pub readable: bool, // false when an encrypted file couldn't be decrypted
+}
+
+/// Same location the plugin's conversation_manager.lua uses
+pub fn conversations_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".config/geany/plugins/geanylua/codingbuddy/conversations")
+}
+
+fn passphrase_or_env(passphrase: Option<String>) -> Option<Secret> {
+    passphrase
+        .or_else(|| std::env::var(PASSPHRASE_ENV).ok())
+        .filter(|p| !p.is_empty())
+        .map(Secret::new)
+}
+
+/// Conversation ids become file names; keep them inside the directory
+fn conversation_path(dir: &Path, id: &str, suffix: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || id.contains('/') || id.contains('\\') || id.starts_with('.') {
+        return Err(format!("Invalid conversation id: {}", id));
+    }
+    Ok(dir.join(format!("{}{}", id, suffix)))
+}
+
+/// Decrypt an openssl file and check it holds JSON; CBC alone can't detect a wrong key reliably
+fn decrypt_conversation(path: &Path, passphrase: &str) -> Result<serde_json::Value, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let plaintext = openssl_enc::decrypt(&data, passphrase).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&plaintext)
+        .map_err(|_| format!("{} did not decrypt to JSON; wrong passphrase?", path.display()))
+}
+
+fn read_plain(path: &Path) -> Result<serde_json::Value, String> {
+    let content = std::fs::read(path).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&content).map_err(|e| e.to_string())
+}
+
+/// All conversations, newest first. A plain file wins over an encrypted one with
+/// the same id, matching the plugin's load order.
+pub fn scan_conversations(dir: &Path, passphrase: Option<&str>) -> Result<Vec<ConversationSummary>, String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut files: Vec<(String, bool)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(id) = name.strip_suffix(ENCRYPTED_SUFFIX) {
+                Some((id.to_string(), true))
+            } else {
+                name.strip_suffix(PLAIN_SUFFIX).map(|id| (id.to_string(), false))
+            }
+        })
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    files.dedup_by(|later, earlier| later.0 == earlier.0);
+
+    let mut conversations: Vec<ConversationSummary> = files.into_iter()
+        .map(|(id, encrypted)| {
+            let content = if encrypted {
+                passphrase.and_then(|p| decrypt_conversation(&dir.join(format!("{}{}", id, ENCRYPTED_SUFFIX)), p).ok())
+            } else {
+                read_plain(&dir.join(format!("{}{}", id, PLAIN_SUFFIX))).ok()
+            };
+            let field = |key: &str| content.as_ref()
+                .and_then(|c| c.get(key))
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+
+            ConversationSummary {
+                title: field("title"),
+                created_at: field("created_at"),
+                updated_at: field("updated_at").or_else(|| field("created_at")),
+                readable: content.is_some(),
+                encrypted,
+                id,
+            }
+        })
+        .collect();
+
+    conversations.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    Ok(conversations)
+}
+
+pub fn load_conversation(dir: &Path, id: &str, passphrase: Option<&str>) -> Result<serde_json::Value, String> {
+    let plain = conversation_path(dir, id, PLAIN_SUFFIX)?;
+    if plain.exists() {
+        return read_plain(&plain);
+    }
+
+    let encrypted = conversation_path(dir, id, ENCRYPTED_SUFFIX)?;
+    if !encrypted.exists() {
+        return Err(format!("Conversation {} not found", id));
+    }
+    let passphrase = passphrase
+        .ok_or_else(|| format!("Conversation {} is encrypted; set {} or pass a passphrase", id, PASSPHRASE_ENV))?;
+    decrypt_conversation(&encrypted, passphrase)
+}
+
+/// Re-encrypt every `.json.enc` file under a new passphrase. All files are decrypted
+/// before any is written, so a wrong old passphrase changes nothing.
+pub fn reencrypt_all(dir: &Path, old: &str, new: &str) -> Result<usize, String> {
+    if new.is_empty() {
+        return Err("Passphrase must not be empty".to_string());
+    }
+
+    // Every encrypted file, including ones shadowed by a plain file of the same id
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.to_string_lossy().ends_with(ENCRYPTED_SUFFIX))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    paths.sort();
+
+    let mut decrypted = Vec::new();
+    for path in paths {
+        let conversation = decrypt_conversation(&path, old)?;
+        decrypted.push((path, serde_json::to_vec_pretty(&conversation).map_err(|e| e.to_string())?));
+    }
+
+    for (path, plaintext) in &decrypted {
+        persistence::write_atomic(path, &openssl_enc::encrypt(plaintext, new)).map_err(|e| e.to_string())?;
+    }
+    Ok(decrypted.len())
+}
+
+// Tauri command handlers
+#[tauri::command]
+pub async fn list_conversations(passphrase: Option<String>) -> Result<Vec<ConversationSummary>, String> {
+    let passphrase = passphrase_or_env(passphrase);
+    tokio::task::spawn_blocking(move || scan_conversations(&conversations_dir(), passphrase.as_ref().map(|p| p.as_str())))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub async fn open_conversation(id: String, passphrase: Option<String>) -> Result<serde_json::Value, String> {
+    let passphrase = passphrase_or_env(passphrase);
+    tokio::task::spawn_blocking(move || load_conversation(&conversations_dir(), &id, passphrase.as_ref().map(|p| p.as_str())))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub async fn reencrypt_conversations(old_passphrase: String, new_passphrase: String) -> Result<usize, String> {
+    let (old_passphrase, new_passphrase) = (Secret::new(old_passphrase), Secret::new(new_passphrase));
+    tokio::task::spawn_blocking(move || reencrypt_all(&conversations_dir(), &old_passphrase, &new_passphrase))
+        .await
+        .map_err(|e| e.to_string())?
+}
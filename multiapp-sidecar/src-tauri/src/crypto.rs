@@ -2,14 +2,20 @@
 // Provides AES-GCM encryption with Argon2 key derivation for state persistence
 
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
     Aes256Gcm, Key, Nonce,
 };
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::{rand_core::RngCore, SaltString}};
+use argon2::{Algorithm, Argon2, Params, Version, password_hash::rand_core::RngCore};
 use base64::{Engine as _, engine::general_purpose};
+use crate::crypto_stream::{
+    self, StreamDecryptor, StreamEncryptor, StreamHeader, CIPHER_AES_256_GCM_STREAM, STREAM_CHUNK_SIZE, STREAM_VERSION,
+};
+use crate::passphrase::Secret;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io::{Read, Write};
 use thiserror::Error;
+use zeroize::Zeroizing;
 
 #[derive(Error, Debug)]
 pub enum CryptoError {
@@ -21,33 +27,166 @@ pub enum CryptoError {
     KeyDerivationFailed(String),
     #[error("Invalid passphrase")]
     InvalidPassphrase,
+    #[error("Unsupported encryption envelope: {0}")]
+    UnsupportedEnvelope(String),
+    #[error("Encrypted data belongs elsewhere: {0}")]
+    BindingMismatch(String),
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Envelope layout written by this build. Envelopes without a `version` field
+/// (version 0) are refused: the build that wrote them never derived a key
+/// successfully, so none hold data. Version 2 added the artifact binding;
+/// older envelopes have none.
+pub const ENVELOPE_VERSION: u32 = 2;
+const BOUND_ENVELOPE_VERSION: u32 = 2;
+pub const CIPHER_AES_256_GCM: &str = "aes-256-gcm";
+pub const KDF_ARGON2ID: &str = "argon2id";
+
+// Upper bounds for parameters read from files, so a tampered header can't make
+// key derivation allocate gigabytes or spin forever
+const MAX_M_COST: u32 = 1024 * 1024; // KiB, i.e. 1 GiB
+const MAX_T_COST: u32 = 64;
+const MAX_P_COST: u32 = 16;
+
+/// Key derivation settings recorded in every envelope
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub version: u32, // Argon2 version, 0x13
+    pub m_cost: u32,  // memory in KiB
+    pub t_cost: u32,  // iterations
+    pub p_cost: u32,  // lanes
+    pub key_len: usize,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            algorithm: KDF_ARGON2ID.to_string(),
+            version: Version::V0x13 as u32,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+            key_len: 32,
+        }
+    }
+}
+
+/// What a ciphertext is bound to. Its encoding is the AEAD associated data, so a
+/// blob moved to another file, row or artifact kind fails to decrypt.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArtifactBinding {
+    pub kind: String,        // e.g. "state", "state-backup", "operation-payload"
+    pub path: String,        // logical location, relative to the app data dir
+    pub schema_version: u32, // schema of the plaintext when it was sealed
+}
+
+impl ArtifactBinding {
+    pub fn new(kind: &str, path: impl Into<String>, schema_version: u32) -> Self {
+        Self { kind: kind.to_string(), path: path.into(), schema_version }
+    }
+
+    /// Length-prefixed fields, so no choice of kind or path can collide with another
+    pub fn aad(&self) -> Vec<u8> {
+        let mut aad = b"multiapp-sidecar-aad-v1".to_vec();
+        for field in [self.kind.as_bytes(), self.path.as_bytes()] {
+            aad.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            aad.extend_from_slice(field);
+        }
+        aad.extend_from_slice(&self.schema_version.to_be_bytes());
+        aad
+    }
+
+    /// Check a binding read from a file against where it was found
+    pub fn expect(&self, kind: &str, path: &str) -> Result<(), CryptoError> {
+        if self.kind != kind || self.path != path {
+            return Err(CryptoError::BindingMismatch(format!(
+                "sealed as {} {}, found as {} {}", self.kind, self.path, kind, path
+            )));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct EncryptedData {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<KdfParams>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binding: Option<ArtifactBinding>,
     pub salt: String,
     pub nonce: String,
     pub ciphertext: String,
 }
 
 pub struct CryptoManager {
-    passphrase: Option<String>,
-    key_cache: HashMap<String, Vec<u8>>, // Cache derived keys by salt
+    passphrase: Option<Secret>,
+    enabled: bool, // Whether new writes are encrypted; mirrors SidecarConfig.encryption_enabled
+    kdf: KdfParams, // Parameters for new writes; reads use whatever the envelope records
+    key_cache: HashMap<String, Zeroizing<Vec<u8>>>, // Derived keys by KDF parameters and salt; wiped on drop
+    session_salt: Option<String>, // Reused across writes so Argon2 runs once per session
 }
 
 impl CryptoManager {
-    pub fn new(passphrase: Option<String>) -> Self {
+    pub fn new(passphrase: Option<Secret>) -> Self {
         Self {
+            enabled: passphrase.is_some(),
             passphrase,
+            kdf: KdfParams::default(),
             key_cache: HashMap::new(),
+            session_salt: None,
+        }
+    }
+
+    /// Fresh manager with the same passphrase and mode, without the key cache
+    pub fn fork(&self) -> Self {
+        Self {
+            enabled: self.enabled,
+            kdf: self.kdf.clone(),
+            ..Self::new(self.passphrase.clone())
+        }
+    }
+
+    /// Replace the passphrase; derived keys and the session salt are dropped with it
+    pub fn set_passphrase(&mut self, passphrase: Option<Secret>) {
+        self.passphrase = passphrase;
+        self.key_cache.clear();
+        self.session_salt = None;
+    }
+
+    /// Switch to `new` after checking `old` against the current passphrase. Returns a
+    /// manager with the old passphrase for reading data written before the switch;
+    /// the next write picks a fresh salt.
+    pub fn rotate_passphrase(&mut self, old: &str, new: Secret) -> Result<CryptoManager, CryptoError> {
+        if self.passphrase.as_deref().map(String::as_str) != Some(old) {
+            return Err(CryptoError::InvalidPassphrase);
         }
+        let previous = self.fork();
+        self.set_passphrase(Some(new));
+        Ok(previous)
+    }
+
+    pub fn has_passphrase(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
+    /// Turn encryption of new writes on or off. Decryption only needs the passphrase.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
     }
 
     /// Derive key from passphrase using Argon2
-    fn derive_key(&mut self, salt: &str) -> Result<Vec<u8>, CryptoError> {
-        if let Some(cached_key) = self.key_cache.get(salt) {
+    fn derive_key(&mut self, salt: &str, kdf: &KdfParams) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        let cache_key = format!("{}|{}", serde_json::to_string(kdf)?, salt);
+        if let Some(cached_key) = self.key_cache.get(&cache_key) {
             return Ok(cached_key.clone());
         }
 
@@ -58,36 +197,47 @@ impl CryptoManager {
             .decode(salt)
             .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
 
-        let salt_string = SaltString::from_b64(&general_purpose::STANDARD.encode(&salt_bytes))
-            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
-
-        let argon2 = Argon2::default();
-        let password_hash = argon2
-            .hash_password(passphrase.as_bytes(), &salt_string)
-            .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
-
-        let key = password_hash.hash
-            .ok_or_else(|| CryptoError::KeyDerivationFailed("No hash generated".to_string()))?
-            .as_bytes()[..32] // Use first 32 bytes for AES-256
-            .to_vec();
+        let key = derive_raw_key(passphrase, &salt_bytes, kdf)?;
 
-        self.key_cache.insert(salt.to_string(), key.clone());
+        self.key_cache.insert(cache_key, key.clone());
         Ok(key)
     }
 
-    /// Encrypt data with AES-GCM
-    pub fn encrypt(&mut self, data: &[u8]) -> Result<EncryptedData, CryptoError> {
+    /// Salt, KDF parameters and key for new writes. The salt is random per session;
+    /// every message or stream still gets its own nonce.
+    fn session_key(&mut self) -> Result<(String, KdfParams, Zeroizing<Vec<u8>>), CryptoError> {
         if self.passphrase.is_none() {
             return Err(CryptoError::InvalidPassphrase);
         }
 
-        // Generate random salt for key derivation
-        let mut salt = [0u8; 16];
-        OsRng.fill_bytes(&mut salt);
-        let salt_b64 = general_purpose::STANDARD.encode(salt);
+        let salt_b64 = match &self.session_salt {
+            Some(salt) => salt.clone(),
+            None => {
+                let mut salt = [0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                let salt_b64 = general_purpose::STANDARD.encode(salt);
+                self.session_salt = Some(salt_b64.clone());
+                salt_b64
+            }
+        };
+
+        let kdf = self.kdf.clone();
+        let key = self.derive_key(&salt_b64, &kdf)?;
+        Ok((salt_b64, kdf, key))
+    }
+
+    /// Encrypt data with AES-GCM
+    pub fn encrypt(&mut self, data: &[u8]) -> Result<EncryptedData, CryptoError> {
+        self.seal(data, None)
+    }
+
+    /// Encrypt data bound to `binding`; see `decrypt_bound`
+    pub fn encrypt_bound(&mut self, data: &[u8], binding: &ArtifactBinding) -> Result<EncryptedData, CryptoError> {
+        self.seal(data, Some(binding))
+    }
 
-        // Derive key
-        let key_bytes = self.derive_key(&salt_b64)?;
+    fn seal(&mut self, data: &[u8], binding: Option<&ArtifactBinding>) -> Result<EncryptedData, CryptoError> {
+        let (salt_b64, kdf, key_bytes) = self.session_key()?;
         let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
         let cipher = Aes256Gcm::new(key);
 
@@ -95,11 +245,16 @@ impl CryptoManager {
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
         
         // Encrypt
+        let aad = binding.map(ArtifactBinding::aad).unwrap_or_default();
         let ciphertext = cipher
-            .encrypt(&nonce, data)
+            .encrypt(&nonce, Payload { msg: data, aad: &aad })
             .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
 
         Ok(EncryptedData {
+            version: ENVELOPE_VERSION,
+            cipher: Some(CIPHER_AES_256_GCM.to_string()),
+            kdf: Some(kdf),
+            binding: binding.cloned(),
             salt: salt_b64,
             nonce: general_purpose::STANDARD.encode(nonce),
             ciphertext: general_purpose::STANDARD.encode(ciphertext),
@@ -108,12 +263,45 @@ impl CryptoManager {
 
     /// Decrypt data with AES-GCM
     pub fn decrypt(&mut self, encrypted_data: &EncryptedData) -> Result<Vec<u8>, CryptoError> {
+        if let Some(binding) = &encrypted_data.binding {
+            return Err(CryptoError::BindingMismatch(format!("sealed as {} {}", binding.kind, binding.path)));
+        }
+        self.open(encrypted_data, &[])
+    }
+
+    /// Decrypt data sealed by `encrypt_bound` for artifact `kind` at logical `path`.
+    /// The schema version comes from the envelope and is authenticated with it.
+    /// Envelopes from before bindings existed are accepted; the next write binds them.
+    pub fn decrypt_bound(&mut self, encrypted_data: &EncryptedData, kind: &str, path: &str) -> Result<Vec<u8>, CryptoError> {
+        match &encrypted_data.binding {
+            Some(binding) => {
+                binding.expect(kind, path)?;
+                self.open(encrypted_data, &binding.aad())
+            }
+            None if encrypted_data.version < BOUND_ENVELOPE_VERSION => self.open(encrypted_data, &[]),
+            None => Err(CryptoError::UnsupportedEnvelope("missing artifact binding".to_string())),
+        }
+    }
+
+    fn open(&mut self, encrypted_data: &EncryptedData, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
         if self.passphrase.is_none() {
             return Err(CryptoError::InvalidPassphrase);
         }
 
-        // Derive key using stored salt
-        let key_bytes = self.derive_key(&encrypted_data.salt)?;
+        if encrypted_data.version > ENVELOPE_VERSION {
+            return Err(CryptoError::UnsupportedEnvelope(format!("version {}", encrypted_data.version)));
+        }
+        if let Some(cipher) = encrypted_data.cipher.as_deref().filter(|c| *c != CIPHER_AES_256_GCM) {
+            return Err(CryptoError::UnsupportedEnvelope(format!("cipher {}", cipher)));
+        }
+        if encrypted_data.version == 0 {
+            return Err(CryptoError::UnsupportedEnvelope("unversioned envelope".to_string()));
+        }
+        let kdf = encrypted_data.kdf.as_ref()
+            .ok_or_else(|| CryptoError::UnsupportedEnvelope("missing kdf parameters".to_string()))?;
+
+        // Derive key using stored salt and parameters
+        let key_bytes = self.derive_key(&encrypted_data.salt, kdf)?;
         let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
         let cipher = Aes256Gcm::new(key);
 
@@ -121,6 +309,9 @@ impl CryptoManager {
         let nonce_bytes = general_purpose::STANDARD
             .decode(&encrypted_data.nonce)
             .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+        if nonce_bytes.len() != 12 {
+            return Err(CryptoError::DecryptionFailed("nonce length".to_string()));
+        }
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         let ciphertext = general_purpose::STANDARD
@@ -129,7 +320,7 @@ impl CryptoManager {
 
         // Decrypt
         let plaintext = cipher
-            .decrypt(nonce, ciphertext.as_ref())
+            .decrypt(nonce, Payload { msg: &ciphertext, aad })
             .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
 
         Ok(plaintext)
@@ -148,18 +339,82 @@ impl CryptoManager {
         Ok(data)
     }
 
-    /// Check if encryption is available
+    pub fn encrypt_json_bound<T: Serialize>(&mut self, data: &T, binding: &ArtifactBinding) -> Result<EncryptedData, CryptoError> {
+        let json_bytes = serde_json::to_vec(data)?;
+        self.encrypt_bound(&json_bytes, binding)
+    }
+
+    pub fn decrypt_json_bound<T: for<'de> Deserialize<'de>>(&mut self, encrypted_data: &EncryptedData, kind: &str, path: &str) -> Result<T, CryptoError> {
+        let json_bytes = self.decrypt_bound(encrypted_data, kind, path)?;
+        Ok(serde_json::from_slice(&json_bytes)?)
+    }
+
+    /// Encrypt a stream of any length into the chunked container format (see crypto_stream.rs).
+    /// The binding is part of the header, which every chunk authenticates.
+    pub fn encrypt_writer<W: Write>(&mut self, inner: W, binding: &ArtifactBinding) -> Result<StreamEncryptor<W>, CryptoError> {
+        self.encrypt_writer_with_chunk_size(inner, binding, STREAM_CHUNK_SIZE)
+    }
+
+    pub fn encrypt_writer_with_chunk_size<W: Write>(
+        &mut self,
+        inner: W,
+        binding: &ArtifactBinding,
+        chunk_size: usize,
+    ) -> Result<StreamEncryptor<W>, CryptoError> {
+        let (salt, kdf, key) = self.session_key()?;
+        let mut nonce_prefix = [0u8; 7];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        let header = StreamHeader {
+            version: STREAM_VERSION,
+            cipher: CIPHER_AES_256_GCM_STREAM.to_string(),
+            kdf,
+            binding: binding.clone(),
+            salt,
+            nonce_prefix: general_purpose::STANDARD.encode(nonce_prefix),
+            chunk_size,
+        };
+        StreamEncryptor::new(inner, header, &key, &nonce_prefix)
+    }
+
+    /// Read back a stream written by `encrypt_writer` for `kind` at `path`. Chunks are
+    /// authenticated as they are read, so a read error means the data can't be
+    /// trusted from that point on.
+    pub fn decrypt_reader<R: Read>(&mut self, mut inner: R, kind: &str, path: &str) -> Result<StreamDecryptor<R>, CryptoError> {
+        if self.passphrase.is_none() {
+            return Err(CryptoError::InvalidPassphrase);
+        }
+        let (header, aad) = crypto_stream::read_header(&mut inner)?;
+        header.binding.expect(kind, path)?;
+        let key = self.derive_key(&header.salt, &header.kdf)?;
+        StreamDecryptor::new(inner, &header, aad, &key)
+    }
+
+    /// Check if new writes should be encrypted
     pub fn is_encryption_enabled(&self) -> bool {
-        self.passphrase.is_some()
+        self.enabled && self.passphrase.is_some()
     }
 }
 
-impl Default for CryptoManager {
-    fn default() -> Self {
-        // Try to get passphrase from environment
-        let passphrase = std::env::var("MULTIAPP_PASSPHRASE").ok();
-        Self::new(passphrase)
+/// Raw Argon2 output for the parameters recorded in an envelope
+fn derive_raw_key(passphrase: &str, salt: &[u8], kdf: &KdfParams) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    if kdf.algorithm != KDF_ARGON2ID {
+        return Err(CryptoError::UnsupportedEnvelope(format!("kdf {}", kdf.algorithm)));
+    }
+    if kdf.key_len != 32 || kdf.m_cost > MAX_M_COST || kdf.t_cost > MAX_T_COST || kdf.p_cost > MAX_P_COST {
+        return Err(CryptoError::UnsupportedEnvelope(format!("kdf parameters {:?}", kdf)));
     }
+
+    let version = Version::try_from(kdf.version)
+        .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(kdf.key_len))
+        .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+
+    let mut key = Zeroizing::new(vec![0u8; kdf.key_len]);
+    Argon2::new(Algorithm::Argon2id, version, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| CryptoError::KeyDerivationFailed(e.to_string()))?;
+    Ok(key)
 }
 
 #[cfg(test)]
@@ -168,7 +423,7 @@ mod tests {
 
     #[test]
     fn test_encrypt_decrypt_cycle() {
-        let mut crypto = CryptoManager::new(Some("test_passphrase".to_string()));
+        let mut crypto = CryptoManager::new(Some(Secret::new("test_passphrase".to_string())));
         let original_data = b"Hello, encrypted world!";
         
         let encrypted = crypto.encrypt(original_data).unwrap();
@@ -181,7 +436,7 @@ mod tests {
     fn test_encrypt_decrypt_json() {
         use serde_json::json;
         
-        let mut crypto = CryptoManager::new(Some("test_passphrase".to_string()));
+        let mut crypto = CryptoManager::new(Some(Secret::new("test_passphrase".to_string())));
         let original_data = json!({
             "operation": "write_file",
             "path": "/tmp/test.txt",
@@ -193,4 +448,48 @@ mod tests {
         
         assert_eq!(original_data, decrypted);
     }
+
+    #[test]
+    fn test_envelope_records_kdf_params() {
+        let mut crypto = CryptoManager::new(Some(Secret::new("test_passphrase".to_string())));
+        let encrypted = crypto.encrypt(b"payload").unwrap();
+
+        assert_eq!(encrypted.version, ENVELOPE_VERSION);
+        assert_eq!(encrypted.cipher.as_deref(), Some(CIPHER_AES_256_GCM));
+        assert_eq!(encrypted.kdf, Some(KdfParams::default()));
+    }
+
+    #[test]
+    fn test_malformed_envelopes_are_refused() {
+        let mut crypto = CryptoManager::new(Some(Secret::new("test_passphrase".to_string())));
+        let encrypted = crypto.encrypt(b"payload").unwrap();
+
+        let mut short_nonce = encrypted.clone();
+        short_nonce.nonce = general_purpose::STANDARD.encode([0u8; 8]);
+        assert!(matches!(crypto.decrypt(&short_nonce), Err(CryptoError::DecryptionFailed(_))));
+
+        let mut unversioned = serde_json::to_value(&encrypted).unwrap();
+        unversioned.as_object_mut().unwrap().remove("version");
+        let unversioned: EncryptedData = serde_json::from_value(unversioned).unwrap();
+        assert!(matches!(crypto.decrypt(&unversioned), Err(CryptoError::UnsupportedEnvelope(_))));
+    }
+
+    #[test]
+    fn test_binding_is_authenticated() {
+        let mut crypto = CryptoManager::new(Some(Secret::new("test_passphrase".to_string())));
+        let binding = ArtifactBinding::new("state", "sidecar_state.json", 1);
+        let encrypted = crypto.encrypt_bound(b"live state", &binding).unwrap();
+
+        assert_eq!(crypto.decrypt_bound(&encrypted, "state", "sidecar_state.json").unwrap(), b"live state");
+        assert!(crypto.decrypt_bound(&encrypted, "state-backup", "sidecar_state.json").is_err());
+        assert!(crypto.decrypt(&encrypted).is_err());
+
+        // Relabelling the envelope doesn't help; the binding is the associated data
+        let mut relabelled = encrypted.clone();
+        relabelled.binding = Some(ArtifactBinding::new("state", "sidecar_state.json", 2));
+        assert!(matches!(
+            crypto.decrypt_bound(&relabelled, "state", "sidecar_state.json"),
+            Err(CryptoError::DecryptionFailed(_))
+        ));
+    }
 }
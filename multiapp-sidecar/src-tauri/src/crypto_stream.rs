@@ -0,0 +1,294 @@
+// Streaming encryption for MultiappV1 Sidecar
+// STREAM-construction AES-GCM in fixed-size chunks, for artifacts too large to hold in memory
+
+use crate::crypto::{ArtifactBinding, CryptoError, KdfParams};
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::Payload;
+use aes_gcm::{Aes256Gcm, Key, KeyInit};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+// Container layout:
+//   MAGIC (8 bytes) | header length (u32 BE) | header JSON | chunk* | last chunk
+// Every chunk except the last holds exactly `chunk_size` bytes of plaintext plus a
+// 16-byte tag. The STREAM nonce carries a chunk counter and a last-chunk flag, so
+// reordered, dropped or truncated chunks fail to decrypt. The magic, length and
+// header are the associated data of every chunk.
+pub const MAGIC: &[u8; 8] = b"MAPPSTRM";
+pub const STREAM_VERSION: u32 = 1;
+pub const CIPHER_AES_256_GCM_STREAM: &str = "aes-256-gcm-stream-be32";
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+const TAG_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 7; // 12-byte GCM nonce minus the 5 bytes STREAM uses
+const MAX_HEADER_LEN: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StreamHeader {
+    pub version: u32,
+    pub cipher: String,
+    pub kdf: KdfParams,
+    pub binding: ArtifactBinding,
+    pub salt: String,
+    pub nonce_prefix: String,
+    pub chunk_size: usize,
+}
+
+fn invalid_data(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Magic, length prefix and header bytes exactly as they appear in the file
+fn encode_preamble(header: &StreamHeader) -> Result<Vec<u8>, CryptoError> {
+    let json = serde_json::to_vec(header)?;
+    let mut preamble = Vec::with_capacity(MAGIC.len() + 4 + json.len());
+    preamble.extend_from_slice(MAGIC);
+    preamble.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    preamble.extend_from_slice(&json);
+    Ok(preamble)
+}
+
+/// Read and validate the preamble; returns the header and the raw bytes used as AAD
+pub fn read_header<R: Read>(inner: &mut R) -> Result<(StreamHeader, Vec<u8>), CryptoError> {
+    let mut fixed = [0u8; 12];
+    inner.read_exact(&mut fixed)?;
+    if &fixed[..8] != MAGIC {
+        return Err(CryptoError::UnsupportedEnvelope("not an encrypted stream".to_string()));
+    }
+
+    let len = u32::from_be_bytes([fixed[8], fixed[9], fixed[10], fixed[11]]) as usize;
+    if len > MAX_HEADER_LEN {
+        return Err(CryptoError::UnsupportedEnvelope(format!("stream header of {} bytes", len)));
+    }
+    let mut json = vec![0u8; len];
+    inner.read_exact(&mut json)?;
+    let header: StreamHeader = serde_json::from_slice(&json)?;
+
+    if header.version > STREAM_VERSION {
+        return Err(CryptoError::UnsupportedEnvelope(format!("stream version {}", header.version)));
+    }
+    if header.cipher != CIPHER_AES_256_GCM_STREAM {
+        return Err(CryptoError::UnsupportedEnvelope(format!("cipher {}", header.cipher)));
+    }
+    if header.chunk_size == 0 || header.chunk_size > MAX_CHUNK_SIZE {
+        return Err(CryptoError::UnsupportedEnvelope(format!("chunk size {}", header.chunk_size)));
+    }
+
+    let mut preamble = fixed.to_vec();
+    preamble.extend_from_slice(&json);
+    Ok((header, preamble))
+}
+
+/// Writes the container to `inner`. Call `finish` to write the last chunk; a stream
+/// dropped without it is truncated and will not decrypt.
+pub struct StreamEncryptor<W: Write> {
+    inner: W,
+    encryptor: Option<EncryptorBE32<Aes256Gcm>>,
+    aad: Vec<u8>,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> StreamEncryptor<W> {
+    pub(crate) fn new(mut inner: W, header: StreamHeader, key: &[u8], nonce_prefix: &[u8]) -> Result<Self, CryptoError> {
+        let aad = encode_preamble(&header)?;
+        inner.write_all(&aad)?;
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        Ok(Self {
+            inner,
+            encryptor: Some(EncryptorBE32::from_aead(cipher, nonce_prefix.into())),
+            aad,
+            chunk_size: header.chunk_size,
+            buffer: Vec::with_capacity(header.chunk_size),
+        })
+    }
+
+    /// Encrypt the buffered tail as the last chunk and hand back the inner writer
+    pub fn finish(mut self) -> Result<W, CryptoError> {
+        let encryptor = self.encryptor.take()
+            .ok_or_else(|| CryptoError::EncryptionFailed("stream already failed".to_string()))?;
+        let chunk = encryptor
+            .encrypt_last(Payload { msg: &self.buffer, aad: &self.aad })
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+        self.inner.write_all(&chunk)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for StreamEncryptor<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let written = buf.len();
+        while !buf.is_empty() {
+            // A full buffer is only known not to be the last chunk once more data arrives
+            if self.buffer.len() == self.chunk_size {
+                let encryptor = self.encryptor.as_mut()
+                    .ok_or_else(|| invalid_data("stream already failed"))?;
+                let chunk = encryptor
+                    .encrypt_next(Payload { msg: &self.buffer, aad: &self.aad })
+                    .map_err(|e| {
+                        self.encryptor = None;
+                        invalid_data(e)
+                    })?;
+                self.inner.write_all(&chunk)?;
+                self.buffer.clear();
+            }
+
+            let take = buf.len().min(self.chunk_size - self.buffer.len());
+            self.buffer.extend_from_slice(&buf[..take]);
+            buf = &buf[take..];
+        }
+        Ok(written)
+    }
+
+    /// Only flushes what's already encrypted; a partial chunk waits for more data or `finish`
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads plaintext back out of a container, one chunk in memory at a time
+pub struct StreamDecryptor<R: Read> {
+    inner: R,
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    aad: Vec<u8>,
+    chunk_size: usize,
+    pending: Option<Vec<u8>>, // next encrypted chunk, read ahead to spot the last one
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+    pub(crate) fn new(inner: R, header: &StreamHeader, aad: Vec<u8>, key: &[u8]) -> Result<Self, CryptoError> {
+        use base64::{Engine as _, engine::general_purpose};
+
+        let nonce_prefix = general_purpose::STANDARD
+            .decode(&header.nonce_prefix)
+            .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+        if nonce_prefix.len() != NONCE_PREFIX_LEN {
+            return Err(CryptoError::UnsupportedEnvelope("stream nonce prefix length".to_string()));
+        }
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        Ok(Self {
+            inner,
+            decryptor: Some(DecryptorBE32::from_aead(cipher, nonce_prefix.as_slice().into())),
+            aad,
+            chunk_size: header.chunk_size,
+            pending: None,
+            plaintext: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Up to one encrypted chunk; shorter only at the end of the input
+    fn read_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let mut chunk = Vec::with_capacity(self.chunk_size + TAG_LEN);
+        (&mut self.inner)
+            .take((self.chunk_size + TAG_LEN) as u64)
+            .read_to_end(&mut chunk)?;
+        Ok(chunk)
+    }
+
+    /// Decrypt the next chunk into `plaintext`. Returns false once the last chunk is done.
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.decryptor.is_none() {
+            return Ok(false);
+        }
+
+        let current = match self.pending.take() {
+            Some(chunk) => chunk,
+            None => self.read_chunk()?,
+        };
+        let next = self.read_chunk()?;
+        let payload = Payload { msg: &current, aad: &self.aad };
+
+        let result = if next.is_empty() {
+            let decryptor = self.decryptor.take().expect("checked above");
+            decryptor.decrypt_last(payload)
+        } else {
+            self.pending = Some(next);
+            self.decryptor.as_mut().expect("checked above").decrypt_next(payload)
+        };
+
+        self.plaintext = result.map_err(|_| {
+            self.decryptor = None;
+            invalid_data("stream chunk failed to decrypt; wrong passphrase, tampered or truncated")
+        })?;
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for StreamDecryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.plaintext.len() - self.position);
+        buf[..n].copy_from_slice(&self.plaintext[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{ArtifactBinding, CryptoManager};
+    use crate::passphrase::Secret;
+    use std::io::{Read, Write};
+
+    fn binding() -> ArtifactBinding {
+        ArtifactBinding::new("recording", "recordings/test.cast", 1)
+    }
+
+    fn encrypt(crypto: &mut CryptoManager, data: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut writer = crypto.encrypt_writer_with_chunk_size(Vec::new(), &binding(), chunk_size).unwrap();
+        for piece in data.chunks(7) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(crypto: &mut CryptoManager, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        crypto.decrypt_reader(data, "recording", "recordings/test.cast").unwrap().read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let mut crypto = CryptoManager::new(Some(Secret::new("test_passphrase".to_string())));
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+
+        // Exact multiples of the chunk size, a ragged tail and empty input
+        for (input, chunk_size) in [(&data[..], 100), (&data[..], 64), (&data[..0], 64)] {
+            let encrypted = encrypt(&mut crypto, input, chunk_size);
+            assert_eq!(decrypt(&mut crypto, &encrypted).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_stream_detects_truncation_and_tampering() {
+        let mut crypto = CryptoManager::new(Some(Secret::new("test_passphrase".to_string())));
+        let data = vec![42u8; 300];
+        let encrypted = encrypt(&mut crypto, &data, 100);
+
+        // Dropping the last chunk leaves a stream whose final chunk isn't flagged last
+        let truncated = &encrypted[..encrypted.len() - (100 + 16)];
+        assert!(decrypt(&mut crypto, truncated).is_err());
+
+        let mut tampered = encrypted.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(decrypt(&mut crypto, &tampered).is_err());
+
+        // A stream bound elsewhere is refused before any chunk is read
+        assert!(crypto.decrypt_reader(&encrypted[..], "recording", "recordings/other.cast").is_err());
+    }
+}
@@ -1,6 +1,9 @@
 // Event handling for the Tauri frontend
 // Manages events from GeanyLua and user interactions
 
+use crate::history;
+use crate::notifications::{self, NotificationAction, NotificationPage, NotificationQuery};
+use crate::payload_crypto;
 use crate::state::{AppState, PendingOperation, NotificationEvent};
 use serde_json::Value;
 use std::sync::Arc;
@@ -32,10 +35,29 @@ pub async fn approve_operation(
     state: State<'_, Arc<Mutex<AppState>>>
 ) -> Result<OperationResponse, String> {
     let mut app_state = state.lock().await;
-    
+    approve_operation_in(&mut app_state, operation_id).await
+}
+
+#[tauri::command]
+pub async fn reject_operation(
+    operation_id: String,
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<OperationResponse, String> {
+    let mut app_state = state.lock().await;
+    reject_operation_in(&mut app_state, operation_id)
+}
+
+/// Approve and execute an operation on an already locked state
+pub async fn approve_operation_in(app_state: &mut AppState, operation_id: String) -> Result<OperationResponse, String> {
     // Find the operation
     if let Some(operation) = app_state.pending_operations.iter().find(|op| op.id == operation_id) {
         let operation_clone = operation.clone();
+        if operation_clone.status != "pending" {
+            return Err(format!("Operation is already {}", operation_clone.status));
+        }
+        if payload_crypto::is_sealed(&operation_clone.payload) {
+            return Err(payload_crypto::LOCKED_MESSAGE.to_string());
+        }
         
         // Update status to approved
         app_state.update_operation_status(&operation_id, "approved".to_string())
@@ -50,10 +72,12 @@ pub async fn approve_operation(
         app_state.update_operation_status(&operation_id, final_status.to_string())
             .map_err(|e| e.to_string())?;
         
-        app_state.add_notification(
+        app_state.add_operation_notification(
+            &operation_id,
             &format!("Operation {} executed: {}", operation_id, 
                     if result.success { "Success" } else { "Failed" }),
-            if result.success { "info" } else { "error" }
+            if result.success { "info" } else { "error" },
+            "sidecar"
         );
         
         Ok(OperationResponse {
@@ -66,19 +90,24 @@ pub async fn approve_operation(
     }
 }
 
-#[tauri::command]
-pub async fn reject_operation(
-    operation_id: String,
-    state: State<'_, Arc<Mutex<AppState>>>
-) -> Result<OperationResponse, String> {
-    let mut app_state = state.lock().await;
+/// Reject an operation on an already locked state
+pub fn reject_operation_in(app_state: &mut AppState, operation_id: String) -> Result<OperationResponse, String> {
+    let status = app_state.pending_operations.iter()
+        .find(|op| op.id == operation_id)
+        .map(|op| op.status.clone())
+        .ok_or_else(|| "Operation not found".to_string())?;
+    if status != "pending" {
+        return Err(format!("Operation is already {}", status));
+    }
     
     app_state.update_operation_status(&operation_id, "rejected".to_string())
         .map_err(|e| e.to_string())?;
     
-    app_state.add_notification(
+    app_state.add_operation_notification(
+        &operation_id,
         &format!("Operation {} rejected by user", operation_id),
-        "warning"
+        "warning",
+        "sidecar"
     );
     
     Ok(OperationResponse {
@@ -89,21 +118,101 @@ pub async fn reject_operation(
 }
 
 #[tauri::command]
-pub async fn clear_notifications(
+pub async fn invoke_notification_action(
+    notification_id: String,
+    action: NotificationAction,
     state: State<'_, Arc<Mutex<AppState>>>
-) -> Result<(), String> {
+) -> Result<NotificationActionResponse, String> {
     let mut app_state = state.lock().await;
     
-    // Mark all notifications as dismissed instead of deleting them
-    for notification in &mut app_state.notifications {
-        notification.dismissed = true;
+    let notification = app_state.notifications.iter()
+        .find(|n| n.id == notification_id)
+        .ok_or_else(|| "Notification not found".to_string())?;
+    
+    let operation_id = notification.operation_id.clone()
+        .ok_or_else(|| "Notification is not linked to an operation".to_string())?;
+    let operation = app_state.pending_operations.iter()
+        .find(|op| op.id == operation_id)
+        .cloned()
+        .ok_or_else(|| "Operation not found".to_string())?;
+    if matches!(action, NotificationAction::ViewDiff | NotificationAction::Revert)
+        && payload_crypto::is_sealed(&operation.payload)
+    {
+        return Err(payload_crypto::LOCKED_MESSAGE.to_string());
     }
     
-    app_state.save_state().map_err(|e| e.to_string())?;
+    // The stored actions date from when the notification was raised; the
+    // operation may have been approved, rejected or reverted since
+    if !notifications::actions_for(&operation).contains(&action) {
+        return Err(format!("Action {:?} is not available for this notification", action));
+    }
     
+    let mut response = NotificationActionResponse {
+        notification_id,
+        action,
+        operation_id: operation_id.clone(),
+        status: operation.status.clone(),
+        result: None,
+        diff: None,
+    };
+    
+    match action {
+        NotificationAction::Approve => {
+            let outcome = approve_operation_in(&mut app_state, operation_id).await?;
+            response.status = outcome.status;
+            response.result = outcome.result;
+        }
+        NotificationAction::Reject => {
+            let outcome = reject_operation_in(&mut app_state, operation_id)?;
+            response.status = outcome.status;
+        }
+        NotificationAction::ViewDiff => {
+            response.diff = history::operation_diff(&operation).map(|(_, diff)| diff);
+        }
+        NotificationAction::Revert => {
+            let (operation_type, payload) = history::revert_operation(&operation)
+                .ok_or_else(|| "Operation cannot be reverted".to_string())?;
+            let revert_id = app_state.add_operation(operation_type, payload, "sidecar".to_string());
+            app_state.add_operation_notification(
+                &revert_id,
+                &format!("Revert of operation {} pending approval", operation_id),
+                "info",
+                "sidecar"
+            );
+            response.result = Some(revert_id);
+        }
+    }
+    
+    Ok(response)
+}
+
+#[tauri::command]
+pub async fn clear_notifications(
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<(), String> {
+    let mut app_state = state.lock().await;
+    app_state.clear_notifications();
     Ok(())
 }
 
+#[tauri::command]
+pub async fn dismiss_notification(
+    notification_id: String,
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<(), String> {
+    let mut app_state = state.lock().await;
+    app_state.dismiss_notification(&notification_id)
+}
+
+#[tauri::command]
+pub async fn get_notifications(
+    query: Option<NotificationQuery>,
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<NotificationPage, String> {
+    let app_state = state.lock().await;
+    notifications::paginate(&app_state.notifications, &query.unwrap_or_default())
+}
+
 #[derive(serde::Serialize)]
 pub struct EventsResponse {
     pub pending_operations: Vec<PendingOperation>,
@@ -119,6 +228,16 @@ pub struct OperationResponse {
     pub result: Option<String>,
 }
 
+#[derive(serde::Serialize)]
+pub struct NotificationActionResponse {
+    pub notification_id: String,
+    pub action: NotificationAction,
+    pub operation_id: String,
+    pub status: String,
+    pub result: Option<String>, // execution message, or the id of a queued revert
+    pub diff: Option<String>,
+}
+
 #[derive(serde::Serialize)]
 pub struct ExecutionResult {
     pub success: bool,
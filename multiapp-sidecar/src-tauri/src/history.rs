@@ -0,0 +1,373 @@
+// Operation history export for MultiappV1 Sidecar
+// Turns completed operations into replayable patch series, shell scripts or JSON bundles
+
+use crate::payload_crypto;
+use crate::state::{AppState, PendingOperation};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use tauri::State;
+use tokio::sync::Mutex;
+
+const PATCH_AUTHOR: &str = "GeanyLua CodingBuddy <codingbuddy@localhost>";
+// git format-patch writes this fixed date on the "From <sha>" separator line
+const MBOX_MAGIC_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Patch,  // mbox / `git format-patch` series for file changes
+    Script, // POSIX shell script for run_command steps
+    Bundle, // JSON bundle with operations, patches and script
+}
+
+/// Selects which completed operations to export. Bounds are inclusive.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HistoryRange {
+    pub from_id: Option<String>,
+    pub to_id: Option<String>,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportArtifact {
+    pub format: ExportFormat,
+    pub filename: String,
+    pub content_type: String,
+    pub content: String,
+    pub operation_count: usize,
+}
+
+/// Pick the completed operations that fall inside the requested range
+pub fn select_operations(
+    operations: &[PendingOperation],
+    range: &HistoryRange,
+) -> Result<Vec<PendingOperation>, String> {
+    let mut completed: Vec<&PendingOperation> = operations.iter()
+        .filter(|op| op.status == "completed")
+        .collect();
+    completed.sort_by_key(|op| op.timestamp);
+
+    let start = match &range.from_id {
+        Some(id) => completed.iter().position(|op| &op.id == id)
+            .ok_or_else(|| format!("Operation {} not found in completed history", id))?,
+        None => 0,
+    };
+    let end = match &range.to_id {
+        Some(id) => completed.iter().position(|op| &op.id == id)
+            .ok_or_else(|| format!("Operation {} not found in completed history", id))?,
+        None => completed.len().saturating_sub(1),
+    };
+
+    if completed.is_empty() || start > end {
+        return Ok(Vec::new());
+    }
+
+    Ok(completed[start..=end].iter()
+        .filter(|op| range.since.map_or(true, |since| op.timestamp >= since))
+        .filter(|op| range.until.map_or(true, |until| op.timestamp <= until))
+        .map(|op| (*op).clone())
+        .collect())
+}
+
+/// Export a list of operations in the requested format
+pub fn export_operations(operations: &[PendingOperation], format: ExportFormat) -> Result<ExportArtifact, String> {
+    // A sealed payload would silently drop out of the patches and script
+    if operations.iter().any(|op| payload_crypto::is_sealed(&op.payload)) {
+        return Err(payload_crypto::LOCKED_MESSAGE.to_string());
+    }
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+
+    let (filename, content_type, content) = match format {
+        ExportFormat::Patch => (
+            format!("codingbuddy-{}.patch", stamp),
+            "application/mbox",
+            build_patch_series(operations),
+        ),
+        ExportFormat::Script => (
+            format!("codingbuddy-{}.sh", stamp),
+            "application/x-sh",
+            build_shell_script(operations),
+        ),
+        ExportFormat::Bundle => {
+            let bundle = json!({
+                "version": 1,
+                "exported_at": chrono::Utc::now(),
+                "operations": operations,
+                "patch_series": build_patch_series(operations),
+                "script": build_shell_script(operations),
+            });
+            (
+                format!("codingbuddy-{}.json", stamp),
+                "application/json",
+                serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?,
+            )
+        }
+    };
+
+    Ok(ExportArtifact {
+        format,
+        filename,
+        content_type: content_type.to_string(),
+        content,
+        operation_count: operations.len(),
+    })
+}
+
+/// Build a `git am` compatible mbox from write_file and apply_patch operations
+fn build_patch_series(operations: &[PendingOperation]) -> String {
+    let patches: Vec<(&PendingOperation, String, String)> = operations.iter()
+        .filter_map(|op| operation_diff(op).map(|(path, diff)| (op, path, diff)))
+        .collect();
+
+    let total = patches.len();
+    let mut out = String::new();
+
+    for (index, (op, path, diff)) in patches.iter().enumerate() {
+        out.push_str(&format!("From {} {}\n", "0".repeat(40), MBOX_MAGIC_DATE));
+        out.push_str(&format!("From: {}\n", PATCH_AUTHOR));
+        out.push_str(&format!("Date: {}\n", op.timestamp.to_rfc2822()));
+        out.push_str(&format!(
+            "Subject: [PATCH {}/{}] {}: {}\n\n",
+            index + 1, total, op.operation_type, path
+        ));
+        out.push_str(&format!("Operation-Id: {}\nSource: {}\n---\n", op.id, op.source));
+        out.push_str(diff);
+        if !diff.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str("-- \nmultiapp-sidecar\n\n");
+    }
+
+    out
+}
+
+/// Produce a git-style diff for a file-changing operation
+pub fn operation_diff(op: &PendingOperation) -> Option<(String, String)> {
+    match op.operation_type.as_str() {
+        "write_file" => {
+            let path = op.payload.get("path")?.as_str()?.trim_start_matches('/').to_string();
+            let content = op.payload.get("content").and_then(|c| c.as_str()).unwrap_or("");
+            let original = op.payload.get("original_content").and_then(|c| c.as_str());
+            Some((path.clone(), write_file_diff(&path, original, content)))
+        }
+        "apply_patch" => {
+            let path = op.payload.get("file")?.as_str()?.trim_start_matches('/').to_string();
+            let patch = op.payload.get("patch").and_then(|p| p.as_str())?;
+            if patch.starts_with("diff --git ") {
+                return Some((path, patch.to_string()));
+            }
+            let mut diff = format!("diff --git a/{0} b/{0}\n", path);
+            if !patch.starts_with("--- ") {
+                diff.push_str(&format!("--- a/{0}\n+++ b/{0}\n", path));
+            }
+            diff.push_str(patch);
+            Some((path, diff))
+        }
+        _ => None,
+    }
+}
+
+/// Build the operation that undoes a completed file change, if it can be undone
+pub fn revert_operation(op: &PendingOperation) -> Option<(String, serde_json::Value)> {
+    match op.operation_type.as_str() {
+        "write_file" => {
+            let path = op.payload.get("path")?;
+            let original = op.payload.get("original_content")?;
+            Some(("write_file".to_string(), json!({
+                "path": path,
+                "content": original,
+                "original_content": op.payload.get("content"),
+                "reverts": op.id,
+            })))
+        }
+        "apply_patch" => {
+            let file = op.payload.get("file")?;
+            let patch = op.payload.get("patch")?;
+            Some(("apply_patch".to_string(), json!({
+                "file": file,
+                "patch": patch,
+                "mode": "reverse",
+                "reverts": op.id,
+            })))
+        }
+        _ => None,
+    }
+}
+
+/// Full-replacement diff; creates the file when the original content is unknown
+fn write_file_diff(path: &str, original: Option<&str>, content: &str) -> String {
+    let new_lines: Vec<&str> = content.lines().collect();
+    let mut diff = format!("diff --git a/{0} b/{0}\n", path);
+
+    let old_lines: Vec<&str> = match original {
+        Some(old) => {
+            diff.push_str(&format!("--- a/{0}\n+++ b/{0}\n", path));
+            old.lines().collect()
+        }
+        None => {
+            diff.push_str(&format!("new file mode 100644\n--- /dev/null\n+++ b/{}\n", path));
+            Vec::new()
+        }
+    };
+
+    // Like git, an empty new file is just the header; an empty hunk doesn't apply
+    if old_lines.is_empty() && new_lines.is_empty() {
+        return diff;
+    }
+
+    let old_start = if old_lines.is_empty() { 0 } else { 1 };
+    let new_start = if new_lines.is_empty() { 0 } else { 1 };
+    diff.push_str(&format!(
+        "@@ -{},{} +{},{} @@\n",
+        old_start, old_lines.len(), new_start, new_lines.len()
+    ));
+    for line in &old_lines {
+        diff.push_str(&format!("-{}\n", line));
+    }
+    for line in &new_lines {
+        diff.push_str(&format!("+{}\n", line));
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        diff.push_str("\\ No newline at end of file\n");
+    }
+
+    diff
+}
+
+/// Build a shell script replaying run_command operations in order
+fn build_shell_script(operations: &[PendingOperation]) -> String {
+    let mut out = String::from("#!/bin/sh\n# Replay of CodingBuddy run_command operations\nset -e\n");
+
+    for op in operations.iter().filter(|op| op.operation_type == "run_command") {
+        let Some(command) = op.payload.get("command").and_then(|c| c.as_str()) else {
+            continue;
+        };
+
+        out.push_str(&format!("\n# {} ({})\n", op.id, op.timestamp.to_rfc3339()));
+        match op.payload.get("cwd").and_then(|c| c.as_str()) {
+            Some(cwd) => out.push_str(&format!("(cd {} && {})\n", shell_quote(cwd), command)),
+            None => out.push_str(&format!("{}\n", command)),
+        }
+    }
+
+    out
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Tauri command handlers
+#[tauri::command]
+pub async fn export_history(
+    format: ExportFormat,
+    range: Option<HistoryRange>,
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<ExportArtifact, String> {
+    let app_state = state.lock().await;
+    let operations = select_operations(&app_state.pending_operations, &range.unwrap_or_default())?;
+    export_operations(&operations, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn operation(id: &str, operation_type: &str, status: &str, minute: i64, payload: serde_json::Value) -> PendingOperation {
+        PendingOperation {
+            id: id.to_string(),
+            operation_type: operation_type.to_string(),
+            payload,
+            status: status.to_string(),
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap() + Duration::minutes(minute),
+            source: "geanylua".to_string(),
+            changeset_id: None,
+        }
+    }
+
+    fn history() -> Vec<PendingOperation> {
+        vec![
+            operation("c", "run_command", "completed", 2, json!({"command": "make test", "cwd": "/tmp/it's"})),
+            operation("a", "write_file", "completed", 0, json!({"path": "/src/a.txt", "content": "new\n", "original_content": "old\n"})),
+            operation("p", "write_file", "pending", 1, json!({"path": "/src/p.txt", "content": "x"})),
+            operation("b", "apply_patch", "completed", 1, json!({"file": "src/b.txt", "patch": "@@ -1 +1 @@\n-1\n+2\n"})),
+        ]
+    }
+
+    fn ids(operations: &[PendingOperation]) -> Vec<&str> {
+        operations.iter().map(|op| op.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_range_selects_completed_in_order() {
+        let operations = history();
+        assert_eq!(ids(&select_operations(&operations, &HistoryRange::default()).unwrap()), ["a", "b", "c"]);
+
+        let range = HistoryRange { from_id: Some("b".to_string()), ..Default::default() };
+        assert_eq!(ids(&select_operations(&operations, &range).unwrap()), ["b", "c"]);
+        let range = HistoryRange { to_id: Some("b".to_string()), ..Default::default() };
+        assert_eq!(ids(&select_operations(&operations, &range).unwrap()), ["a", "b"]);
+        let range = HistoryRange { from_id: Some("c".to_string()), to_id: Some("a".to_string()), ..Default::default() };
+        assert!(select_operations(&operations, &range).unwrap().is_empty());
+
+        // Time bounds are inclusive
+        let range = HistoryRange {
+            since: Some(operations[3].timestamp),
+            until: Some(operations[3].timestamp),
+            ..Default::default()
+        };
+        assert_eq!(ids(&select_operations(&operations, &range).unwrap()), ["b"]);
+
+        // Pending operations aren't part of the completed history
+        let range = HistoryRange { from_id: Some("p".to_string()), ..Default::default() };
+        assert!(select_operations(&operations, &range).is_err());
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let operations = select_operations(&history(), &HistoryRange::default()).unwrap();
+
+        let patch = export_operations(&operations, ExportFormat::Patch).unwrap();
+        assert_eq!((patch.content_type.as_str(), patch.operation_count), ("application/mbox", 3));
+        assert!(patch.filename.ends_with(".patch"));
+        assert!(patch.content.starts_with(&format!("From {} {}\n", "0".repeat(40), MBOX_MAGIC_DATE)));
+        assert!(patch.content.contains("Subject: [PATCH 1/2] write_file: src/a.txt\n"));
+        assert!(patch.content.contains("--- a/src/a.txt\n+++ b/src/a.txt\n@@ -1,1 +1,1 @@\n-old\n+new\n"));
+        assert!(patch.content.contains("Subject: [PATCH 2/2] apply_patch: src/b.txt\n"));
+        assert!(patch.content.contains("diff --git a/src/b.txt b/src/b.txt\n--- a/src/b.txt\n+++ b/src/b.txt\n@@ -1 +1 @@"));
+        assert!(!patch.content.contains("make test"));
+
+        let script = export_operations(&operations, ExportFormat::Script).unwrap();
+        assert!(script.content.starts_with("#!/bin/sh\n"));
+        assert!(script.content.contains("(cd '/tmp/it'\\''s' && make test)\n"));
+        assert!(!script.content.contains("src/a.txt"));
+
+        let bundle = export_operations(&operations, ExportFormat::Bundle).unwrap();
+        let bundle: serde_json::Value = serde_json::from_str(&bundle.content).unwrap();
+        assert_eq!(bundle["operations"].as_array().unwrap().len(), 3);
+        assert_eq!(bundle["patch_series"], patch.content);
+        assert_eq!(bundle["script"], script.content);
+    }
+
+    #[test]
+    fn test_empty_new_file_has_no_hunk() {
+        let operations = vec![operation("e", "write_file", "completed", 0, json!({"path": "/src/empty.txt", "content": ""}))];
+        let patch = export_operations(&operations, ExportFormat::Patch).unwrap();
+        assert!(patch.content.contains("diff --git a/src/empty.txt b/src/empty.txt\nnew file mode 100644\n--- /dev/null\n+++ b/src/empty.txt\n"));
+        assert!(!patch.content.contains("@@"));
+        assert!(!patch.content.contains("No newline"));
+    }
+
+    #[test]
+    fn test_export_refuses_sealed_payloads() {
+        let mut operations = history();
+        operations[0].payload = json!({"sealed": {"nonce": "", "ciphertext": ""}});
+        assert_eq!(
+            export_operations(&operations, ExportFormat::Script).unwrap_err(),
+            payload_crypto::LOCKED_MESSAGE
+        );
+    }
+}
@@ -2,22 +2,43 @@
 // Provides dark-mode UI, approvals, and terminal pane for GeanyLua CodingBuddy
 
 use std::sync::Arc;
+use tauri::Manager;
 use tokio::sync::Mutex;
 
+mod conversations;
 mod crypto;
+mod crypto_stream;
 mod server;
 mod terminal;
+mod terminal_exec;
+mod terminal_output;
 mod events;
+mod history;
+mod notifications;
+mod openssl_enc;
+mod passphrase;
+mod patch_import;
+mod payload_crypto;
+mod persistence;
+mod recording;
+mod schema;
+mod shell_integration;
+mod sqlite_store;
 mod state;
+mod store;
 
 use state::AppState;
 use server::SidecarServer;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    // Take the passphrase settings out of the environment before any thread or child
+    // process can see them
+    let passphrase_source = passphrase::PassphraseSource::from_env();
+    let rt = Arc::new(tokio::runtime::Runtime::new().unwrap());
+    let setup_rt = rt.clone();
     
-    tauri::Builder::default()
+    let app = tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_http::init())
@@ -25,13 +46,37 @@ pub fn run() {
             let app_handle = app.handle().clone();
             
             // Initialize application state with encryption
-            let state = AppState::new(&app_handle)?;
+            let passphrase = passphrase::load_passphrase(passphrase_source?)?;
+            let state = AppState::new(&app_handle, passphrase)?;
             let shared_state = Arc::new(Mutex::new(state));
             
+            // Move state writes onto a debounced background task
+            let persist_handle = persistence::spawn_persistence_task(&setup_rt, shared_state.clone());
+            shared_state.blocking_lock().attach_persistence(persist_handle);
+            
+            // Terminals are shared between the UI and GeanyLua's HTTP API
+            let app_dir = app_handle.path().app_data_dir()?;
+            let integration_dir = shell_integration::default_dir(&app_dir);
+            let terminals = match shell_integration::install(&integration_dir) {
+                Ok(()) => terminal::TerminalManager::with_shell_integration(integration_dir),
+                Err(e) => {
+                    eprintln!("Failed to install shell integration: {}", e);
+                    terminal::TerminalManager::new()
+                }
+            };
+            let token = server::issue_terminal_token(&app_dir)
+                .map_err(|e| eprintln!("Failed to write terminal token: {}", e))
+                .ok();
+            let server_terminals = server::TerminalHost {
+                manager: terminals.clone(),
+                sink: Arc::new(app_handle.clone()),
+                token: token.map(Arc::new),
+            };
+            
             // Start the sidecar server for GeanyLua communication
             let server_state = shared_state.clone();
-            rt.spawn(async move {
-                let server = SidecarServer::new(server_state);
+            setup_rt.spawn(async move {
+                let server = SidecarServer::new(server_state, server_terminals);
                 if let Err(e) = server.start().await {
                     eprintln!("Sidecar server error: {}", e);
                 }
@@ -39,6 +84,8 @@ pub fn run() {
             
             // Store state in Tauri's managed state
             app.manage(shared_state);
+            app.manage(terminals);
+            app.manage(recording::Replays::default());
             
             Ok(())
         })
@@ -47,15 +94,57 @@ pub fn run() {
             events::approve_operation,
             events::reject_operation,
             events::clear_notifications,
+            events::dismiss_notification,
+            events::get_notifications,
+            events::invoke_notification_action,
             terminal::create_terminal,
+            terminal::restore_terminal,
+            terminal::list_restorable_terminals,
             terminal::write_to_terminal,
+            terminal::ack_terminal_output,
+            terminal::list_terminals,
+            terminal::get_terminal_scrollback,
+            terminal::get_terminal_commands,
             terminal::resize_terminal,
             terminal::kill_terminal,
+            recording::list_recordings,
+            recording::export_recording,
+            recording::delete_recording,
+            recording::replay_recording,
+            recording::stop_replay,
+            history::export_history,
+            patch_import::import_patch,
             state::get_app_config,
-            state::update_app_config
+            state::update_app_config,
+            state::query_operation_history,
+            state::reveal_operation_payload,
+            state::recover_state,
+            state::set_passphrase,
+            state::enable_encryption,
+            state::disable_encryption,
+            state::rotate_passphrase,
+            conversations::list_conversations,
+            conversations::open_conversation,
+            conversations::reencrypt_conversations
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application");
+    
+    app.run(move |app_handle, event| {
+        if let tauri::RunEvent::Exit = event {
+            // Hang up on shells so they don't outlive the sidecar, noting them for restore first
+            let state = app_handle.state::<Arc<Mutex<AppState>>>();
+            let terminals = app_handle.state::<terminal::TerminalManager>();
+            rt.block_on(async { terminal::record_shutdown(&mut *state.lock().await, &terminals) });
+            terminals.kill_all();
+            
+            // Flush pending state writes before the process exits
+            let persist_handle = rt.block_on(async { state.lock().await.persist_handle() });
+            if let Some(handle) = persist_handle {
+                rt.block_on(handle.flush());
+            }
+        }
+    });
 }
 
 fn main() {
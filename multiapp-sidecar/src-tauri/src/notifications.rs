@@ -0,0 +1,270 @@
+// Notification handling for MultiappV1 Sidecar
+// Collapses repeated messages, rate-limits noisy sources, bounds storage and paginates queries
+
+use crate::history;
+use crate::state::{NotificationEvent, PendingOperation};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Identical messages within this window are merged into one entry
+pub const DEDUP_WINDOW_SECS: i64 = 30;
+/// Each origin may add at most RATE_LIMIT_MAX notifications per window
+pub const RATE_LIMIT_WINDOW_SECS: i64 = 10;
+pub const RATE_LIMIT_MAX: u32 = 20;
+/// Notifications raised by the sidecar itself; every other source came in over
+/// HTTP and shares the GeanyLua limit, whatever name the client sent
+pub const ORIGIN_SIDECAR: &str = "sidecar";
+pub const ORIGIN_GEANYLUA: &str = "geanylua";
+/// Default page size for paginated notification queries
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationAction {
+    Approve,
+    Reject,
+    ViewDiff,
+    Revert,
+}
+
+/// Actions offered on a notification for an operation in its current status
+pub fn actions_for(operation: &PendingOperation) -> Vec<NotificationAction> {
+    let mut actions = Vec::new();
+    let has_diff = history::operation_diff(operation).is_some();
+
+    match operation.status.as_str() {
+        "pending" => {
+            actions.push(NotificationAction::Approve);
+            actions.push(NotificationAction::Reject);
+        }
+        "completed" if history::revert_operation(operation).is_some() => {
+            actions.push(NotificationAction::Revert);
+        }
+        _ => {}
+    }
+    if has_diff {
+        actions.push(NotificationAction::ViewDiff);
+    }
+
+    actions
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RateDecision {
+    Allow,
+    /// Dropped; `first` is set on the first drop of the current window
+    Drop { first: bool },
+}
+
+struct RateWindow {
+    started: DateTime<Utc>,
+    accepted: u32,
+    dropped: u32,
+}
+
+pub struct NotificationThrottle {
+    windows: HashMap<&'static str, RateWindow>,
+}
+
+impl NotificationThrottle {
+    pub fn new() -> Self {
+        Self {
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Count a notification against its origin's fixed window
+    pub fn check_rate(&mut self, source: &str, now: DateTime<Utc>) -> RateDecision {
+        self.windows.retain(|_, window| now - window.started < Duration::seconds(RATE_LIMIT_WINDOW_SECS));
+        let window = self.windows.entry(rate_origin(source)).or_insert(RateWindow {
+            started: now,
+            accepted: 0,
+            dropped: 0,
+        });
+
+        if window.accepted < RATE_LIMIT_MAX {
+            window.accepted += 1;
+            RateDecision::Allow
+        } else {
+            window.dropped += 1;
+            RateDecision::Drop { first: window.dropped == 1 }
+        }
+    }
+}
+
+/// The rate-limit key for a notification source
+pub fn rate_origin(source: &str) -> &'static str {
+    if source == ORIGIN_SIDECAR {
+        ORIGIN_SIDECAR
+    } else {
+        ORIGIN_GEANYLUA
+    }
+}
+
+/// Find an existing entry the new notification should be folded into.
+/// Operation notifications group by operation id; others merge when the
+/// same message and level repeat within the dedup window.
+pub fn find_merge_target(
+    notifications: &[NotificationEvent],
+    message: &str,
+    level: &str,
+    operation_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Option<usize> {
+    notifications.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, n)| !n.dismissed)
+        .find(|(_, n)| match operation_id {
+            Some(op_id) => n.operation_id.as_deref() == Some(op_id),
+            None => n.operation_id.is_none()
+                && n.message == message
+                && n.level == level
+                && now - n.timestamp <= Duration::seconds(DEDUP_WINDOW_SECS),
+        })
+        .map(|(index, _)| index)
+}
+
+/// Drop the oldest notifications until at most `limit` remain
+pub fn evict_oldest(notifications: &mut Vec<NotificationEvent>, limit: usize) -> usize {
+    let excess = notifications.len().saturating_sub(limit);
+    notifications.drain(..excess);
+    excess
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct NotificationQuery {
+    pub level: Option<String>,
+    pub unread: Option<bool>,
+    pub cursor: Option<String>, // id of the last item from the previous page
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationPage {
+    pub items: Vec<NotificationEvent>,
+    pub next_cursor: Option<String>,
+    pub total: usize,
+}
+
+/// Newest-first page of notifications matching the query. A cursor that is no
+/// longer stored (dismissed and cleared, or evicted) is an error rather than a
+/// silent restart from the newest page.
+pub fn paginate(notifications: &[NotificationEvent], query: &NotificationQuery) -> Result<NotificationPage, String> {
+    let matches = |n: &&NotificationEvent| {
+        query.level.as_deref().map_or(true, |level| n.level == level)
+            && query.unread.map_or(true, |unread| n.dismissed != unread)
+    };
+
+    // Everything older than the cursor, which need not match the filter itself
+    let before = match query.cursor.as_deref() {
+        Some(cursor) => notifications.iter()
+            .position(|n| n.id == cursor)
+            .ok_or_else(|| format!("Unknown notification cursor: {}", cursor))?,
+        None => notifications.len(),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+
+    let mut remaining = notifications[..before].iter().rev().filter(matches);
+    let items: Vec<NotificationEvent> = remaining.by_ref().take(limit).cloned().collect();
+    let next_cursor = if remaining.next().is_some() {
+        items.last().map(|n| n.id.clone())
+    } else {
+        None
+    };
+
+    Ok(NotificationPage {
+        items,
+        next_cursor,
+        total: notifications.iter().filter(matches).count(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(message: &str, operation_id: Option<&str>, timestamp: DateTime<Utc>) -> NotificationEvent {
+        NotificationEvent {
+            id: message.to_string(),
+            message: message.to_string(),
+            level: "info".to_string(),
+            timestamp,
+            dismissed: false,
+            source: "geanylua".to_string(),
+            operation_id: operation_id.map(str::to_string),
+            count: 1,
+            actions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_identical_messages_merge_within_window() {
+        let now = Utc::now();
+        let existing = vec![notification("Chat: hi", None, now - Duration::seconds(5))];
+
+        assert_eq!(find_merge_target(&existing, "Chat: hi", "info", None, now), Some(0));
+        assert_eq!(find_merge_target(&existing, "Chat: hi", "info", None, now + Duration::seconds(60)), None);
+        assert_eq!(find_merge_target(&existing, "Chat: bye", "info", None, now), None);
+    }
+
+    #[test]
+    fn test_operation_notifications_group_by_id() {
+        let now = Utc::now();
+        let existing = vec![
+            notification("New write_file operation pending approval", Some("op-1"), now),
+            notification("New run_command operation pending approval", Some("op-2"), now),
+        ];
+
+        assert_eq!(find_merge_target(&existing, "Operation op-1 approved", "info", Some("op-1"), now), Some(0));
+        assert_eq!(find_merge_target(&existing, "Operation op-3 approved", "info", Some("op-3"), now), None);
+    }
+
+    #[test]
+    fn test_rate_limit_per_origin() {
+        let mut throttle = NotificationThrottle::new();
+        let now = Utc::now();
+
+        for _ in 0..RATE_LIMIT_MAX {
+            assert_eq!(throttle.check_rate("geanylua", now), RateDecision::Allow);
+        }
+        assert_eq!(throttle.check_rate("geanylua", now), RateDecision::Drop { first: true });
+        assert_eq!(throttle.check_rate("geanylua", now), RateDecision::Drop { first: false });
+        assert_eq!(throttle.check_rate("sidecar", now), RateDecision::Allow);
+        // Client-chosen names share the GeanyLua window
+        assert_eq!(throttle.check_rate("made-up-source", now), RateDecision::Drop { first: false });
+        assert_eq!(throttle.windows.len(), 2);
+
+        let later = now + Duration::seconds(RATE_LIMIT_WINDOW_SECS);
+        assert_eq!(throttle.check_rate("geanylua", later), RateDecision::Allow);
+        // The expired sidecar window is pruned
+        assert_eq!(throttle.windows.len(), 1);
+    }
+
+    #[test]
+    fn test_paginate_newest_first_with_cursor() {
+        let now = Utc::now();
+        let mut all: Vec<NotificationEvent> = (0..5)
+            .map(|i| notification(&format!("n{}", i), None, now))
+            .collect();
+        all[3].dismissed = true;
+
+        let query = NotificationQuery { unread: Some(true), limit: Some(2), ..Default::default() };
+        let first = paginate(&all, &query).unwrap();
+        assert_eq!(first.total, 4);
+        assert_eq!(first.items.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), vec!["n4", "n2"]);
+
+        // The cursor still works after its notification is dismissed
+        all[2].dismissed = true;
+        let query = NotificationQuery { cursor: first.next_cursor, ..query };
+        let second = paginate(&all, &query).unwrap();
+        assert_eq!(second.items.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), vec!["n1", "n0"]);
+        assert!(second.next_cursor.is_none());
+
+        assert_eq!(evict_oldest(&mut all, 3), 2);
+        assert_eq!(all[0].id, "n2");
+        let query = NotificationQuery { cursor: Some("n1".to_string()), ..Default::default() };
+        assert!(paginate(&all, &query).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+// OpenSSL `enc` compatibility for MultiappV1 Sidecar
+// Reads and writes the `Salted__` files made by `openssl enc -aes-256-cbc -salt -pbkdf2 -iter 100000`
+
+use crate::crypto::CryptoError;
+use aes::Aes256;
+use aes_gcm::aead::OsRng;
+use argon2::password_hash::rand_core::RngCore;
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+/// Header OpenSSL writes before the salt when `-salt` is in effect
+pub const MAGIC: &[u8; 8] = b"Salted__";
+const SALT_LEN: usize = 8;
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 16;
+
+/// Iteration count CodingBuddy passes to `openssl enc -iter`
+pub const PBKDF2_ITERATIONS: u32 = 100_000;
+
+type Aes256CbcEnc = cbc::Encryptor<Aes256>;
+type Aes256CbcDec = cbc::Decryptor<Aes256>;
+
+/// Whether `data` starts with the OpenSSL salted header
+pub fn is_salted(data: &[u8]) -> bool {
+    data.len() >= MAGIC.len() + SALT_LEN && data.starts_with(MAGIC)
+}
+
+/// `-pbkdf2` derives key and IV from one PBKDF2-HMAC-SHA256 output (SHA-256 is the default `-md`)
+fn derive_key_iv(passphrase: &str, salt: &[u8], iterations: u32) -> (Zeroizing<[u8; KEY_LEN]>, [u8; IV_LEN]) {
+    let mut output = Zeroizing::new([0u8; KEY_LEN + IV_LEN]);
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, output.as_mut());
+
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    let mut iv = [0u8; IV_LEN];
+    key.copy_from_slice(&output[..KEY_LEN]);
+    iv.copy_from_slice(&output[KEY_LEN..]);
+    (key, iv)
+}
+
+/// Encrypt like `openssl enc -aes-256-cbc -salt -pbkdf2 -iter 100000`
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Vec<u8> {
+    encrypt_with_iterations(plaintext, passphrase, PBKDF2_ITERATIONS)
+}
+
+pub fn encrypt_with_iterations(plaintext: &[u8], passphrase: &str, iterations: u32) -> Vec<u8> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let (key, iv) = derive_key_iv(passphrase, &salt, iterations);
+
+    let ciphertext = Aes256CbcEnc::new((&*key).into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+
+    let mut out = Vec::with_capacity(MAGIC.len() + SALT_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&ciphertext);
+    out
+}
+
+/// Decrypt like `openssl enc -d -aes-256-cbc -pbkdf2 -iter 100000`
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, CryptoError> {
+    decrypt_with_iterations(data, passphrase, PBKDF2_ITERATIONS)
+}
+
+/// CBC is unauthenticated: a wrong passphrase is only caught when the padding
+/// doesn't check out, so callers should still validate what comes back
+pub fn decrypt_with_iterations(data: &[u8], passphrase: &str, iterations: u32) -> Result<Vec<u8>, CryptoError> {
+    if !is_salted(data) {
+        return Err(CryptoError::DecryptionFailed("missing OpenSSL Salted__ header".to_string()));
+    }
+
+    let salt = &data[MAGIC.len()..MAGIC.len() + SALT_LEN];
+    let ciphertext = &data[MAGIC.len() + SALT_LEN..];
+    let (key, iv) = derive_key_iv(passphrase, salt, iterations);
+
+    Aes256CbcDec::new((&*key).into(), &iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|_| CryptoError::DecryptionFailed("bad passphrase or corrupt file".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine as _, engine::general_purpose};
+
+    #[test]
+    fn test_decrypt_openssl_cli_output() {
+        // printf '{"id":"conv1","messages":[]}' |
+        //   openssl enc -aes-256-cbc -salt -pbkdf2 -iter 100000 -pass pass:test-passphrase | base64
+        let data = general_purpose::STANDARD
+            .decode("U2FsdGVkX19CGU6M+m4Mr1PcWL1Pq5d8DjtJbKSExc/0fSu/LaATNwK0BglNCIev")
+            .unwrap();
+
+        let plaintext = decrypt(&data, "test-passphrase").unwrap();
+        assert_eq!(plaintext, br#"{"id":"conv1","messages":[]}"#);
+    }
+
+    #[test]
+    fn test_round_trip_and_wrong_passphrase() {
+        let encrypted = encrypt_with_iterations(b"conversation", "secret", 1000);
+
+        assert!(is_salted(&encrypted));
+        assert_eq!(decrypt_with_iterations(&encrypted, "secret", 1000).unwrap(), b"conversation");
+        // Usually a padding error; either way the plaintext must not come back
+        assert_ne!(decrypt_with_iterations(&encrypted, "wrong", 1000).ok(), Some(b"conversation".to_vec()));
+    }
+}
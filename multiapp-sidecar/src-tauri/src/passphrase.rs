@@ -0,0 +1,224 @@
+// Passphrase sources for MultiappV1 Sidecar
+// Reads the state passphrase from the environment, a passfile, an inherited fd or a helper program
+
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// Passphrase or key material that is wiped from memory when dropped
+pub type Secret = Zeroizing<String>;
+
+pub const PASSPHRASE_ENV: &str = "MULTIAPP_PASSPHRASE";
+pub const PASSFILE_ENV: &str = "MULTIAPP_PASSPHRASE_FILE";
+pub const PASSFD_ENV: &str = "MULTIAPP_PASSPHRASE_FD";
+pub const PASSCOMMAND_ENV: &str = "MULTIAPP_PASSPHRASE_COMMAND";
+pub const ASKPASS_ENV: &str = "MULTIAPP_ASKPASS";
+
+/// Variables that must never reach a shell or other child process
+pub const SENSITIVE_ENV_VARS: &[&str] = &[PASSPHRASE_ENV, "CODINGBUDDY_PASSPHRASE"];
+
+const ASKPASS_PROMPT: &str = "MultiappV1 Sidecar passphrase:";
+
+#[derive(Error, Debug)]
+pub enum PassphraseError {
+    #[error("Failed to read passphrase from {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Passfile {0} is readable by other users (mode {1:o}); chmod 600 it")]
+    InsecurePermissions(PathBuf, u32),
+    #[error("Invalid {PASSFD_ENV} value: {0}")]
+    InvalidFd(String),
+    #[error("Passphrase command failed: {0}")]
+    CommandFailed(String),
+    #[error("Passphrase from {0} is empty")]
+    Empty(String),
+    #[error("Passphrase from {0} is not valid UTF-8")]
+    NotUtf8(String),
+}
+
+/// Where the state passphrase comes from. Chosen once at startup from the environment.
+pub enum PassphraseSource {
+    Env(Secret),      // value of MULTIAPP_PASSPHRASE, already removed from the environment
+    File(PathBuf),    // first line of a file only the owner can read
+    Fd(i32),          // first line read from a descriptor the launcher left open
+    Command(String),  // e.g. `secret-tool lookup service multiapp-sidecar`, run through `sh -c`
+    Askpass(String),  // ssh-askpass style program that prompts in the desktop session
+}
+
+impl PassphraseSource {
+    /// The first configured source, in the order of the constants above. All the
+    /// variables are removed from the environment so the terminals and helpers the
+    /// sidecar spawns never inherit them; call this before starting other threads.
+    pub fn from_env() -> Result<Option<Self>, PassphraseError> {
+        let take = |name: &str| {
+            let value = std::env::var(name).ok().filter(|v| !v.is_empty());
+            std::env::remove_var(name);
+            value
+        };
+
+        let env = take(PASSPHRASE_ENV).map(Zeroizing::new);
+        let file = take(PASSFILE_ENV);
+        let fd = take(PASSFD_ENV);
+        let command = take(PASSCOMMAND_ENV);
+        let askpass = take(ASKPASS_ENV);
+
+        if let Some(passphrase) = env {
+            return Ok(Some(Self::Env(passphrase)));
+        }
+        if let Some(path) = file {
+            return Ok(Some(Self::File(PathBuf::from(path))));
+        }
+        if let Some(fd) = fd {
+            return match fd.parse::<i32>() {
+                Ok(n) if n > 2 => Ok(Some(Self::Fd(n))),
+                _ => Err(PassphraseError::InvalidFd(fd)),
+            };
+        }
+        Ok(command.map(Self::Command).or(askpass.map(Self::Askpass)))
+    }
+
+    /// Human-readable origin for messages; never includes the passphrase
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Env(_) => PASSPHRASE_ENV.to_string(),
+            Self::File(path) => path.display().to_string(),
+            Self::Fd(fd) => format!("file descriptor {}", fd),
+            Self::Command(command) => format!("`{}`", command),
+            Self::Askpass(program) => program.clone(),
+        }
+    }
+
+    /// Fetch the passphrase. An inherited descriptor is consumed and closed.
+    pub fn read(self) -> Result<Secret, PassphraseError> {
+        let origin = self.describe();
+        let raw = match self {
+            Self::Env(passphrase) => return Ok(passphrase),
+            Self::File(path) => read_passfile(&path)?,
+            Self::Fd(fd) => read_fd(fd)?,
+            Self::Command(command) => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(&command);
+                run_helper(cmd)?
+            }
+            Self::Askpass(program) => {
+                let mut cmd = Command::new(&program);
+                cmd.arg(ASKPASS_PROMPT);
+                run_helper(cmd)?
+            }
+        };
+        first_line(raw, &origin)
+    }
+}
+
+/// Passphrase from whichever source the environment configures, if any
+pub fn load_passphrase(source: Option<PassphraseSource>) -> Result<Option<Secret>, PassphraseError> {
+    source.map(PassphraseSource::read).transpose()
+}
+
+/// Same rule as OpenSSL's `-pass file:`: only the first line counts
+fn first_line(raw: Zeroizing<Vec<u8>>, origin: &str) -> Result<Secret, PassphraseError> {
+    let end = raw.iter().position(|&b| b == b'\n').unwrap_or(raw.len());
+    let line = raw[..end].strip_suffix(b"\r").unwrap_or(&raw[..end]);
+    if line.is_empty() {
+        return Err(PassphraseError::Empty(origin.to_string()));
+    }
+
+    let passphrase = std::str::from_utf8(line)
+        .map_err(|_| PassphraseError::NotUtf8(origin.to_string()))?;
+    Ok(Zeroizing::new(passphrase.to_string()))
+}
+
+fn read_passfile(path: &std::path::Path) -> Result<Zeroizing<Vec<u8>>, PassphraseError> {
+    let io_err = |e| PassphraseError::Io(path.display().to_string(), e);
+    let mut file = std::fs::File::open(path).map_err(io_err)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = file.metadata().map_err(io_err)?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(PassphraseError::InsecurePermissions(path.to_path_buf(), mode));
+        }
+    }
+
+    let mut raw = Zeroizing::new(Vec::new());
+    file.read_to_end(&mut raw).map_err(io_err)?;
+    Ok(raw)
+}
+
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Result<Zeroizing<Vec<u8>>, PassphraseError> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: the launcher handed us this descriptor for exactly this purpose and
+    // nothing else in the process uses it; taking ownership closes it after reading.
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    let mut raw = Zeroizing::new(Vec::new());
+    file.read_to_end(&mut raw)
+        .map_err(|e| PassphraseError::Io(format!("file descriptor {}", fd), e))?;
+    Ok(raw)
+}
+
+#[cfg(not(unix))]
+fn read_fd(fd: i32) -> Result<Zeroizing<Vec<u8>>, PassphraseError> {
+    Err(PassphraseError::InvalidFd(fd.to_string()))
+}
+
+/// Run a helper and capture its stdout. stderr stays attached so prompts and
+/// errors from the helper remain visible.
+fn run_helper(mut cmd: Command) -> Result<Zeroizing<Vec<u8>>, PassphraseError> {
+    for var in SENSITIVE_ENV_VARS {
+        cmd.env_remove(var);
+    }
+
+    let output = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| PassphraseError::CommandFailed(e.to_string()))?;
+    let stdout = Zeroizing::new(output.stdout);
+
+    if !output.status.success() {
+        return Err(PassphraseError::CommandFailed(format!("exited with {}", output.status)));
+    }
+    Ok(stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_line_only() {
+        let passphrase = first_line(Zeroizing::new(b"secret\r\nignored\n".to_vec()), "test").unwrap();
+        assert_eq!(passphrase.as_str(), "secret");
+        assert!(first_line(Zeroizing::new(b"\nsecret".to_vec()), "test").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_passfile_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("multiapp-pass-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "secret\n").unwrap();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(
+            PassphraseSource::File(path.clone()).read(),
+            Err(PassphraseError::InsecurePermissions(_, 0o644))
+        ));
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(PassphraseSource::File(path.clone()).read().unwrap().as_str(), "secret");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_command_source() {
+        let source = PassphraseSource::Command("printf 'from-helper\\n'".to_string());
+        assert_eq!(source.read().unwrap().as_str(), "from-helper");
+        assert!(PassphraseSource::Command("exit 1".to_string()).read().is_err());
+    }
+}
@@ -0,0 +1,430 @@
+// Patch import for MultiappV1 Sidecar
+// Splits unified diffs and git patches into per-file apply_patch operations
+
+use crate::state::AppState;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+use tauri::State;
+use tokio::sync::Mutex;
+
+const PREVIEW_MAX_LINES: usize = 40;
+
+// Path fragments that make a change inherently more sensitive
+const SENSITIVE_PATHS: &[&str] = &[
+    ".env", ".git/", ".ssh/", "id_rsa", "credentials", "secret", "passwd",
+    "Cargo.toml", "package.json", "Makefile", ".github/workflows",
+];
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub enum FileChange {
+    Modified,
+    Created,
+    Deleted,
+    Renamed { from: String },
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FilePatch {
+    pub path: String,
+    pub change: FileChange,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub hunks: usize,
+    pub patch: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RiskAssessment {
+    pub score: u8, // 0 (trivial) to 100 (dangerous)
+    pub level: String, // "low", "medium", "high"
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResponse {
+    pub changeset_id: String,
+    pub operation_ids: Vec<String>,
+    pub files: Vec<String>,
+}
+
+/// Split a unified diff or `git format-patch` output into per-file patches
+pub fn parse_patch(text: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut files = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let starts_git = line.starts_with("diff --git ");
+        let starts_plain = line.starts_with("--- ")
+            && lines.get(i + 1).map_or(false, |next| next.starts_with("+++ "));
+
+        if !starts_git && !starts_plain {
+            i += 1;
+            continue;
+        }
+
+        // Collect lines until the next file header or the mail signature
+        let start = i;
+        i += if starts_plain { 2 } else { 1 };
+        let mut remaining: (usize, usize) = (0, 0); // old and new lines left in the current hunk
+        while i < lines.len() {
+            let l = lines[i];
+            // Inside a hunk every line is content, even one that looks like a header
+            if remaining != (0, 0) {
+                match l.chars().next() {
+                    Some('-') => remaining.0 = remaining.0.saturating_sub(1),
+                    Some('+') => remaining.1 = remaining.1.saturating_sub(1),
+                    Some('\\') => {} // "\ No newline at end of file"
+                    _ => remaining = (remaining.0.saturating_sub(1), remaining.1.saturating_sub(1)),
+                }
+                i += 1;
+                continue;
+            }
+            if let Some(counts) = hunk_counts(l) {
+                remaining = counts;
+                i += 1;
+                continue;
+            }
+            if l.starts_with("diff --git ") || l == "-- " {
+                break;
+            }
+            if l.starts_with("--- ") && lines.get(i + 1).map_or(false, |n| n.starts_with("+++ ")) {
+                // A second ---/+++ pair only starts a new file outside a git header block
+                if starts_plain || lines[start..i].iter().any(|p| p.starts_with("+++ ")) {
+                    break;
+                }
+            }
+            i += 1;
+        }
+
+        files.push(parse_file_section(&lines[start..i])?);
+    }
+
+    if files.is_empty() {
+        return Err("No file changes found in patch".to_string());
+    }
+
+    Ok(files)
+}
+
+/// Old and new line counts from a `@@ -a,b +c,d @@` hunk header; a missing count is 1
+fn hunk_counts(line: &str) -> Option<(usize, usize)> {
+    let mut ranges = line.strip_prefix("@@ ")?.split_whitespace();
+    let mut count = |sign: char| -> Option<usize> {
+        let range = ranges.next()?.strip_prefix(sign)?;
+        match range.split_once(',') {
+            Some((_, count)) => count.parse().ok(),
+            None => range.parse::<usize>().ok().map(|_| 1),
+        }
+    };
+    Some((count('-')?, count('+')?))
+}
+
+fn parse_file_section(section: &[&str]) -> Result<FilePatch, String> {
+    let mut old_path: Option<String> = None;
+    let mut new_path: Option<String> = None;
+    let mut rename_from: Option<String> = None;
+    let mut file_mode: Option<FileChange> = None; // from git's "new file mode" / "deleted file mode"
+    let mut mode_changed = false;
+    let mut binary = false;
+    let mut additions = 0;
+    let mut deletions = 0;
+    let mut hunks = 0;
+    let mut in_hunk = false;
+
+    for line in section {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            // Fallback paths for patches without ---/+++ lines (binary, pure renames)
+            let mut parts = rest.split_whitespace();
+            old_path = parts.next().map(strip_prefix_dir);
+            new_path = parts.next().map(strip_prefix_dir);
+        } else if let Some(rest) = line.strip_prefix("rename from ") {
+            rename_from = Some(rest.to_string());
+        } else if let Some(rest) = line.strip_prefix("rename to ") {
+            new_path = Some(rest.to_string());
+        } else if line.starts_with("new file mode ") {
+            file_mode = Some(FileChange::Created);
+        } else if line.starts_with("deleted file mode ") {
+            file_mode = Some(FileChange::Deleted);
+        } else if line.starts_with("old mode ") || line.starts_with("new mode ") {
+            mode_changed = true;
+        } else if line.starts_with("Binary files ") || *line == "GIT binary patch" {
+            binary = true;
+        } else if line.starts_with("@@") {
+            hunks += 1;
+            in_hunk = true;
+        } else if !in_hunk && line.starts_with("--- ") {
+            old_path = Some(header_path(&line[4..]));
+        } else if !in_hunk && line.starts_with("+++ ") {
+            new_path = Some(header_path(&line[4..]));
+        } else if in_hunk && line.starts_with('+') {
+            additions += 1;
+        } else if in_hunk && line.starts_with('-') {
+            deletions += 1;
+        }
+    }
+
+    let change = match (old_path.as_deref(), new_path.as_deref()) {
+        (Some("/dev/null"), _) => FileChange::Created,
+        (_, Some("/dev/null")) => FileChange::Deleted,
+        _ => match (rename_from, file_mode.clone()) {
+            (Some(from), _) => FileChange::Renamed { from },
+            (None, Some(change)) => change,
+            (None, None) => FileChange::Modified,
+        },
+    };
+
+    let path = match change {
+        FileChange::Deleted => old_path,
+        _ => new_path,
+    }
+    .filter(|p| p != "/dev/null")
+    .ok_or_else(|| "Patch section is missing a file path".to_string())?;
+
+    // Renames, mode changes and empty files created or deleted come without hunks
+    let header_only = matches!(change, FileChange::Renamed { .. }) || mode_changed || file_mode.is_some();
+    if hunks == 0 && !binary && !header_only {
+        return Err(format!("Patch for {} contains no hunks", path));
+    }
+
+    let mut patch = section.join("\n");
+    patch.push('\n');
+
+    Ok(FilePatch {
+        path,
+        change,
+        binary,
+        additions,
+        deletions,
+        hunks,
+        patch,
+    })
+}
+
+/// Path from a ---/+++ header, without the a/ b/ prefix or trailing timestamp
+fn header_path(raw: &str) -> String {
+    let path = raw.split('\t').next().unwrap_or(raw).trim();
+    if path == "/dev/null" {
+        path.to_string()
+    } else {
+        strip_prefix_dir(path)
+    }
+}
+
+fn strip_prefix_dir(path: &str) -> String {
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// Score how risky applying a single file patch is
+pub fn assess_risk(file: &FilePatch) -> RiskAssessment {
+    let mut score: u32 = 0;
+    let mut reasons = Vec::new();
+
+    match &file.change {
+        FileChange::Deleted => {
+            score += 40;
+            reasons.push("Deletes a file".to_string());
+        }
+        FileChange::Renamed { from } => {
+            score += 15;
+            reasons.push(format!("Renames {}", from));
+        }
+        FileChange::Created => {
+            score += 5;
+        }
+        FileChange::Modified => {}
+    }
+
+    if file.binary {
+        score += 30;
+        reasons.push("Binary change cannot be previewed".to_string());
+    }
+
+    let changed = file.additions + file.deletions;
+    if changed > 200 {
+        score += 25;
+        reasons.push(format!("Large change ({} lines)", changed));
+    } else if changed > 50 {
+        score += 10;
+        reasons.push(format!("Medium change ({} lines)", changed));
+    }
+
+    if file.deletions > file.additions * 2 && file.deletions > 20 {
+        score += 10;
+        reasons.push("Mostly removes code".to_string());
+    }
+
+    if let Some(pattern) = SENSITIVE_PATHS.iter().find(|p| file.path.contains(*p)) {
+        score += 30;
+        reasons.push(format!("Touches sensitive path ({})", pattern));
+    }
+
+    if file.path.starts_with('/') || file.path.split('/').any(|part| part == "..") {
+        score += 40;
+        reasons.push("Path escapes the workspace".to_string());
+    }
+
+    let score = score.min(100) as u8;
+    let level = match score {
+        0..=29 => "low",
+        30..=59 => "medium",
+        _ => "high",
+    };
+
+    RiskAssessment {
+        score,
+        level: level.to_string(),
+        reasons,
+    }
+}
+
+/// First lines of the hunk content, for display in the approval panel
+fn diff_preview(file: &FilePatch) -> String {
+    let body: Vec<&str> = file.patch.lines()
+        .skip_while(|l| !l.starts_with("@@"))
+        .collect();
+    let mut preview = body.iter().take(PREVIEW_MAX_LINES).cloned().collect::<Vec<_>>().join("\n");
+    if body.len() > PREVIEW_MAX_LINES {
+        preview.push_str(&format!("\n... {} more lines", body.len() - PREVIEW_MAX_LINES));
+    }
+    preview
+}
+
+/// Parse a patch and queue one apply_patch operation per file under a single changeset
+pub fn import_patch_into(app_state: &mut AppState, patch_text: &str, source: &str) -> Result<ImportResponse, String> {
+    let files = parse_patch(patch_text)?;
+
+    let operations = files.iter()
+        .map(|file| {
+            let payload = json!({
+                "file": file.path,
+                "patch": file.patch,
+                "change": file.change,
+                "preview": {
+                    "additions": file.additions,
+                    "deletions": file.deletions,
+                    "hunks": file.hunks,
+                    "binary": file.binary,
+                    "diff": diff_preview(file),
+                },
+                "risk": assess_risk(file),
+            });
+            ("apply_patch".to_string(), payload)
+        })
+        .collect();
+
+    let (changeset_id, operation_ids) = app_state.add_changeset(operations, source.to_string());
+
+    app_state.add_notification_from(
+        &format!("Imported patch with {} file(s) pending approval", files.len()),
+        "info",
+        source,
+        None
+    );
+
+    Ok(ImportResponse {
+        changeset_id,
+        operation_ids,
+        files: files.into_iter().map(|f| f.path).collect(),
+    })
+}
+
+// Tauri command handlers
+#[tauri::command]
+pub async fn import_patch(
+    patch: String,
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<ImportResponse, String> {
+    let mut app_state = state.lock().await;
+    import_patch_into(&mut app_state, &patch, "sidecar")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIT_PATCH: &str = "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+From: Someone <someone@example.com>
+Subject: [PATCH] Example
+
+---
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,2 +1,2 @@
+-fn old() {}
++fn new() {}
+ fn keep() {}
+diff --git a/notes.txt b/notes.txt
+new file mode 100644
+--- /dev/null
++++ b/notes.txt
+@@ -0,0 +1 @@
++hello
+--\x20
+2.40.0
+";
+
+    #[test]
+    fn test_parse_git_patch_splits_files() {
+        let files = parse_patch(GIT_PATCH).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "src/lib.rs");
+        assert_eq!(files[0].change, FileChange::Modified);
+        assert_eq!((files[0].additions, files[0].deletions), (1, 1));
+        assert_eq!(files[1].path, "notes.txt");
+        assert_eq!(files[1].change, FileChange::Created);
+        assert!(!files[1].patch.contains("2.40.0"));
+    }
+
+    #[test]
+    fn test_parse_plain_unified_diff() {
+        let diff = "--- a.txt\t2024-01-01\n+++ a.txt\t2024-01-02\n@@ -1 +1 @@\n-a\n+b\n\
+                    --- b.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone\n";
+        let files = parse_patch(diff).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "a.txt");
+        assert_eq!(files[1].path, "b.txt");
+        assert_eq!(files[1].change, FileChange::Deleted);
+        assert_eq!(assess_risk(&files[1]).level, "medium");
+    }
+
+    #[test]
+    fn test_hunk_lines_are_not_file_headers() {
+        // A removed "-- x" and an added "++ y" look like a ---/+++ pair
+        let diff = "--- a.txt\n+++ a.txt\n@@ -1,2 +1,2 @@\n--- x\n+++ y\n keep\n\
+                    --- b.txt\n+++ b.txt\n@@ -1 +1 @@\n-a\n+b\n";
+        let files = parse_patch(diff).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].path.as_str(), files[0].additions, files[0].deletions), ("a.txt", 1, 1));
+        assert!(files[0].patch.contains("+++ y"));
+        assert_eq!(files[1].path, "b.txt");
+    }
+
+    #[test]
+    fn test_parse_sections_without_hunks() {
+        let patch = "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n\
+                     diff --git a/empty.txt b/empty.txt\nnew file mode 100644\nindex 0000000..e69de29\n\
+                     diff --git a/lib.rs b/lib.rs\nindex 1111111..2222222 100644\n";
+        assert_eq!(parse_patch(patch).unwrap_err(), "Patch for lib.rs contains no hunks");
+
+        let files = parse_patch(&patch[..patch.find("diff --git a/lib.rs").unwrap()]).unwrap();
+        assert_eq!((files[0].path.as_str(), &files[0].change, files[0].hunks), ("run.sh", &FileChange::Modified, 0));
+        assert_eq!((files[1].path.as_str(), &files[1].change), ("empty.txt", &FileChange::Created));
+    }
+
+    #[test]
+    fn test_parse_rejects_text_without_diff() {
+        assert!(parse_patch("just some chat text").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+// Field-level encryption of operation payloads for MultiappV1 Sidecar
+// Seals payload bodies under a separate data key so history metadata stays readable without a passphrase
+
+use crate::crypto::{ArtifactBinding, CryptoError, CryptoManager, EncryptedData};
+use crate::schema::CURRENT_SCHEMA_VERSION;
+use crate::state::{PendingOperation, PersistedState};
+use crate::store::SharedCrypto;
+use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use argon2::password_hash::rand_core::RngCore;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use zeroize::Zeroizing;
+
+/// Payload fields left in the clear, so history can be listed and searched while locked
+pub const METADATA_FIELDS: &[&str] = &["path", "file"];
+
+/// Field that holds the encrypted remainder of a sealed payload
+pub const SEALED_FIELD: &str = "sealed";
+
+pub const LOCKED_MESSAGE: &str = "Operation payload is locked; set the passphrase to reveal it";
+
+const SEALED_VERSION: u32 = 1;
+const DATA_KEY_LEN: usize = 32;
+
+// The wrapped key and each sealed payload are bound to where they belong
+const ARTIFACT_PAYLOAD_KEY: &str = "payload-key";
+const PAYLOAD_KEY_PATH: &str = "payload_key";
+const ARTIFACT_OPERATION_FIELDS: &str = "operation-fields";
+
+#[derive(Serialize, Deserialize)]
+struct SealedFields {
+    version: u32,
+    schema_version: u32,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Random data key for payload fields. It is stored wrapped under the passphrase,
+/// so a passphrase change rewraps the key instead of every payload.
+pub struct PayloadKey {
+    key: Zeroizing<[u8; DATA_KEY_LEN]>,
+}
+
+impl PayloadKey {
+    pub fn generate() -> Self {
+        let mut key = Zeroizing::new([0u8; DATA_KEY_LEN]);
+        OsRng.fill_bytes(key.as_mut());
+        Self { key }
+    }
+
+    /// Encrypt the key under the passphrase for storage next to the state
+    pub fn wrap(&self, crypto: &mut CryptoManager) -> Result<EncryptedData, CryptoError> {
+        let binding = ArtifactBinding::new(ARTIFACT_PAYLOAD_KEY, PAYLOAD_KEY_PATH, CURRENT_SCHEMA_VERSION);
+        crypto.encrypt_bound(self.key.as_ref(), &binding)
+    }
+
+    pub fn unwrap(wrapped: &EncryptedData, crypto: &mut CryptoManager) -> Result<Self, CryptoError> {
+        let raw = Zeroizing::new(crypto.decrypt_bound(wrapped, ARTIFACT_PAYLOAD_KEY, PAYLOAD_KEY_PATH)?);
+        if raw.len() != DATA_KEY_LEN {
+            return Err(CryptoError::DecryptionFailed("payload key has the wrong length".to_string()));
+        }
+
+        let mut key = Zeroizing::new([0u8; DATA_KEY_LEN]);
+        key.copy_from_slice(&raw);
+        Ok(Self { key })
+    }
+
+    /// Move every non-metadata field into an encrypted `sealed` field. The operation id
+    /// and the clear metadata are associated data, so neither can be edited or swapped.
+    pub fn seal(&self, operation_id: &str, payload: &Value) -> Result<Value, CryptoError> {
+        let (mut metadata, body) = split_payload(payload);
+        let schema_version = CURRENT_SCHEMA_VERSION;
+        let plaintext = Zeroizing::new(serde_json::to_vec(&body)?);
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = fields_aad(operation_id, &metadata, schema_version)?;
+        let ciphertext = self.cipher()
+            .encrypt(&nonce, Payload { msg: &plaintext, aad: &aad })
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+
+        let sealed = SealedFields {
+            version: SEALED_VERSION,
+            schema_version,
+            nonce: general_purpose::STANDARD.encode(nonce),
+            ciphertext: general_purpose::STANDARD.encode(ciphertext),
+        };
+        metadata.insert(SEALED_FIELD.to_string(), serde_json::to_value(sealed)?);
+        Ok(Value::Object(metadata))
+    }
+
+    /// Inverse of `seal`; payloads that aren't sealed come back unchanged
+    pub fn open(&self, operation_id: &str, payload: &Value) -> Result<Value, CryptoError> {
+        let mut metadata = match payload {
+            Value::Object(fields) if is_sealed(payload) => fields.clone(),
+            _ => return Ok(payload.clone()),
+        };
+        let sealed: SealedFields = serde_json::from_value(metadata.remove(SEALED_FIELD).unwrap_or_default())?;
+        if sealed.version > SEALED_VERSION {
+            return Err(CryptoError::UnsupportedEnvelope(format!("sealed payload version {}", sealed.version)));
+        }
+
+        let decode = |field: &str| general_purpose::STANDARD.decode(field)
+            .map_err(|e| CryptoError::DecryptionFailed(e.to_string()));
+        let nonce = decode(&sealed.nonce)?;
+        if nonce.len() != 12 {
+            return Err(CryptoError::DecryptionFailed("sealed payload nonce length".to_string()));
+        }
+        let ciphertext = decode(&sealed.ciphertext)?;
+        let aad = fields_aad(operation_id, &metadata, sealed.schema_version)?;
+
+        let plaintext = Zeroizing::new(self.cipher()
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|_| CryptoError::DecryptionFailed("sealed payload failed to authenticate".to_string()))?);
+        match serde_json::from_slice(&plaintext)? {
+            Value::Object(body) => {
+                metadata.extend(body);
+                Ok(Value::Object(metadata))
+            }
+            other => Ok(other),
+        }
+    }
+
+    /// Open sealed payloads in place; returns how many could not be opened
+    pub fn open_all(&self, operations: &mut [PendingOperation]) -> usize {
+        let mut failed = 0;
+        for op in operations.iter_mut().filter(|op| is_sealed(&op.payload)) {
+            match self.open(&op.id, &op.payload) {
+                Ok(payload) => op.payload = payload,
+                Err(e) => {
+                    eprintln!("Warning: Failed to open payload of operation {}: {}", op.id, e);
+                    failed += 1;
+                }
+            }
+        }
+        failed
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(self.key.as_ref()))
+    }
+}
+
+/// Whether the payload body is encrypted
+pub fn is_sealed(payload: &Value) -> bool {
+    payload.get(SEALED_FIELD).is_some_and(|sealed| sealed.get("ciphertext").is_some())
+}
+
+/// Clear metadata and the body to encrypt. Non-object payloads are all body.
+fn split_payload(payload: &Value) -> (Map<String, Value>, Value) {
+    match payload {
+        Value::Object(fields) => {
+            let (metadata, body): (Map<String, Value>, Map<String, Value>) = fields.clone()
+                .into_iter()
+                .partition(|(key, _)| METADATA_FIELDS.contains(&key.as_str()));
+            (metadata, Value::Object(body))
+        }
+        other => (Map::new(), other.clone()),
+    }
+}
+
+fn fields_aad(operation_id: &str, metadata: &Map<String, Value>, schema_version: u32) -> Result<Vec<u8>, CryptoError> {
+    let binding = ArtifactBinding::new(ARTIFACT_OPERATION_FIELDS, format!("operations/{}", operation_id), schema_version);
+    let mut aad = binding.aad();
+    // serde_json maps are sorted, so this encoding is stable
+    aad.extend_from_slice(&serde_json::to_vec(metadata)?);
+    Ok(aad)
+}
+
+/// Rewrap a stored payload key from `previous` to `current` passphrase settings. A key
+/// the previous settings can't open (or no passphrase on either side) is left as it is.
+pub fn rewrap(state: &mut PersistedState, previous: &SharedCrypto, current: &SharedCrypto) -> Result<(), CryptoError> {
+    let Some(wrapped) = &state.payload_key else {
+        return Ok(());
+    };
+
+    let key = {
+        let mut previous = previous.lock().unwrap();
+        if !previous.has_passphrase() {
+            return Ok(());
+        }
+        match PayloadKey::unwrap(wrapped, &mut previous) {
+            Ok(key) => key,
+            Err(_) => return Ok(()),
+        }
+    };
+
+    let mut current = current.lock().unwrap();
+    if current.has_passphrase() {
+        state.payload_key = Some(key.wrap(&mut current)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::passphrase::Secret;
+    use serde_json::json;
+
+    #[test]
+    fn test_seal_keeps_metadata_readable() {
+        let key = PayloadKey::generate();
+        let payload = json!({ "path": "/src/main.rs", "content": "API_KEY=hunter2" });
+
+        let sealed = key.seal("op1", &payload).unwrap();
+        assert!(is_sealed(&sealed));
+        assert_eq!(sealed["path"], "/src/main.rs");
+        assert!(!sealed.to_string().contains("hunter2"));
+        assert_eq!(key.open("op1", &sealed).unwrap(), payload);
+
+        // Bound to the operation and to its clear metadata
+        assert!(key.open("op2", &sealed).is_err());
+        let mut moved = sealed.clone();
+        moved["path"] = json!("/etc/passwd");
+        assert!(key.open("op1", &moved).is_err());
+    }
+
+    #[test]
+    fn test_key_wrap_and_rewrap() {
+        let key = PayloadKey::generate();
+        let sealed = key.seal("op1", &json!({ "command": "echo secret" })).unwrap();
+
+        let previous: SharedCrypto = std::sync::Arc::new(std::sync::Mutex::new(
+            CryptoManager::new(Some(Secret::new("old".to_string())))
+        ));
+        let current: SharedCrypto = std::sync::Arc::new(std::sync::Mutex::new(
+            CryptoManager::new(Some(Secret::new("new".to_string())))
+        ));
+        let mut state = PersistedState {
+            payload_key: Some(key.wrap(&mut previous.lock().unwrap()).unwrap()),
+            ..Default::default()
+        };
+
+        rewrap(&mut state, &previous, &current).unwrap();
+        let wrapped = state.payload_key.as_ref().unwrap();
+        assert!(PayloadKey::unwrap(wrapped, &mut previous.lock().unwrap()).is_err());
+        let unwrapped = PayloadKey::unwrap(wrapped, &mut current.lock().unwrap()).unwrap();
+        assert_eq!(unwrapped.open("op1", &sealed).unwrap(), json!({ "command": "echo secret" }));
+    }
+}
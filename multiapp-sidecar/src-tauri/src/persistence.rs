@@ -0,0 +1,272 @@
+// Crash-safe file persistence for MultiappV1 Sidecar
+// Atomic temp-file writes with fsync, rotating numbered backups and a debounced writer task
+
+use crate::state::AppState;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+/// Dirty signals arriving within this window are folded into a single write
+const SAVE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Tells apart the temp files of writers in this process
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+enum PersistSignal {
+    Dirty,
+    Flush(oneshot::Sender<()>),
+}
+
+/// Cheap, cloneable sender used by AppState to request writes
+#[derive(Clone)]
+pub struct PersistHandle {
+    tx: mpsc::UnboundedSender<PersistSignal>,
+}
+
+impl PersistHandle {
+    pub fn mark_dirty(&self) {
+        // A closed channel only happens during shutdown, after the final flush
+        let _ = self.tx.send(PersistSignal::Dirty);
+    }
+
+    /// Write any pending changes now and wait for the write to finish
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(PersistSignal::Flush(done_tx)).is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+/// Start the background writer; returns the handle to attach to AppState
+pub fn spawn_persistence_task(runtime: &tokio::runtime::Runtime, state: Arc<Mutex<AppState>>) -> PersistHandle {
+    let (tx, rx) = mpsc::unbounded_channel();
+    runtime.spawn(run_persistence_task(state, rx));
+    PersistHandle { tx }
+}
+
+async fn run_persistence_task(state: Arc<Mutex<AppState>>, mut rx: mpsc::UnboundedReceiver<PersistSignal>) {
+    while let Some(signal) = rx.recv().await {
+        let mut waiters = Vec::new();
+
+        match signal {
+            PersistSignal::Flush(done) => waiters.push(done),
+            PersistSignal::Dirty => {
+                // Coalesce further signals until the window passes or a flush arrives
+                let window = tokio::time::sleep(SAVE_DEBOUNCE);
+                tokio::pin!(window);
+                loop {
+                    tokio::select! {
+                        _ = &mut window => break,
+                        next = rx.recv() => match next {
+                            Some(PersistSignal::Dirty) => continue,
+                            Some(PersistSignal::Flush(done)) => {
+                                waiters.push(done);
+                                break;
+                            }
+                            None => break,
+                        },
+                    }
+                }
+            }
+        }
+
+        write_snapshot(&state).await;
+
+        for done in waiters {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Snapshot under the state lock, then encode and write on a blocking thread. If a
+/// synchronous save (a passphrase change, a backend switch) lands in between, the
+/// snapshot is stale; a fresh one is taken rather than writing over the newer data.
+async fn write_snapshot(state: &Arc<Mutex<AppState>>) {
+    loop {
+        let (snapshot, store, writes, seen) = {
+            let app_state = state.lock().await;
+            let snapshot = match app_state.snapshot() {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    eprintln!("Failed to save state: {}", e);
+                    return;
+                }
+            };
+            let writes = app_state.store_writes();
+            let seen = writes.load(Ordering::SeqCst);
+            (snapshot, app_state.store(), writes, seen)
+        };
+
+        let result = tokio::task::spawn_blocking(move || {
+            let mut store = store.lock().unwrap();
+            if writes.load(Ordering::SeqCst) != seen {
+                return Ok(false);
+            }
+            store.save(&snapshot).map(|()| true).map_err(|e| e.to_string())
+        }).await;
+
+        match result {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => continue,
+            Ok(Err(e)) => eprintln!("Failed to save state: {}", e),
+            Err(e) => eprintln!("State writer join error: {}", e),
+        }
+        break;
+    }
+}
+
+/// Path of the n-th backup, e.g. `sidecar_state.json.1` (1 is the newest)
+pub fn backup_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", index));
+    path.with_file_name(name)
+}
+
+/// Existing backups ordered newest first
+pub fn existing_backups(path: &Path, count: usize) -> Vec<PathBuf> {
+    (1..=count)
+        .map(|index| backup_path(path, index))
+        .filter(|backup| backup.exists())
+        .collect()
+}
+
+/// Write `data` to `path` so readers see either the old or the new contents, never a mix
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".tmp-{}-{}", std::process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        sync_parent_dir(path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Shift `path.1..path.N` down one slot and copy the current file into `path.1`,
+/// then atomically replace `path` with `data`
+pub fn write_with_backups(path: &Path, data: &[u8], backups: usize) -> io::Result<()> {
+    write_with_backups_via(path, data, backups, Some)
+}
+
+/// Like `write_with_backups`, but the outgoing contents pass through `to_backup`
+/// before landing in `path.1` (e.g. to re-seal encrypted state for a backup slot).
+/// `None` means the outgoing file is damaged: it is overwritten without rotating,
+/// so it can't push a good backup out.
+pub fn write_with_backups_via<F>(path: &Path, data: &[u8], backups: usize, to_backup: F) -> io::Result<()>
+where
+    F: FnOnce(Vec<u8>) -> Option<Vec<u8>>,
+{
+    if backups > 0 && path.exists() {
+        if let Some(backup) = to_backup(fs::read(path)?) {
+            for index in (1..backups).rev() {
+                let from = backup_path(path, index);
+                if from.exists() {
+                    fs::rename(&from, backup_path(path, index + 1))?;
+                }
+            }
+            write_atomic(&backup_path(path, 1), &backup)?;
+        }
+    }
+
+    write_atomic(path, data)
+}
+
+#[cfg(unix)]
+pub fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    // Persist the rename itself; without this a crash can roll the directory entry back
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_state_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("multiapp-persist-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("sidecar_state.json")
+    }
+
+    #[test]
+    fn test_write_with_backups_rotates() {
+        let path = temp_state_path("rotate");
+
+        for version in 1..=4 {
+            write_with_backups(&path, format!("v{}", version).as_bytes(), 2).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v4");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "v3");
+        assert_eq!(fs::read_to_string(backup_path(&path, 2)).unwrap(), "v2");
+        assert!(!backup_path(&path, 3).exists());
+        assert_eq!(existing_backups(&path, 5).len(), 2);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_file_is_not_rotated() {
+        let path = temp_state_path("damaged");
+        write_with_backups(&path, b"v1", 2).unwrap();
+        write_with_backups(&path, b"garbage", 2).unwrap();
+
+        let valid = |current: Vec<u8>| Some(current).filter(|c| c.starts_with(b"v"));
+        write_with_backups_via(&path, b"v3", 2, valid).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v3");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "v1");
+        assert!(!backup_path(&path, 2).exists());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_atomic_writes() {
+        let path = temp_state_path("concurrent");
+        let writers: Vec<_> = (0..8)
+            .map(|n| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        write_atomic(&path, format!("writer {}", n).repeat(1000).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // One writer's contents, whole, and no temp files left behind
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, contents[..8].repeat(1000));
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,1139 @@
+// Terminal session recordings for MultiappV1 Sidecar
+// Saves PTY sessions as asciicast v2 files, encrypted when encryption is enabled, and plays them back
+
+use crate::crypto::ArtifactBinding;
+use crate::crypto_stream::StreamEncryptor;
+use crate::state::AppState;
+use crate::store::SharedCrypto;
+use crate::terminal::TerminalManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+pub const RECORDINGS_DIR: &str = "recordings";
+pub const REPLAY_EVENT: &str = "recording_replay";
+pub const REPLAY_END_EVENT: &str = "recording_replay_end";
+
+const ARTIFACT_RECORDING: &str = "terminal-recording";
+const ASCIICAST_VERSION: u32 = 2;
+const PLAIN_EXT: &str = ".cast";
+const ENCRYPTED_EXT: &str = ".cast.enc";
+const PART_EXT: &str = ".part"; // still being written, or cut short by a crash
+const REKEY_EXT: &str = ".rekey"; // a copy under new crypto settings, until the store commits them
+const ROLLBACK_EXT: &str = ".rollback"; // a running recording's copy back under the old settings
+
+// Longest sleep between checks for a stopped replay
+const REPLAY_POLL: Duration = Duration::from_millis(100);
+
+/// Header line of an asciicast v2 file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CastHeader {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// One event line: seconds since the start, event code ("o" output, "r" resize
+/// as "COLSxROWS", "m" marker) and data
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CastEvent(pub f64, pub String, pub String);
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordingInfo {
+    pub id: String, // the recorded session's id
+    pub title: Option<String>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub size_bytes: u64,
+    pub encrypted: bool,
+    pub complete: bool, // false while the session runs, or if the sidecar stopped mid-write
+    pub error: Option<String>, // why the header couldn't be read, e.g. a missing passphrase
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordingExport {
+    pub filename: String,
+    pub path: String,   // where the plain asciicast v2 file was written
+    pub complete: bool, // false if the file ends early or fails to authenticate part-way
+}
+
+/// A session's recorder; taken out to finish it when the shell exits or the sidecar stops
+pub type SharedRecorder = Arc<std::sync::Mutex<Option<Recorder>>>;
+
+// Sessions still writing, by id; ones dropped without finishing no longer upgrade
+type LiveRecorders = HashMap<String, Weak<std::sync::Mutex<Option<Recorder>>>>;
+
+/// The recordings directory, with the crypto settings to write and read it
+#[derive(Clone)]
+pub struct Recordings {
+    dir: PathBuf,
+    crypto: SharedCrypto,
+    live: Arc<std::sync::Mutex<LiveRecorders>>,
+    rekeying: Arc<std::sync::Mutex<()>>, // held by a rekey, and while a session's recording starts or finishes
+}
+
+impl std::fmt::Debug for Recordings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recordings").field("dir", &self.dir).finish()
+    }
+}
+
+/// Location of one recording's file
+struct CastFile {
+    path: PathBuf,
+    encrypted: bool,
+    complete: bool,
+}
+
+/// A recording copied under new settings, waiting for the store to commit them
+struct Staged {
+    id: String,
+    staged: PathBuf,
+    path: PathBuf,     // where the copy goes on commit
+    original: PathBuf, // removed on commit when its name differs
+}
+
+impl Staged {
+    fn commit(&self) -> std::io::Result<()> {
+        std::fs::rename(&self.staged, &self.path)?;
+        if self.original != self.path {
+            std::fs::remove_file(&self.original)?;
+        }
+        Ok(())
+    }
+}
+
+impl Recordings {
+    pub fn new(app_dir: &Path, crypto: SharedCrypto) -> Self {
+        Self {
+            dir: app_dir.join(RECORDINGS_DIR),
+            crypto,
+            live: Arc::default(),
+            rekeying: Arc::default(),
+        }
+    }
+
+    /// The same directory read with other crypto settings
+    fn with_crypto(&self, crypto: SharedCrypto) -> Self {
+        Self { crypto, ..self.clone() }
+    }
+
+    /// Start recording a session. New recordings are encrypted while encryption is enabled.
+    pub fn start(&self, session_id: &str, shell: &str, cols: u16, rows: u16) -> Result<SharedRecorder, String> {
+        let header = CastHeader {
+            version: ASCIICAST_VERSION,
+            width: cols,
+            height: rows,
+            timestamp: Some(chrono::Utc::now().timestamp()),
+            title: Some(shell.to_string()),
+            env: HashMap::from([
+                ("SHELL".to_string(), shell.to_string()),
+                ("TERM".to_string(), "xterm-256color".to_string()),
+            ]),
+        };
+        check_id(session_id)?;
+        // Under the rekey lock, so a rekey either switches this recorder over or ran before it
+        let _rekeying = self.rekeying.lock().unwrap();
+        let mut recorder = self.create(session_id, &header, PART_EXT)?;
+        recorder.live = Some(self.clone());
+        let recorder = Arc::new(std::sync::Mutex::new(Some(recorder)));
+        self.live.lock().unwrap().insert(session_id.to_string(), Arc::downgrade(&recorder));
+        Ok(recorder)
+    }
+
+    /// A recorder writing to `<name><staging>`, renamed to `<name>` when finished
+    fn create(&self, session_id: &str, header: &CastHeader, staging: &str) -> Result<Recorder, String> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create recordings directory: {}", e))?;
+
+        // One lock, so the copy kept for reading back matches what the file is written with
+        let mut crypto = self.crypto.lock().unwrap();
+        let encrypted = crypto.is_encryption_enabled();
+        let ext = if encrypted { ENCRYPTED_EXT } else { PLAIN_EXT };
+        let path = self.dir.join(format!("{}{}", session_id, ext));
+        let part = with_suffix(&path, staging);
+        let file = File::create(&part)
+            .map_err(|e| format!("Failed to create recording: {}", e))?;
+        let writer = if encrypted {
+            let binding = ArtifactBinding::new(ARTIFACT_RECORDING, logical_path(session_id), ASCIICAST_VERSION);
+            let encryptor = crypto
+                .encrypt_writer(BufWriter::new(file), &binding)
+                .map_err(|e| format!("Failed to encrypt recording: {}", e))?;
+            CastWriter::Encrypted(Box::new(encryptor))
+        } else {
+            CastWriter::Plain(BufWriter::new(file))
+        };
+        let written_with = Arc::new(std::sync::Mutex::new(crypto.fork()));
+        drop(crypto);
+
+        let mut recorder = Recorder {
+            writer: Some(writer),
+            started: Instant::now(),
+            pending: Vec::new(),
+            id: session_id.to_string(),
+            encrypted,
+            part,
+            path,
+            written_with,
+            live: None,
+            superseded: None,
+        };
+        recorder.write_line(&serde_json::to_string(header).map_err(|e| e.to_string())?)
+            .map_err(|e| format!("Failed to write recording: {}", e))?;
+        Ok(recorder)
+    }
+
+    /// Every recording file by id, without opening any
+    fn files(&self) -> Result<Vec<(String, CastFile, std::fs::Metadata)>, String> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read recordings: {}", e)),
+        };
+        Ok(entries.flatten()
+            .filter_map(|entry| {
+                let (id, file) = self.parse_name(&entry.file_name().to_string_lossy())?;
+                Some((id, file, entry.metadata().ok()?))
+            })
+            .collect())
+    }
+
+    /// Every recording, oldest first. Reading the headers of encrypted ones costs one
+    /// key derivation per distinct salt; the crypto manager caches the keys.
+    pub fn list(&self) -> Result<Vec<RecordingInfo>, String> {
+        let mut recordings = Vec::new();
+        for (id, file, metadata) in self.files()? {
+            let header = self.open_file(&id, &file).map(|reader| reader.header);
+            let (header, error) = match header {
+                Ok(header) => (Some(header), None),
+                Err(e) => (None, Some(e)),
+            };
+            recordings.push(RecordingInfo {
+                id,
+                title: header.as_ref().and_then(|h| h.title.clone()),
+                width: header.as_ref().map(|h| h.width),
+                height: header.as_ref().map(|h| h.height),
+                started_at: header.as_ref()
+                    .and_then(|h| h.timestamp)
+                    .and_then(|t| chrono::DateTime::from_timestamp(t, 0)),
+                updated_at: metadata.modified()
+                    .map(chrono::DateTime::<chrono::Utc>::from)
+                    .unwrap_or_else(|_| chrono::Utc::now()),
+                size_bytes: metadata.len(),
+                encrypted: file.encrypted,
+                complete: file.complete,
+                error,
+            });
+        }
+        recordings.sort_by_key(|r| r.started_at.unwrap_or(r.updated_at));
+        Ok(recordings)
+    }
+
+    /// Read a recording's header and events
+    pub fn open(&self, id: &str) -> Result<CastReader, String> {
+        check_id(id)?;
+        let file = self.find(id)?;
+        self.open_file(id, &file)
+    }
+
+    /// Decrypt the recording into a new plain asciicast file in `dir`, one event at a
+    /// time. Events after damage are left out.
+    pub fn export(&self, id: &str, dir: &Path) -> Result<RecordingExport, String> {
+        let mut reader = self.open(id)?;
+        let stamp = reader.header.timestamp
+            .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+            .unwrap_or_else(chrono::Utc::now)
+            .format("%Y%m%d-%H%M%S");
+        let base = format!("terminal-{}-{}", stamp, &id[..8]);
+
+        // Never overwrite: an earlier export of the same recording gets a numbered sibling
+        let (filename, path, file) = (1..100)
+            .map(|n| if n == 1 { format!("{}.cast", base) } else { format!("{}-{}.cast", base, n) })
+            .find_map(|filename| {
+                let path = dir.join(&filename);
+                match OpenOptions::new().write(true).create_new(true).open(&path) {
+                    Ok(file) => Some(Ok((filename, path, file))),
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => None,
+                    Err(e) => Some(Err(format!("Failed to create {}: {}", path.display(), e))),
+                }
+            })
+            .unwrap_or_else(|| Err(format!("Too many exports of {} in {}", base, dir.display())))?;
+
+        let written = (|| {
+            let mut out = BufWriter::new(file);
+            serde_json::to_writer(&mut out, &reader.header)?;
+            out.write_all(b"\n")?;
+            while let Some(event) = reader.next_event() {
+                serde_json::to_writer(&mut out, &event)?;
+                out.write_all(b"\n")?;
+            }
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })();
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&path);
+            return Err(format!("Failed to export recording: {}", e));
+        }
+
+        Ok(RecordingExport {
+            filename,
+            path: path.display().to_string(),
+            complete: reader.is_complete(),
+        })
+    }
+
+    /// Remove a recording; the caller makes sure its session isn't still writing it
+    pub fn delete(&self, id: &str) -> Result<(), String> {
+        check_id(id)?;
+        let file = self.find(id)?;
+        std::fs::remove_file(&file.path)
+            .map_err(|e| format!("Failed to delete recording: {}", e))
+    }
+
+    /// Rewrite recordings after the crypto settings changed: `previous` reads them,
+    /// the current settings write them. Copies are staged next to the originals and
+    /// only replace them once `commit` (the store's own rekey) succeeds; running
+    /// sessions switch files the same way. If the sidecar stops in between, `recover`
+    /// sorts it out at the next start. Returns the recordings that couldn't be
+    /// rewritten, which keep their old settings.
+    pub fn rekey<E: From<String>>(&self, previous: &SharedCrypto, commit: impl FnOnce() -> Result<(), E>) -> Result<Vec<String>, E> {
+        let _rekeying = self.rekeying.lock().unwrap();
+        let old = self.with_crypto(previous.clone());
+        let live: HashMap<String, SharedRecorder> = self.live.lock().unwrap()
+            .iter()
+            .filter_map(|(id, recorder)| Some((id.clone(), recorder.upgrade()?)))
+            .collect();
+        let (mut staged, mut switched, mut skipped) = (Vec::new(), Vec::new(), Vec::new());
+
+        let mut stage_all = || -> Result<(), String> {
+            for (id, file, _) in self.files()? {
+                // Already rewritten by an earlier rekey, or written since
+                if live.contains_key(&id) || self.is_current(&id, &file) {
+                    continue;
+                }
+                match old.open_file(&id, &file) {
+                    Ok(reader) => staged.push(self.stage(&id, &file, reader)
+                        .map_err(|e| format!("Failed to rewrite recording {}: {}", id, e))?),
+                    // Unreadable with the old settings too; left as it is
+                    Err(e) => skipped.push(format!("{}: {}", id, e)),
+                }
+            }
+            for recorder in live.values() {
+                if let Some(recorder) = recorder.lock().unwrap().as_mut() {
+                    recorder.switch(self, &format!("{}{}", PART_EXT, REKEY_EXT))
+                        .map_err(|e| format!("Failed to rewrite recording {}: {}", recorder.id, e))?;
+                    switched.push(recorder.id.clone());
+                }
+            }
+            Ok(())
+        };
+        let outcome = stage_all().map_err(E::from).and_then(|()| commit());
+        if let Err(e) = outcome {
+            for file in &staged {
+                let _ = std::fs::remove_file(&file.staged);
+            }
+            for id in &switched {
+                if let Some(recorder) = live[id].lock().unwrap().as_mut() {
+                    let restored = recorder.switch(&old, &format!("{}{}", PART_EXT, ROLLBACK_EXT))
+                        .and_then(|()| recorder.commit_switch());
+                    if let Err(e) = restored {
+                        eprintln!("Failed to restore recording {}: {}", id, e);
+                    }
+                }
+            }
+            return Err(e);
+        }
+
+        // The store has committed; whatever fails from here is finished by `recover`
+        for file in &staged {
+            if let Err(e) = file.commit() {
+                skipped.push(format!("{}: {}", file.id, e));
+            }
+        }
+        for id in &switched {
+            if let Some(recorder) = live[id].lock().unwrap().as_mut() {
+                if let Err(e) = recorder.commit_switch() {
+                    skipped.push(format!("{}: {}", id, e));
+                }
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Finish or undo a rekey that was cut short. Each staged copy either replaces its
+    /// original or is dropped, whichever the current settings (those the state loaded
+    /// with) can read; a copy they can't read belongs to a rekey that never committed.
+    /// Run once the state has loaded, before any session starts recording. Returns the
+    /// copies that couldn't be sorted out.
+    pub fn recover(&self) -> Vec<String> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => return vec![format!("Failed to read recordings: {}", e)],
+        };
+        let encrypt = self.crypto.lock().unwrap().is_encryption_enabled();
+        let mut errors = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(target) = name.strip_suffix(REKEY_EXT).or_else(|| name.strip_suffix(ROLLBACK_EXT)) else {
+                continue;
+            };
+            let Some((id, file)) = self.parse_name(target) else {
+                continue;
+            };
+            let staged = CastFile { path: entry.path(), ..file };
+            let original = self.find(&id).ok();
+            let original_reads = original.as_ref().map(|original| self.open_file(&id, original).is_ok());
+            let keep = match (self.open_file(&id, &staged).is_ok(), original_reads) {
+                // Both read, as after turning encryption on or off: the setting decides
+                (true, Some(true)) => staged.encrypted == encrypt,
+                (true, _) => true,
+                (false, Some(true)) => false,
+                (false, _) => {
+                    errors.push(format!("{}: neither copy can be read with the current settings", id));
+                    continue;
+                }
+            };
+            let sorted = if keep {
+                let original = original.map_or_else(|| file.path.clone(), |original| original.path);
+                Staged { id: id.clone(), staged: staged.path, path: file.path, original }.commit()
+            } else {
+                std::fs::remove_file(&staged.path)
+            };
+            if let Err(e) = sorted {
+                errors.push(format!("{}: {}", id, e));
+            }
+        }
+        errors
+    }
+
+    /// Whether a recording is stored the way the current settings would write it
+    fn is_current(&self, id: &str, file: &CastFile) -> bool {
+        let encrypt = self.crypto.lock().unwrap().is_encryption_enabled();
+        file.encrypted == encrypt && (!encrypt || self.open_file(id, file).is_ok())
+    }
+
+    /// Copy a recording into a staged file under these settings. Of an unfinished or
+    /// damaged recording, what reads back is kept, still marked incomplete.
+    fn stage(&self, id: &str, source: &CastFile, mut reader: CastReader) -> Result<Staged, String> {
+        let mut copy = self.create(id, &reader.header, REKEY_EXT)?;
+        let mut copied = Ok(());
+        while let Some(event) = reader.next_event() {
+            let line = serde_json::to_string(&event).expect("events serialize");
+            copied = copy.write_line(&line).map_err(|e| e.to_string());
+            if copied.is_err() {
+                break;
+            }
+        }
+        let path = if reader.is_complete() { copy.path.clone() } else { with_suffix(&copy.path, PART_EXT) };
+        let staged = with_suffix(&path, REKEY_EXT);
+        let copied = copied
+            .and_then(|()| copy.close())
+            .and_then(|()| std::fs::rename(&copy.part, &staged).map_err(|e| e.to_string()));
+        if let Err(e) = copied {
+            copy.discard();
+            return Err(e);
+        }
+        Ok(Staged { id: id.to_string(), staged, path, original: source.path.clone() })
+    }
+
+    fn find(&self, id: &str) -> Result<CastFile, String> {
+        [ENCRYPTED_EXT, PLAIN_EXT]
+            .iter()
+            .flat_map(|ext| [format!("{}{}", id, ext), format!("{}{}{}", id, ext, PART_EXT)])
+            .find_map(|name| self.parse_name(&name).filter(|(_, file)| file.path.exists()))
+            .map(|(_, file)| file)
+            .ok_or_else(|| "Recording not found".to_string())
+    }
+
+    fn parse_name(&self, name: &str) -> Option<(String, CastFile)> {
+        let (name, complete) = match name.strip_suffix(PART_EXT) {
+            Some(name) => (name, false),
+            None => (name, true),
+        };
+        let (id, encrypted) = match name.strip_suffix(ENCRYPTED_EXT) {
+            Some(id) => (id, true),
+            None => (name.strip_suffix(PLAIN_EXT)?, false),
+        };
+        check_id(id).ok()?;
+        let mut file_name = format!("{}{}", id, if encrypted { ENCRYPTED_EXT } else { PLAIN_EXT });
+        if !complete {
+            file_name.push_str(PART_EXT);
+        }
+        Some((id.to_string(), CastFile { path: self.dir.join(file_name), encrypted, complete }))
+    }
+
+    fn open_file(&self, id: &str, file: &CastFile) -> Result<CastReader, String> {
+        let raw = BufReader::new(File::open(&file.path)
+            .map_err(|e| format!("Failed to open recording: {}", e))?);
+        let inner: Box<dyn Read + Send> = if file.encrypted {
+            Box::new(self.crypto.lock().unwrap()
+                .decrypt_reader(raw, ARTIFACT_RECORDING, &logical_path(id))
+                .map_err(|e| format!("Failed to decrypt recording: {}", e))?)
+        } else {
+            Box::new(raw)
+        };
+        CastReader::new(inner, file.complete)
+    }
+}
+
+/// Recordings are named after session ids, which keeps ids from naming other files
+fn check_id(id: &str) -> Result<(), String> {
+    Uuid::parse_str(id)
+        .map(|_| ())
+        .map_err(|_| format!("Invalid recording id: {}", id))
+}
+
+fn logical_path(id: &str) -> String {
+    format!("{}/{}{}", RECORDINGS_DIR, id, PLAIN_EXT)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    name.into()
+}
+
+enum CastWriter {
+    Plain(BufWriter<File>),
+    Encrypted(Box<StreamEncryptor<BufWriter<File>>>),
+}
+
+/// Appends one session's events to its recording. Finishing renames the file
+/// from `.part`; an encrypted one only decrypts in full after that.
+pub struct Recorder {
+    writer: Option<CastWriter>, // None once a write has failed
+    started: Instant,
+    pending: Vec<u8>, // the start of a character split across reads
+    id: String,
+    encrypted: bool,
+    part: PathBuf,
+    path: PathBuf,
+    written_with: SharedCrypto, // copy of the settings the file is written with
+    live: Option<Recordings>, // set for a session's recording, not for a rekey copy
+    superseded: Option<PathBuf>, // the file written before a rekey switched over, until it commits
+}
+
+impl Recorder {
+    pub fn output(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        // Hold back an incomplete character at the end for the next read
+        let complete = match std::str::from_utf8(&self.pending) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => self.pending.len(),
+        };
+        if complete == 0 {
+            return;
+        }
+        let text = String::from_utf8_lossy(&self.pending[..complete]).into_owned();
+        self.pending.drain(..complete);
+        self.event("o", text);
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.event("r", format!("{}x{}", cols, rows));
+    }
+
+    pub fn marker(&mut self, label: &str) {
+        self.event("m", label.to_string());
+    }
+
+    fn event(&mut self, code: &str, data: String) {
+        // Microseconds, like asciinema writes them
+        let time = (self.started.elapsed().as_secs_f64() * 1e6).round() / 1e6;
+        let line = serde_json::to_string(&CastEvent(time, code.to_string(), data))
+            .expect("events serialize");
+        if let Err(e) = self.write_line(&line) {
+            eprintln!("Failed to write recording {}: {}", self.part.display(), e);
+            self.writer = None;
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let writer: &mut dyn Write = match &mut self.writer {
+            Some(CastWriter::Plain(writer)) => writer,
+            Some(CastWriter::Encrypted(writer)) => writer.as_mut(),
+            None => return Ok(()),
+        };
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")
+    }
+
+    /// Write the last chunk and close the file, keeping its name
+    fn close(&mut self) -> Result<(), String> {
+        if !self.pending.is_empty() {
+            let rest = String::from_utf8_lossy(&std::mem::take(&mut self.pending)).into_owned();
+            self.event("o", rest);
+        }
+        let file = match self.writer.take() {
+            Some(CastWriter::Plain(writer)) => writer.into_inner().map_err(|e| e.into_error().to_string())?,
+            Some(CastWriter::Encrypted(writer)) => writer.finish()
+                .map_err(|e| e.to_string())?
+                .into_inner()
+                .map_err(|e| e.into_error().to_string())?,
+            None => return Err("recording was cut short by a write error".to_string()),
+        };
+        file.sync_all().map_err(|e| e.to_string())
+    }
+
+    /// Write the last chunk and move the file to its final name
+    fn finish(mut self) -> Result<(), String> {
+        self.close()?;
+        std::fs::rename(&self.part, &self.path).map_err(|e| e.to_string())?;
+        if let Some(recordings) = self.live.take() {
+            recordings.live.lock().unwrap().remove(&self.id);
+        }
+        Ok(())
+    }
+
+    /// Carry on under `target`'s settings: the events so far are copied into a new
+    /// file, `<name><staging>`, which takes the writes from now on. The old file
+    /// stays until `commit_switch`.
+    fn switch(&mut self, target: &Recordings, staging: &str) -> Result<(), String> {
+        // A recorder stopped by a write error has its file copied but stays stopped
+        let writing = self.writer.is_some();
+        let pending = std::mem::take(&mut self.pending);
+        if writing {
+            self.close()?;
+        }
+        let source = CastFile { path: self.part.clone(), encrypted: self.encrypted, complete: false };
+        let mut reader = target.with_crypto(self.written_with.clone()).open_file(&self.id, &source)?;
+        let mut copy = target.create(&self.id, &reader.header, staging)?;
+        let mut copied = Ok(());
+        while let Some(event) = reader.next_event() {
+            let line = serde_json::to_string(&event).expect("events serialize");
+            copied = copy.write_line(&line).map_err(|e| e.to_string());
+            if copied.is_err() {
+                break;
+            }
+        }
+        if let Err(e) = copied.and_then(|()| if writing { Ok(()) } else { copy.close() }) {
+            copy.discard();
+            return Err(e);
+        }
+
+        self.writer = copy.writer.take();
+        self.pending = pending;
+        self.encrypted = copy.encrypted;
+        self.written_with = copy.written_with.clone();
+        self.path = std::mem::take(&mut copy.path);
+        self.superseded = Some(std::mem::replace(&mut self.part, std::mem::take(&mut copy.part)));
+        Ok(())
+    }
+
+    /// Make a switch final: the new file takes the recording's name, the old one goes
+    fn commit_switch(&mut self) -> Result<(), String> {
+        let Some(superseded) = self.superseded.take() else {
+            return Ok(());
+        };
+        let part = with_suffix(&self.path, PART_EXT);
+        std::fs::rename(&self.part, &part).map_err(|e| e.to_string())?;
+        self.part = part;
+        if superseded != self.part {
+            std::fs::remove_file(&superseded).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn discard(self) {
+        let _ = std::fs::remove_file(&self.part);
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Finish takes this; otherwise the recording stopped without finishing
+        if let Some(recordings) = self.live.take() {
+            recordings.live.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+/// Finish a session's recording. Takes the rekey lock first, the order a rekey locks
+/// them in, so a rekey never sees the recorder half-finished.
+pub fn finish_recorder(recording: &SharedRecorder) -> Result<(), String> {
+    let recordings = match recording.lock().unwrap().as_ref() {
+        Some(recorder) => recorder.live.clone(),
+        None => return Ok(()),
+    };
+    let _rekeying = recordings.as_ref().map(|recordings| recordings.rekeying.lock().unwrap());
+    match recording.lock().unwrap().take() {
+        Some(recorder) => recorder.finish(),
+        None => Ok(()),
+    }
+}
+
+/// Reads a recording line by line. Reading stops at the first line that fails to
+/// decrypt or parse; `is_complete` tells whether the whole file was read.
+pub struct CastReader {
+    lines: std::io::Lines<BufReader<Box<dyn Read + Send>>>,
+    pub header: CastHeader,
+    finished: bool, // the file was fully written
+    damaged: bool,
+}
+
+impl CastReader {
+    fn new(inner: Box<dyn Read + Send>, finished: bool) -> Result<Self, String> {
+        let mut lines = BufReader::new(inner).lines();
+        let header: CastHeader = match lines.next() {
+            Some(Ok(line)) => serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid recording header: {}", e))?,
+            Some(Err(e)) => return Err(format!("Failed to read recording: {}", e)),
+            None => return Err("Recording is empty".to_string()),
+        };
+        if header.version != ASCIICAST_VERSION {
+            return Err(format!("Unsupported asciicast version {}", header.version));
+        }
+        Ok(Self { lines, header, finished, damaged: false })
+    }
+
+    pub fn next_event(&mut self) -> Option<CastEvent> {
+        if self.damaged {
+            return None;
+        }
+        loop {
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => match serde_json::from_str(&line) {
+                    Ok(event) => return Some(event),
+                    Err(_) => break,
+                },
+                Err(_) => break,
+            }
+        }
+        self.damaged = true;
+        None
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.finished && !self.damaged
+    }
+}
+
+/// An event of a replay, sent to TerminalPanel with the recorded timing
+#[derive(Debug, Serialize, Clone)]
+pub struct ReplayFrame {
+    pub replay_id: String,
+    pub time: f64,
+    pub code: String, // asciicast event code
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ReplayEnd {
+    pub replay_id: String,
+    pub stopped: bool,
+    pub complete: bool,
+}
+
+/// Replays in progress, by id
+#[derive(Clone, Default)]
+pub struct Replays(Arc<std::sync::Mutex<HashMap<String, Arc<AtomicBool>>>>);
+
+impl Replays {
+    /// Register a replay; its flag is set when it is to stop
+    fn begin(&self, replay_id: &str) -> Arc<AtomicBool> {
+        let stop = Arc::new(AtomicBool::new(false));
+        self.0.lock().unwrap().insert(replay_id.to_string(), stop.clone());
+        stop
+    }
+
+    fn end(&self, replay_id: &str) {
+        self.0.lock().unwrap().remove(replay_id);
+    }
+
+    fn stop(&self, replay_id: &str) {
+        if let Some(stop) = self.0.lock().unwrap().get(replay_id) {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Send the recording's events to `emit` with their original spacing, divided by
+/// `speed`. The header comes first as a resize to its size. Returns whether the
+/// replay was stopped.
+pub fn play(reader: &mut CastReader, replay_id: &str, speed: f64, stop: &AtomicBool, mut emit: impl FnMut(ReplayFrame)) -> bool {
+    let frame = |time: f64, code: &str, data: String| ReplayFrame {
+        replay_id: replay_id.to_string(),
+        time,
+        code: code.to_string(),
+        data,
+    };
+    emit(frame(0.0, "r", format!("{}x{}", reader.header.width, reader.header.height)));
+
+    let started = Instant::now();
+    while let Some(CastEvent(time, code, data)) = reader.next_event() {
+        let due = started + Duration::from_secs_f64((time / speed).max(0.0));
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= due {
+                break;
+            }
+            thread::sleep((due - now).min(REPLAY_POLL));
+        }
+        emit(frame(time, &code, data));
+    }
+    stop.load(Ordering::SeqCst)
+}
+
+// Tauri command handlers
+#[tauri::command]
+pub async fn list_recordings(
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<Vec<RecordingInfo>, String> {
+    let recordings = state.lock().await.recordings();
+    tokio::task::spawn_blocking(move || recordings.list())
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Write the recording, decrypted, to the user's downloads directory
+#[tauri::command]
+pub async fn export_recording(
+    recording_id: String,
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<RecordingExport, String> {
+    let dir = app.path().download_dir()
+        .map_err(|e| format!("No downloads directory: {}", e))?;
+    let recordings = state.lock().await.recordings();
+    tokio::task::spawn_blocking(move || recordings.export(&recording_id, &dir))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub async fn delete_recording(
+    recording_id: String,
+    state: State<'_, Arc<Mutex<AppState>>>,
+    terminals: State<'_, TerminalManager>
+) -> Result<(), String> {
+    if terminals.get_session(&recording_id).is_some() {
+        return Err("The session is still being recorded".to_string());
+    }
+    state.lock().await.recordings().delete(&recording_id)
+}
+
+/// Stream a recording to the frontend as `recording_replay` events, then one
+/// `recording_replay_end`. Returns the replay id.
+#[tauri::command]
+pub async fn replay_recording(
+    recording_id: String,
+    speed: Option<f64>,
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<AppState>>>,
+    replays: State<'_, Replays>
+) -> Result<String, String> {
+    let speed = speed.unwrap_or(1.0);
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(format!("Invalid replay speed {}", speed));
+    }
+    let recordings = state.lock().await.recordings();
+    // Opened here, so a missing passphrase is reported to the caller
+    let mut reader = tokio::task::spawn_blocking(move || recordings.open(&recording_id))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let replay_id = Uuid::new_v4().to_string();
+    let stop = replays.begin(&replay_id);
+
+    let (id, replays) = (replay_id.clone(), replays.inner().clone());
+    thread::Builder::new()
+        .name(format!("replay-{}", replay_id))
+        .spawn(move || {
+            let stopped = play(&mut reader, &id, speed, &stop, |frame| {
+                if let Err(e) = app.emit(REPLAY_EVENT, &frame) {
+                    eprintln!("Failed to emit replay frame: {}", e);
+                }
+            });
+            replays.end(&id);
+            let end = ReplayEnd { replay_id: id, stopped, complete: reader.is_complete() };
+            if let Err(e) = app.emit(REPLAY_END_EVENT, &end) {
+                eprintln!("Failed to emit replay end: {}", e);
+            }
+        })
+        .map_err(|e| format!("Failed to start replay: {}", e))?;
+
+    Ok(replay_id)
+}
+
+#[tauri::command]
+pub async fn stop_replay(
+    replay_id: String,
+    replays: State<'_, Replays>
+) -> Result<(), String> {
+    replays.stop(&replay_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoManager;
+    use crate::passphrase::Secret;
+
+    fn recordings(passphrase: Option<&str>) -> (Recordings, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("multiapp-recordings-{}", Uuid::new_v4()));
+        (recordings_at(&dir, passphrase), dir)
+    }
+
+    fn recordings_at(dir: &Path, passphrase: Option<&str>) -> Recordings {
+        let crypto = CryptoManager::new(passphrase.map(|p| Secret::new(p.to_string())));
+        Recordings::new(dir, Arc::new(std::sync::Mutex::new(crypto)))
+    }
+
+    fn record(recordings: &Recordings, extra_output: &[u8]) -> String {
+        let id = Uuid::new_v4().to_string();
+        let recording = recordings.start(&id, "/bin/bash", 80, 24).unwrap();
+        write(&recording, |recorder| {
+            recorder.output(b"$ echo caf\xc3");
+            recorder.output(b"\xa9\r\n");
+            recorder.resize(100, 30);
+            recorder.marker("make test");
+            recorder.output(b"secret-output\r\n");
+            recorder.output(extra_output);
+        });
+        finish_recorder(&recording).unwrap();
+        id
+    }
+
+    fn write(recording: &SharedRecorder, record: impl FnOnce(&mut Recorder)) {
+        record(recording.lock().unwrap().as_mut().unwrap());
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap().flatten() {
+            std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+
+    fn rotate(recordings: &Recordings, old: &str, new: &str) -> SharedCrypto {
+        let previous = recordings.crypto.lock().unwrap().rotate_passphrase(old, Secret::new(new.to_string())).unwrap();
+        Arc::new(std::sync::Mutex::new(previous))
+    }
+
+    /// Export into the recordings' parent dir; returns the text and whether it is complete
+    fn export_text(recordings: &Recordings, id: &str) -> (String, bool) {
+        let export = recordings.export(id, recordings.dir.parent().unwrap()).unwrap();
+        let text = std::fs::read_to_string(&export.path).unwrap();
+        std::fs::remove_file(&export.path).unwrap();
+        (text, export.complete)
+    }
+
+    fn events(reader: &mut CastReader) -> Vec<(String, String)> {
+        std::iter::from_fn(|| reader.next_event())
+            .map(|CastEvent(_, code, data)| (code, data))
+            .collect()
+    }
+
+    #[test]
+    fn test_record_and_read_back() {
+        let (recordings, dir) = recordings(None);
+        let id = Uuid::new_v4().to_string();
+        let recorder = recordings.start(&id, "/bin/bash", 80, 24).unwrap();
+        write(&recorder, |recorder| recorder.output(b"partial\r\n"));
+
+        // Listed as incomplete while the session runs
+        let listed = recordings.list().unwrap();
+        assert_eq!((listed.len(), listed[0].complete), (1, false));
+        drop(recorder);
+
+        let id = record(&recordings, b"");
+        let mut reader = recordings.open(&id).unwrap();
+        assert_eq!((reader.header.width, reader.header.height), (80, 24));
+        assert_eq!(events(&mut reader), [
+            ("o".to_string(), "$ echo caf".to_string()),
+            ("o".to_string(), "é\r\n".to_string()),
+            ("r".to_string(), "100x30".to_string()),
+            ("m".to_string(), "make test".to_string()),
+            ("o".to_string(), "secret-output\r\n".to_string()),
+        ]);
+        assert!(reader.is_complete());
+
+        let out = dir.join("exports");
+        std::fs::create_dir_all(&out).unwrap();
+        let first = recordings.export(&id, &out).unwrap();
+        let second = recordings.export(&id, &out).unwrap();
+        assert!(first.filename.ends_with(".cast") && second.filename.ends_with("-2.cast"));
+        assert_eq!(std::fs::read_to_string(&first.path).unwrap().lines().count(), 6);
+        assert!(recordings.open("../state").is_err());
+
+        recordings.delete(&id).unwrap();
+        assert_eq!(recordings.list().unwrap().len(), 1);
+        recordings.delete(&listed[0].id).unwrap();
+        assert!(recordings.list().unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_recordings() {
+        let (recordings, dir) = recordings(Some("test_passphrase"));
+        // More than one chunk, so a damaged end leaves the start readable
+        let id = record(&recordings, &[b'x'; 100 * 1024]);
+
+        let file = dir.join(RECORDINGS_DIR).join(format!("{}{}", id, ENCRYPTED_EXT));
+        let raw = std::fs::read(&file).unwrap();
+        assert!(!raw.windows(13).any(|w| w == b"secret-output"));
+        let (text, complete) = export_text(&recordings, &id);
+        assert!(complete && text.contains("secret-output"));
+
+        // Truncated: what authenticates is still readable, and flagged incomplete
+        std::fs::write(&file, &raw[..raw.len() - 1]).unwrap();
+        let (text, complete) = export_text(&recordings, &id);
+        assert!(!complete);
+        assert!(text.contains("secret-output"));
+
+        let other = recordings_at(&dir, Some("wrong"));
+        assert!(other.open(&id).is_err());
+        assert!(other.list().unwrap()[0].error.is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rekey_follows_encryption_setting() {
+        let (plain, dir) = recordings(None);
+        let id = record(&plain, b"");
+
+        // Encryption turned on: the plain recording gets encrypted
+        let encrypted = recordings_at(&dir, Some("new"));
+        let previous = Arc::new(std::sync::Mutex::new(CryptoManager::new(None)));
+        assert_eq!(encrypted.rekey(&previous, || Ok::<_, String>(())), Ok(Vec::new()));
+        let listed = encrypted.list().unwrap();
+        assert!(listed[0].encrypted && listed[0].error.is_none());
+        assert!(export_text(&encrypted, &id).0.contains("secret-output"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rekey_covers_unfinished_recordings() {
+        let (recordings, dir) = recordings(Some("old"));
+        // Left behind by a crash: never finished, so the last chunk isn't final
+        let crashed = Uuid::new_v4().to_string();
+        let recording = recordings.start(&crashed, "/bin/bash", 80, 24).unwrap();
+        for _ in 0..150 {
+            write(&recording, |recorder| recorder.output(&[b'y'; 1024]));
+        }
+        std::mem::forget(recording.lock().unwrap().take());
+        drop(recording);
+        // Still being written while the passphrase changes
+        let running = Uuid::new_v4().to_string();
+        let recording = recordings.start(&running, "/bin/bash", 80, 24).unwrap();
+        write(&recording, |recorder| recorder.output(b"before-rotation\r\n"));
+
+        let previous = rotate(&recordings, "old", "new");
+        assert_eq!(recordings.rekey(&previous, || Ok::<_, String>(())), Ok(Vec::new()));
+        write(&recording, |recorder| recorder.output(b"after-rotation\r\n"));
+        finish_recorder(&recording).unwrap();
+
+        // Both read back under the new passphrase alone
+        let reader = recordings_at(&dir, Some("new"));
+        let listed = reader.list().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().all(|r| r.error.is_none()));
+        let crashed_info = listed.iter().find(|r| r.id == crashed).unwrap();
+        assert!(!crashed_info.complete);
+        assert!(export_text(&reader, &crashed).0.contains("yyyy"));
+        let (text, complete) = export_text(&reader, &running);
+        assert!(complete && text.contains("before-rotation") && text.contains("after-rotation"));
+        assert!(recordings_at(&dir, Some("old")).open(&running).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_commit_keeps_old_settings() {
+        let (recordings, dir) = recordings(Some("old"));
+        let finished = record(&recordings, b"");
+        let running = Uuid::new_v4().to_string();
+        let recording = recordings.start(&running, "/bin/bash", 80, 24).unwrap();
+        write(&recording, |recorder| recorder.output(b"before-rotation\r\n"));
+
+        let previous = rotate(&recordings, "old", "new");
+        let failed = recordings.rekey(&previous, || Err("store is read-only".to_string()));
+        assert_eq!(failed, Err("store is read-only".to_string()));
+        // What the caller does when the store didn't commit
+        std::mem::swap(&mut *recordings.crypto.lock().unwrap(), &mut *previous.lock().unwrap());
+        write(&recording, |recorder| recorder.output(b"after-rotation\r\n"));
+        finish_recorder(&recording).unwrap();
+
+        let reader = recordings_at(&dir, Some("old"));
+        assert_eq!(std::fs::read_dir(dir.join(RECORDINGS_DIR)).unwrap().count(), 2);
+        assert!(export_text(&reader, &finished).0.contains("secret-output"));
+        let (text, complete) = export_text(&reader, &running);
+        assert!(complete && text.contains("before-rotation") && text.contains("after-rotation"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_rekey_is_recovered() {
+        let (recordings, dir) = recordings(Some("old"));
+        let finished = record(&recordings, b"");
+        let running = Uuid::new_v4().to_string();
+        let recording = recordings.start(&running, "/bin/bash", 80, 24).unwrap();
+        // Enough chunks that some of the running copy reads back without being finished
+        for _ in 0..200 {
+            write(&recording, |recorder| recorder.output(&[b'z'; 1024]));
+        }
+
+        // The files as a crash while the store commits leaves them
+        let crashed = dir.join("crashed");
+        let previous = rotate(&recordings, "old", "new");
+        recordings.rekey(&previous, || {
+            copy_dir(&dir.join(RECORDINGS_DIR), &crashed.join(RECORDINGS_DIR));
+            Ok::<_, String>(())
+        }).unwrap();
+        finish_recorder(&recording).unwrap();
+
+        // Whether the store's rekey got rolled back or committed, every recording reads
+        // back under the passphrase the state loads with
+        for passphrase in ["old", "new"] {
+            let app_dir = dir.join(passphrase);
+            copy_dir(&crashed.join(RECORDINGS_DIR), &app_dir.join(RECORDINGS_DIR));
+            let recovered = recordings_at(&app_dir, Some(passphrase));
+            assert_eq!(recovered.recover(), Vec::<String>::new());
+            assert_eq!(std::fs::read_dir(app_dir.join(RECORDINGS_DIR)).unwrap().count(), 2);
+            assert!(recovered.list().unwrap().iter().all(|r| r.error.is_none()));
+            assert!(export_text(&recovered, &finished).0.contains("secret-output"));
+            assert!(export_text(&recovered, &running).0.contains("zzzz"));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_keeps_order_and_stops() {
+        let (recordings, dir) = recordings(None);
+        let id = record(&recordings, b"");
+
+        let mut frames = Vec::new();
+        let stopped = play(&mut recordings.open(&id).unwrap(), "r1", 1000.0, &AtomicBool::new(false), |f| frames.push(f));
+        assert!(!stopped);
+        let codes: Vec<&str> = frames.iter().map(|f| f.code.as_str()).collect();
+        assert_eq!(codes, ["r", "o", "o", "r", "m", "o"]);
+        assert_eq!(frames[0].data, "80x24");
+
+        let mut frames = 0;
+        assert!(play(&mut recordings.open(&id).unwrap(), "r2", 1.0, &AtomicBool::new(true), |_| frames += 1));
+        assert_eq!(frames, 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+// Persisted state schema versioning for MultiappV1 Sidecar
+// Wraps PersistedState in a versioned envelope and upgrades older layouts step by step
+
+use crate::state::PersistedState;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+/// Version written by this build. Bump it together with a new entry in MIGRATIONS.
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("State schema version {found} is newer than supported version {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("Migration from schema version {from} failed: {reason}")]
+    MigrationFailed { from: u32, reason: String },
+    #[error("Invalid state document: {0}")]
+    Invalid(#[from] serde_json::Error),
+}
+
+/// On-disk layout: `{"schema_version": N, "state": {...}}`
+#[derive(Serialize)]
+pub struct PersistedEnvelope<'a> {
+    pub schema_version: u32,
+    pub state: &'a PersistedState,
+}
+
+impl<'a> PersistedEnvelope<'a> {
+    pub fn new(state: &'a PersistedState) -> Self {
+        Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            state,
+        }
+    }
+}
+
+type Migration = fn(Value) -> Result<Value, String>;
+
+// MIGRATIONS[n] upgrades a version-n state object to version n + 1
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// Decode a stored document of any supported version into the current PersistedState
+pub fn decode(document: Value) -> Result<PersistedState, SchemaError> {
+    let (version, mut state) = split_envelope(document)?;
+
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(SchemaError::UnsupportedVersion {
+            found: version,
+            supported: CURRENT_SCHEMA_VERSION,
+        });
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        state = migration(state).map_err(|reason| SchemaError::MigrationFailed {
+            from: from as u32,
+            reason,
+        })?;
+    }
+
+    Ok(serde_json::from_value(state)?)
+}
+
+/// Check a bare version number, for stores that keep it outside the document
+pub fn check_version(version: u32) -> Result<(), SchemaError> {
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(SchemaError::UnsupportedVersion {
+            found: version,
+            supported: CURRENT_SCHEMA_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Unversioned documents (written before the envelope existed) are version 0
+fn split_envelope(document: Value) -> Result<(u32, Value), SchemaError> {
+    match document {
+        Value::Object(mut map) if map.contains_key("schema_version") => {
+            let version = map.get("schema_version")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| SchemaError::MigrationFailed {
+                    from: 0,
+                    reason: "schema_version is not a number".to_string(),
+                })? as u32;
+            let state = map.remove("state").unwrap_or_else(|| Value::Object(Map::new()));
+            Ok((version, state))
+        }
+        other => Ok((0, other)),
+    }
+}
+
+/// v0 -> v1: fill in collections and settings added after the first release
+fn migrate_v0_to_v1(state: Value) -> Result<Value, String> {
+    let Value::Object(mut state) = state else {
+        return Err("state is not an object".to_string());
+    };
+
+    for key in ["pending_operations", "notifications"] {
+        state.entry(key).or_insert_with(|| json!([]));
+    }
+    state.entry("terminal_sessions").or_insert_with(|| json!({}));
+
+    let config = state.entry("config").or_insert_with(|| json!({}));
+    let Value::Object(config) = config else {
+        return Err("config is not an object".to_string());
+    };
+    for (key, default) in [
+        ("theme", json!("dark")),
+        ("auto_approve_read_ops", json!(true)),
+        ("show_notifications", json!(true)),
+        ("terminal_shell", json!("/bin/bash")),
+        ("encryption_enabled", json!(false)),
+    ] {
+        config.entry(key).or_insert(default);
+    }
+
+    Ok(Value::Object(state))
+}
+
+/// v1 -> v2: payload bodies may be sealed under `payload_key`. Older builds would
+/// hand sealed payloads to GeanyLua as if they were plain, hence the bump; v1
+/// documents need no changes.
+fn migrate_v1_to_v2(state: Value) -> Result<Value, String> {
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_legacy_unversioned_state() {
+        let legacy = json!({
+            "config": { "theme": "light" },
+            "pending_operations": []
+        });
+
+        let state = decode(legacy).unwrap();
+        assert_eq!(state.config.theme, "light");
+        assert_eq!(state.config.terminal_shell, "/bin/bash");
+        assert!(state.notifications.is_empty());
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let state = PersistedState::default();
+        let document = serde_json::to_value(PersistedEnvelope::new(&state)).unwrap();
+
+        assert_eq!(document["schema_version"], json!(CURRENT_SCHEMA_VERSION));
+        assert!(decode(document).is_ok());
+    }
+
+    #[test]
+    fn test_future_version_is_rejected() {
+        let future = json!({ "schema_version": CURRENT_SCHEMA_VERSION + 1, "state": {} });
+
+        assert!(matches!(decode(future), Err(SchemaError::UnsupportedVersion { .. })));
+    }
+}
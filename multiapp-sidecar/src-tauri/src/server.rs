@@ -1,13 +1,28 @@
 // HTTP Server for GeanyLua communication
 // Supports both HTTP localhost and Unix domain sockets
 
+use crate::history::{self, ExportFormat, HistoryRange};
+use crate::notifications::{self, NotificationQuery};
+use crate::patch_import;
+use crate::payload_crypto;
+use crate::shell_integration;
 use crate::state::AppState;
+use crate::terminal::{self, SessionOptions, TerminalManager, TerminalSink};
+use crate::terminal_exec;
+use aes_gcm::aead::OsRng;
+use argon2::password_hash::rand_core::RngCore;
+use base64::{Engine as _, engine::general_purpose};
+use hyper::header::{CONTENT_TYPE, ORIGIN};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::io::Write;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tower_http::cors::{CorsLayer, Any};
 use anyhow::Result;
@@ -15,13 +30,54 @@ use anyhow::Result;
 const DEFAULT_PORT: u16 = 8765;
 const UNIX_SOCKET_PATH: &str = "/tmp/multiapp-sidecar.sock";
 
+/// Longest an exec request may wait for its command
+const MAX_EXEC_TIMEOUT_SECS: u64 = 3600;
+
+/// File in the app data dir holding the token terminal requests must carry
+pub const TERMINAL_TOKEN_FILE: &str = "terminal-token";
+const TERMINAL_TOKEN_HEADER: &str = "x-sidecar-token";
+
+/// The running terminals, and where sessions started over HTTP send their output
+#[derive(Clone)]
+pub struct TerminalHost {
+    pub manager: TerminalManager,
+    pub sink: Arc<dyn TerminalSink>,
+    pub token: Option<Arc<String>>, // None if it couldn't be written; terminal requests are refused
+}
+
+/// Make a new terminal token for this launch and write it, readable only by the
+/// user, to the app data dir for GeanyLua to pick up
+pub fn issue_terminal_token(app_dir: &Path) -> std::io::Result<String> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+
+    std::fs::create_dir_all(app_dir)?;
+    let path = app_dir.join(TERMINAL_TOKEN_FILE);
+    // A fresh file, so an old one with looser permissions isn't reused
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&path)?.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
 pub struct SidecarServer {
     state: Arc<Mutex<AppState>>,
+    terminals: TerminalHost,
 }
 
 impl SidecarServer {
-    pub fn new(state: Arc<Mutex<AppState>>) -> Self {
-        Self { state }
+    pub fn new(state: Arc<Mutex<AppState>>, terminals: TerminalHost) -> Self {
+        Self { state, terminals }
     }
 
     pub async fn start(self) -> Result<()> {
@@ -56,12 +112,15 @@ impl SidecarServer {
         let addr = SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT));
         
         let state = self.state.clone();
+        let terminals = self.terminals.clone();
         let make_svc = make_service_fn(move |_conn| {
             let state = state.clone();
+            let terminals = terminals.clone();
             async move {
                 Ok::<_, Infallible>(service_fn(move |req| {
                     let state = state.clone();
-                    handle_request(req, state)
+                    let terminals = terminals.clone();
+                    handle_request(req, state, terminals)
                 }))
             }
         });
@@ -89,8 +148,22 @@ impl SidecarServer {
 
 async fn handle_request(
     req: Request<Body>,
-    state: Arc<Mutex<AppState>>
+    state: Arc<Mutex<AppState>>,
+    terminals: TerminalHost
 ) -> Result<Response<Body>, Infallible> {
+    // Browsers send Origin on cross-site requests; GeanyLua never does
+    if req.headers().contains_key(ORIGIN) {
+        return Ok(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from("Cross-origin requests are not accepted"))
+            .unwrap());
+    }
+    if req.uri().path().starts_with("/terminal/") {
+        if let Err(response) = authorize_terminal(&req, &state, &terminals).await {
+            return Ok(response);
+        }
+    }
+
     let response = match (req.method(), req.uri().path()) {
         (&Method::GET, "/health") => {
             Response::builder()
@@ -116,14 +189,55 @@ async fn handle_request(
             handle_reject_operation(req, state).await
         }
 
+        (&Method::POST, "/operations/import") => {
+            handle_import_patch(req, state).await
+        }
+
+        (&Method::POST, "/history/export") => {
+            handle_export_history(req, state).await
+        }
+
         (&Method::GET, "/notifications") => {
-            handle_get_notifications(state).await
+            handle_get_notifications(req, state).await
+        }
+
+        (&Method::POST, "/notifications/dismiss") => {
+            handle_dismiss_notification(req, state).await
         }
 
         (&Method::POST, "/notifications/clear") => {
             handle_clear_notifications(state).await
         }
 
+        (&Method::GET, path) if path.starts_with("/terminal/") && path.ends_with("/commands") => {
+            // /terminal/{id}/commands
+            let session_id = path.trim_start_matches("/terminal/").trim_end_matches("/commands");
+            handle_terminal_commands(&req, session_id, terminals)
+        }
+
+        (&Method::POST, "/terminal/sessions") => {
+            handle_create_terminal(req, state, terminals).await
+        }
+
+        (&Method::POST, path) if path.starts_with("/terminal/") => {
+            // /terminal/{id}/input and /terminal/{id}/exec
+            let parts: Vec<String> = path.trim_start_matches("/terminal/").split('/').map(str::to_string).collect();
+            match parts.as_slice() {
+                [session_id, action] if action == "input" => {
+                    handle_terminal_input(req, session_id, terminals).await
+                }
+                [session_id, action] if action == "exec" => {
+                    handle_terminal_exec(req, session_id, state, terminals).await
+                }
+                _ => {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::from("Not Found"))
+                        .unwrap()
+                }
+            }
+        }
+
         _ => {
             Response::builder()
                 .status(StatusCode::NOT_FOUND)
@@ -175,9 +289,11 @@ async fn handle_events_endpoint(
                 "geanylua".to_string()
             );
             
-            app_state.add_notification(
+            app_state.add_operation_notification(
+                &operation_id,
                 &format!("New {} operation pending approval", operation_type),
-                "info"
+                "info",
+                "geanylua"
             );
             
             Response::builder()
@@ -192,9 +308,11 @@ async fn handle_events_endpoint(
         
         "chat_message" => {
             let message = event_data["message"].as_str().unwrap_or("");
-            app_state.add_notification(
+            app_state.add_notification_from(
                 &format!("Chat: {}", message),
-                "info"
+                "info",
+                "geanylua",
+                None
             );
             
             Response::builder()
@@ -253,9 +371,11 @@ async fn handle_approve_operation(
     
     match app_state.update_operation_status(operation_id, "approved".to_string()) {
         Ok(()) => {
-            app_state.add_notification(
+            app_state.add_operation_notification(
+                operation_id,
                 &format!("Operation {} approved", operation_id),
-                "info"
+                "info",
+                "geanylua"
             );
             
             Response::builder()
@@ -303,9 +423,11 @@ async fn handle_reject_operation(
     
     match app_state.update_operation_status(operation_id, "rejected".to_string()) {
         Ok(()) => {
-            app_state.add_notification(
+            app_state.add_operation_notification(
+                operation_id,
                 &format!("Operation {} rejected", operation_id),
-                "warning"
+                "warning",
+                "geanylua"
             );
             
             Response::builder()
@@ -323,16 +445,100 @@ async fn handle_reject_operation(
     }
 }
 
-async fn handle_get_notifications(state: Arc<Mutex<AppState>>) -> Response<Body> {
+async fn handle_get_notifications(
+    req: Request<Body>,
+    state: Arc<Mutex<AppState>>
+) -> Response<Body> {
+    let params = parse_query(req.uri().query());
+    let query = NotificationQuery {
+        level: params.get("level").filter(|l| !l.is_empty()).cloned(),
+        unread: params.get("unread").and_then(|u| u.parse().ok()),
+        cursor: params.get("cursor").filter(|c| !c.is_empty()).cloned(),
+        limit: params.get("limit").and_then(|l| l.parse().ok()),
+    };
+
     let app_state = state.lock().await;
+    // Without paging parameters, answer with the bare array older clients expect
+    let body = if params.is_empty() {
+        serde_json::to_string(&app_state.notifications).unwrap()
+    } else {
+        match notifications::paginate(&app_state.notifications, &query) {
+            Ok(page) => serde_json::to_string(&page).unwrap(),
+            Err(e) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(e))
+                    .unwrap();
+            }
+        }
+    };
     
     Response::builder()
         .status(StatusCode::OK)
         .header("content-type", "application/json")
-        .body(Body::from(serde_json::to_string(&app_state.notifications).unwrap()))
+        .body(Body::from(body))
         .unwrap()
 }
 
+async fn handle_dismiss_notification(
+    req: Request<Body>,
+    state: Arc<Mutex<AppState>>
+) -> Response<Body> {
+    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Failed to read body: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let request_data: Value = match serde_json::from_slice(&body_bytes) {
+        Ok(data) => data,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid JSON: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let notification_id = request_data["notification_id"].as_str().unwrap_or("");
+
+    let mut app_state = state.lock().await;
+
+    match app_state.dismiss_notification(notification_id) {
+        Ok(()) => {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"status": "dismissed"}).to_string()))
+                .unwrap()
+        }
+        Err(e) => {
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
+
+/// Split a `key=value&key=value` query string; values are not percent-decoded
+fn parse_query(query: Option<&str>) -> HashMap<String, String> {
+    query.unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("").to_string();
+            let value = parts.next().unwrap_or("").to_string();
+            (key, value)
+        })
+        .collect()
+}
+
 async fn handle_clear_notifications(state: Arc<Mutex<AppState>>) -> Response<Body> {
     let mut app_state = state.lock().await;
     app_state.clear_notifications();
@@ -343,3 +549,395 @@ async fn handle_clear_notifications(state: Arc<Mutex<AppState>>) -> Response<Bod
         .body(Body::from(json!({"status": "cleared"}).to_string()))
         .unwrap()
 }
+
+
+async fn handle_export_history(
+    req: Request<Body>,
+    state: Arc<Mutex<AppState>>
+) -> Response<Body> {
+    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Failed to read body: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let request_data: Value = match serde_json::from_slice(&body_bytes) {
+        Ok(data) => data,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid JSON: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let format: ExportFormat = match serde_json::from_value(request_data["format"].clone()) {
+        Ok(format) => format,
+        Err(_) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Invalid format: expected patch, script or bundle"))
+                .unwrap();
+        }
+    };
+    let range: HistoryRange = match &request_data["range"] {
+        Value::Null => HistoryRange::default(),
+        range => match serde_json::from_value(range.clone()) {
+            Ok(range) => range,
+            Err(e) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Invalid range: {}", e)))
+                    .unwrap();
+            }
+        },
+    };
+
+    let app_state = state.lock().await;
+
+    // Selection only fails on a from_id or to_id that isn't in the completed history
+    let operations = match history::select_operations(&app_state.pending_operations, &range) {
+        Ok(operations) => operations,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(e))
+                .unwrap();
+        }
+    };
+
+    match history::export_operations(&operations, format) {
+        Ok(artifact) => {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&artifact).unwrap()))
+                .unwrap()
+        }
+        Err(e) => {
+            let status = if e == payload_crypto::LOCKED_MESSAGE {
+                StatusCode::LOCKED
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Response::builder()
+                .status(status)
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
+
+async fn handle_import_patch(
+    req: Request<Body>,
+    state: Arc<Mutex<AppState>>
+) -> Response<Body> {
+    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Failed to read body: {}", e)))
+                .unwrap();
+        }
+    };
+
+    // Accept either {"patch": "...", "source": "..."} or a raw diff body
+    let (patch_text, source) = match serde_json::from_slice::<Value>(&body_bytes) {
+        Ok(data) => (
+            data["patch"].as_str().unwrap_or("").to_string(),
+            // Only the sidecar's own imports count against its notification limit
+            data["source"].as_str()
+                .filter(|source| *source != notifications::ORIGIN_SIDECAR)
+                .unwrap_or("geanylua")
+                .to_string(),
+        ),
+        Err(_) => (String::from_utf8_lossy(&body_bytes).into_owned(), "geanylua".to_string()),
+    };
+
+    let mut app_state = state.lock().await;
+
+    match patch_import::import_patch_into(&mut app_state, &patch_text, &source) {
+        Ok(response) => {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&response).unwrap()))
+                .unwrap()
+        }
+        Err(e) => {
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
+
+/// Terminal requests need the API switched on, a JSON body when they have one,
+/// and this launch's token
+async fn authorize_terminal(
+    req: &Request<Body>,
+    state: &Arc<Mutex<AppState>>,
+    terminals: &TerminalHost
+) -> Result<(), Response<Body>> {
+    let refuse = |status: StatusCode, message: &'static str| Err(Response::builder()
+        .status(status)
+        .body(Body::from(message))
+        .unwrap());
+
+    if !state.lock().await.config.terminal_http_api {
+        return refuse(StatusCode::FORBIDDEN, "The terminal API is turned off in the sidecar settings");
+    }
+    if req.method() == Method::POST {
+        let json_body = req.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+        if !json_body {
+            return refuse(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Expected Content-Type: application/json");
+        }
+    }
+    let Some(token) = &terminals.token else {
+        return refuse(StatusCode::SERVICE_UNAVAILABLE, "The terminal token could not be written");
+    };
+    let presented = req.headers().get(TERMINAL_TOKEN_HEADER).map(|value| value.as_bytes());
+    if !presented.is_some_and(|presented| tokens_match(presented, token.as_bytes())) {
+        return refuse(StatusCode::UNAUTHORIZED, "Missing or wrong terminal token");
+    }
+    Ok(())
+}
+
+/// Compare without stopping at the first differing byte
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Status for an error from the terminal manager
+fn terminal_error_status(error: &str) -> StatusCode {
+    match error {
+        terminal::SESSION_NOT_FOUND => StatusCode::NOT_FOUND,
+        terminal::EXEC_BUSY => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn handle_create_terminal(
+    req: Request<Body>,
+    state: Arc<Mutex<AppState>>,
+    terminals: TerminalHost
+) -> Response<Body> {
+    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Failed to read body: {}", e)))
+                .unwrap();
+        }
+    };
+
+    // Every field is optional, so an empty body is fine too
+    let request_data: Value = if body_bytes.is_empty() {
+        json!({})
+    } else {
+        match serde_json::from_slice(&body_bytes) {
+            Ok(data) => data,
+            Err(e) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Invalid JSON: {}", e)))
+                    .unwrap();
+            }
+        }
+    };
+
+    // The shell and its environment are the user's choice, not the caller's
+    if !request_data["shell"].is_null() || !request_data["env"].is_null() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("shell and env can't be set over HTTP; the configured shell is used"))
+            .unwrap();
+    }
+    let size = |field: &str, default: u16| request_data[field].as_u64()
+        .and_then(|n| u16::try_from(n).ok())
+        .unwrap_or(default);
+
+    let mut app_state = state.lock().await;
+    let options = SessionOptions {
+        cwd: request_data["cwd"].as_str().map(str::to_string),
+        shell_integration: app_state.config.terminal_shell_integration,
+        recording: app_state.recording_target(),
+        ..SessionOptions::new(
+            &app_state.config.terminal_shell,
+            size("rows", 24),
+            size("cols", 80),
+        )
+    };
+
+    match terminal::start_shared_session(&mut app_state, &terminals.manager, &options, terminals.sink.clone()) {
+        Ok(session) => {
+            app_state.add_notification_from(
+                &format!("GeanyLua opened a terminal session ({})", session.shell),
+                "info",
+                "geanylua",
+                None
+            );
+
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&session).unwrap()))
+                .unwrap()
+        }
+        Err(e) => {
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
+
+fn handle_terminal_commands(
+    req: &Request<Body>,
+    session_id: &str,
+    terminals: TerminalHost
+) -> Response<Body> {
+    let limit = parse_query(req.uri().query())
+        .get("limit")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(shell_integration::MAX_COMMAND_RECORDS);
+
+    match terminals.manager.commands(session_id, limit) {
+        Ok(commands) => {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&commands).unwrap()))
+                .unwrap()
+        }
+        Err(e) => {
+            Response::builder()
+                .status(terminal_error_status(&e))
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
+
+async fn handle_terminal_input(
+    req: Request<Body>,
+    session_id: &str,
+    terminals: TerminalHost
+) -> Response<Body> {
+    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Failed to read body: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let request_data: Value = match serde_json::from_slice(&body_bytes) {
+        Ok(data) => data,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid JSON: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let data = request_data["data"].as_str().unwrap_or("").to_string();
+    let manager = terminals.manager.clone();
+    let session_id = session_id.to_string();
+    // A full PTY input buffer blocks the write
+    let result = tokio::task::spawn_blocking(move || manager.write_to_session(&session_id, data.as_bytes()))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+
+    match result {
+        Ok(()) => {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"status": "written"}).to_string()))
+                .unwrap()
+        }
+        Err(e) => {
+            Response::builder()
+                .status(terminal_error_status(&e))
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
+
+async fn handle_terminal_exec(
+    req: Request<Body>,
+    session_id: &str,
+    state: Arc<Mutex<AppState>>,
+    terminals: TerminalHost
+) -> Response<Body> {
+    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Failed to read body: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let request_data: Value = match serde_json::from_slice(&body_bytes) {
+        Ok(data) => data,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid JSON: {}", e)))
+                .unwrap();
+        }
+    };
+
+    let command = request_data["command"].as_str().unwrap_or("");
+    if command.trim().is_empty() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("Missing command"))
+            .unwrap();
+    }
+    let timeout = request_data["timeout_secs"].as_u64()
+        .map(|secs| Duration::from_secs(secs.clamp(1, MAX_EXEC_TIMEOUT_SECS)))
+        .unwrap_or(terminal_exec::DEFAULT_EXEC_TIMEOUT);
+
+    // The state lock isn't held while the command runs
+    state.lock().await.add_notification_from(
+        &format!("Running in terminal: {}", command),
+        "info",
+        "geanylua",
+        None
+    );
+
+    match terminal_exec::exec(&terminals.manager, session_id, command, timeout).await {
+        Ok(result) => {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&result).unwrap()))
+                .unwrap()
+        }
+        Err(e) => {
+            Response::builder()
+                .status(terminal_error_status(&e))
+                .body(Body::from(e))
+                .unwrap()
+        }
+    }
+}
@@ -0,0 +1,500 @@
+// Shell integration for MultiappV1 Sidecar terminals
+// Has bash and zsh mark prompts and commands with OSC 133 and turns the marks into command records
+
+use crate::terminal_output;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+const BASH_SCRIPT: &str = r#"# MultiappV1 Sidecar shell integration (bash): marks prompts and commands with OSC 133
+[ -f "$HOME/.bashrc" ] && . "$HOME/.bashrc"
+
+if [[ $- == *i* && -z "$__multiapp_osc133" ]]; then
+__multiapp_osc133=1
+__multiapp_at_prompt=0
+__multiapp_ran=
+__multiapp_last_history=
+
+__multiapp_urlencode() {
+  local LC_ALL=C s="$1" out="" c n i
+  for (( i = 0; i < ${#s}; i++ )); do
+    c="${s:i:1}"
+    case "$c" in
+      [a-zA-Z0-9.~_-]) out+="$c" ;;
+      *) printf -v n '%d' "'$c"; printf -v c '%%%02X' $(( n & 255 )); out+="$c" ;;
+    esac
+  done
+  printf '%s' "$out"
+}
+
+__multiapp_precmd() {
+  local code=$?
+  __multiapp_at_prompt=0
+  [[ -n "$__multiapp_ran" ]] && printf '\e]133;D;%s\a' "$code"
+  __multiapp_ran=
+  [[ "$PS1" == *'133;B'* ]] || PS1="\[\e]133;A\a\]$PS1\[\e]133;B\a\]"
+  __multiapp_last_history=$(HISTTIMEFORMAT= builtin history 1)
+  return $code
+}
+
+__multiapp_prompt_ready() {
+  __multiapp_at_prompt=1
+}
+
+# The DEBUG trap runs before every simple command; the first one after a prompt starts the command line
+__multiapp_preexec() {
+  [[ "$__multiapp_at_prompt" == 1 && "$BASH_COMMAND" != __multiapp_precmd* ]] || return 0
+  __multiapp_at_prompt=0
+  __multiapp_ran=1
+  local line command="$BASH_COMMAND"
+  line=$(HISTTIMEFORMAT= builtin history 1)
+  # The whole line comes from history, unless the shell didn't record it
+  if [[ "$line" != "$__multiapp_last_history" && "$line" =~ ^\ *[0-9]+\*?\ +(.*)$ ]]; then
+    command="${BASH_REMATCH[1]}"
+  fi
+  printf '\e]133;C;cmdline_url=%s\a' "$(__multiapp_urlencode "$command")"
+}
+
+PROMPT_COMMAND="__multiapp_precmd${PROMPT_COMMAND:+; $PROMPT_COMMAND}; __multiapp_prompt_ready"
+trap '__multiapp_preexec' DEBUG
+fi
+"#;
+
+const ZSH_ENV: &str = r#"# MultiappV1 Sidecar shell integration (zsh): read the user's .zshenv from their ZDOTDIR
+__multiapp_zdotdir="$ZDOTDIR"
+ZDOTDIR="${MULTIAPP_USER_ZDOTDIR:-$HOME}"
+[[ -f "$ZDOTDIR/.zshenv" ]] && source "$ZDOTDIR/.zshenv"
+ZDOTDIR="$__multiapp_zdotdir"
+"#;
+
+const ZSH_RC: &str = r#"# MultiappV1 Sidecar shell integration (zsh): marks prompts and commands with OSC 133
+__multiapp_zdotdir="$ZDOTDIR"
+ZDOTDIR="${MULTIAPP_USER_ZDOTDIR:-$HOME}"
+unset MULTIAPP_USER_ZDOTDIR
+[[ -f "$ZDOTDIR/.zshrc" ]] && source "$ZDOTDIR/.zshrc"
+
+if [[ -o interactive && -z "$__multiapp_osc133" ]]; then
+__multiapp_osc133=1
+__multiapp_ran=
+
+__multiapp_urlencode() {
+  local LC_ALL=C s="$1" out="" c n i
+  for (( i = 1; i <= ${#s}; i++ )); do
+    c="${s[i]}"
+    case "$c" in
+      [a-zA-Z0-9.~_-]) out+="$c" ;;
+      *) printf -v n '%d' "'$c"; printf -v c '%%%02X' $(( n & 255 )); out+="$c" ;;
+    esac
+  done
+  printf '%s' "$out"
+}
+
+__multiapp_precmd() {
+  local code=$?
+  [[ -n "$__multiapp_ran" ]] && printf '\e]133;D;%s\a' "$code"
+  __multiapp_ran=
+  printf '\e]133;A\a'
+  [[ "$PS1" == *'133;B'* ]] || PS1="$PS1%{"$'\e]133;B\a'"%}"
+}
+
+__multiapp_preexec() {
+  __multiapp_ran=1
+  printf '\e]133;C;cmdline_url=%s\a' "$(__multiapp_urlencode "$1")"
+}
+
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd __multiapp_precmd
+add-zsh-hook preexec __multiapp_preexec
+fi
+"#;
+
+const BASH_RC_FILE: &str = "bashrc";
+const ZSH_DIR: &str = "zsh";
+
+/// Commands kept per session, oldest dropped first
+pub const MAX_COMMAND_RECORDS: usize = 200;
+
+/// Output kept per command record (the tail, cut at a line boundary)
+pub const MAX_RECORD_OUTPUT: usize = 64 * 1024;
+
+// OSC 133 longer than this isn't a marker we sent; it is passed through as output
+const MAX_MARKER_BYTES: usize = 64 * 1024;
+const MARKER_PREFIX: &[u8] = b"\x1b]133;";
+
+/// Write the integration scripts into `dir`, replacing older versions
+pub fn install(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir.join(ZSH_DIR))?;
+    std::fs::write(dir.join(BASH_RC_FILE), BASH_SCRIPT)?;
+    std::fs::write(dir.join(ZSH_DIR).join(".zshenv"), ZSH_ENV)?;
+    std::fs::write(dir.join(ZSH_DIR).join(".zshrc"), ZSH_RC)?;
+    Ok(())
+}
+
+/// How to start `shell` with the integration from `dir`: extra arguments and
+/// environment. Shells other than bash and zsh get none.
+pub fn launch(shell: &str, dir: &Path, user_zdotdir: Option<String>) -> (Vec<String>, Vec<(String, String)>) {
+    let name = Path::new(shell).file_name().and_then(|n| n.to_str()).unwrap_or("");
+    match name {
+        "bash" => (
+            vec!["--rcfile".to_string(), dir.join(BASH_RC_FILE).display().to_string()],
+            Vec::new(),
+        ),
+        "zsh" => {
+            let mut env = vec![("ZDOTDIR".to_string(), dir.join(ZSH_DIR).display().to_string())];
+            if let Some(user_zdotdir) = user_zdotdir {
+                env.push(("MULTIAPP_USER_ZDOTDIR".to_string(), user_zdotdir));
+            }
+            (Vec::new(), env)
+        }
+        _ => (Vec::new(), Vec::new()),
+    }
+}
+
+/// Where the scripts live under the app data directory
+pub fn default_dir(app_dir: &Path) -> PathBuf {
+    app_dir.join("shell-integration")
+}
+
+/// One command line run in a session
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CommandRecord {
+    pub id: u64,
+    pub command: String,
+    pub output_start: u64, // span of the session's output stream, as in frame offsets
+    pub output_end: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration_ms: Option<i64>,
+    pub output: String, // plain text: escape sequences removed, CRLF turned into LF
+    pub output_truncated: bool,
+}
+
+struct Running {
+    record: CommandRecord,
+    output: Vec<u8>,
+}
+
+/// Follows OSC 133 marks in a session's raw output
+pub struct CommandTracker {
+    position: u64,     // stream offset of the first byte not yet consumed
+    partial: Vec<u8>,  // a marker split across reads
+    running: Option<Running>,
+    next_id: u64,
+    label: Option<String>, // replaces the command line the shell reports next
+}
+
+impl Default for CommandTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandTracker {
+    pub fn new() -> Self {
+        Self {
+            position: 0,
+            partial: Vec::new(),
+            running: None,
+            next_id: 1,
+            label: None,
+        }
+    }
+
+    /// Record the next command under `command` instead of the line the shell
+    /// reports, e.g. for an exec whose line is a printf/eval wrapper
+    pub fn label_next(&mut self, command: &str) {
+        self.label = Some(command.to_string());
+    }
+
+    /// Feed raw output; returns the commands that finished in it
+    pub fn feed(&mut self, data: &[u8]) -> Vec<CommandRecord> {
+        let mut bytes = std::mem::take(&mut self.partial);
+        bytes.extend_from_slice(data);
+        let base = self.position;
+        let mut finished = Vec::new();
+
+        let mut i = 0;
+        while i < bytes.len() {
+            let Some(esc) = bytes[i..].iter().position(|&b| b == 0x1b) else {
+                self.capture(&bytes[i..]);
+                i = bytes.len();
+                break;
+            };
+            self.capture(&bytes[i..i + esc]);
+            i += esc;
+
+            let rest = &bytes[i..];
+            if rest.len() < MARKER_PREFIX.len() && MARKER_PREFIX.starts_with(rest) {
+                break; // maybe a marker; wait for more
+            }
+            if !rest.starts_with(MARKER_PREFIX) {
+                self.capture(&rest[..1]);
+                i += 1;
+                continue;
+            }
+
+            match marker_end(rest) {
+                Some((body_len, total)) => {
+                    let body = String::from_utf8_lossy(&rest[MARKER_PREFIX.len()..body_len]).into_owned();
+                    let start = base + i as u64;
+                    let end = start + total as u64;
+                    finished.extend(self.mark(&body, start, end));
+                    i += total;
+                }
+                None if rest.len() > MAX_MARKER_BYTES => {
+                    self.capture(&rest[..1]);
+                    i += 1;
+                }
+                None => break,
+            }
+        }
+
+        self.partial = bytes[i..].to_vec();
+        self.position = base + i as u64;
+        finished
+    }
+
+    /// The command still running, if any
+    pub fn running(&self) -> Option<CommandRecord> {
+        self.running.as_ref().map(|running| CommandRecord {
+            output: plain_text(&running.output),
+            ..running.record.clone()
+        })
+    }
+
+    fn capture(&mut self, bytes: &[u8]) {
+        if let Some(running) = &mut self.running {
+            running.output.extend_from_slice(bytes);
+            // Trim in batches, not on every read
+            if running.output.len() > 2 * MAX_RECORD_OUTPUT {
+                terminal_output::trim_tail(&mut running.output, MAX_RECORD_OUTPUT);
+                running.record.output_truncated = true;
+            }
+        }
+    }
+
+    fn mark(&mut self, body: &str, start: u64, end: u64) -> Option<CommandRecord> {
+        let mut fields = body.split(';');
+        match fields.next() {
+            // Prompt start without an end mark: the command's status is unknown
+            Some("A") => self.finish(start, None),
+            Some("C") => {
+                let finished = self.finish(start, None);
+                let reported = fields
+                    .find_map(|field| field.strip_prefix("cmdline_url="))
+                    .map(percent_decode);
+                let command = self.label.take().or(reported).unwrap_or_default();
+                self.running = Some(Running {
+                    record: CommandRecord {
+                        id: self.next_id,
+                        command,
+                        output_start: end,
+                        output_end: None,
+                        exit_code: None,
+                        started_at: chrono::Utc::now(),
+                        finished_at: None,
+                        duration_ms: None,
+                        output: String::new(),
+                        output_truncated: false,
+                    },
+                    output: Vec::new(),
+                });
+                self.next_id += 1;
+                finished
+            }
+            Some("D") => {
+                let exit_code = fields.next().and_then(|code| code.parse().ok());
+                self.finish(start, exit_code)
+            }
+            _ => None,
+        }
+    }
+
+    fn finish(&mut self, at: u64, exit_code: Option<i32>) -> Option<CommandRecord> {
+        let Running { record, mut output } = self.running.take()?;
+        let truncated = output.len() > MAX_RECORD_OUTPUT;
+        terminal_output::trim_tail(&mut output, MAX_RECORD_OUTPUT);
+        let finished_at = chrono::Utc::now();
+        Some(CommandRecord {
+            output_end: Some(at),
+            exit_code,
+            finished_at: Some(finished_at),
+            duration_ms: Some((finished_at - record.started_at).num_milliseconds()),
+            output: plain_text(&output),
+            output_truncated: record.output_truncated || truncated,
+            ..record
+        })
+    }
+}
+
+/// Commands of one session: the last MAX_COMMAND_RECORDS finished ones and the one running
+#[derive(Default)]
+pub struct CommandLog {
+    tracker: CommandTracker,
+    records: VecDeque<CommandRecord>,
+}
+
+impl CommandLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        for record in self.tracker.feed(data) {
+            if self.records.len() == MAX_COMMAND_RECORDS {
+                self.records.pop_front();
+            }
+            self.records.push_back(record);
+        }
+    }
+
+    pub fn label_next(&mut self, command: &str) {
+        self.tracker.label_next(command);
+    }
+
+    /// The last `limit` commands, oldest first; a running command comes last
+    pub fn recent(&self, limit: usize) -> Vec<CommandRecord> {
+        let running = self.tracker.running();
+        let finished = limit.saturating_sub(running.is_some() as usize);
+        let mut records: Vec<CommandRecord> = self.records.iter()
+            .skip(self.records.len().saturating_sub(finished))
+            .cloned()
+            .collect();
+        records.extend(running.filter(|_| limit > 0));
+        records
+    }
+}
+
+/// Length of an OSC's body (from the start) and of the whole sequence, once its
+/// terminator (BEL or ST) has arrived
+fn marker_end(sequence: &[u8]) -> Option<(usize, usize)> {
+    let mut i = MARKER_PREFIX.len();
+    while i < sequence.len() {
+        match sequence[i] {
+            0x07 => return Some((i, i + 1)),
+            0x1b if sequence.get(i + 1) == Some(&b'\\') => return Some((i, i + 2)),
+            0x1b if i + 1 == sequence.len() => return None,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Terminal output as plain text: escape sequences and carriage returns removed
+pub fn plain_text(raw: &[u8]) -> String {
+    let mut text = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        match raw[i] {
+            0x1b => i += escape_len(&raw[i..]),
+            b'\r' => i += 1,
+            byte => {
+                text.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+/// Length of the escape sequence at the start of `seq` (CSI, OSC or a two-byte escape)
+fn escape_len(seq: &[u8]) -> usize {
+    match seq.get(1) {
+        Some(b'[') => seq[2..].iter()
+            .position(|b| (0x40..=0x7e).contains(b))
+            .map_or(seq.len(), |end| end + 3),
+        Some(b']') => (2..seq.len())
+            .find_map(|i| match seq[i] {
+                0x07 => Some(i + 1),
+                0x1b if seq.get(i + 1) == Some(&b'\\') => Some(i + 2),
+                _ => None,
+            })
+            .unwrap_or(seq.len()),
+        Some(b'(' | b')' | b'*' | b'+') => 3.min(seq.len()),
+        Some(_) => 2,
+        None => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str, output: &str, status: i32) -> Vec<u8> {
+        format!(
+            "\x1b]133;A\x07$ \x1b]133;B\x07{line}\r\n\x1b]133;C;cmdline_url={}\x07{output}\x1b]133;D;{status}\x07",
+            line.replace(' ', "%20")
+        ).into_bytes()
+    }
+
+    #[test]
+    fn test_tracker_builds_records_across_reads() {
+        let mut stream = command("ls -l", "\x1b[1mtotal\x1b[0m 0\r\n", 0);
+        stream.extend(command("false", "", 1));
+
+        // Byte by byte, so every marker is split
+        let mut tracker = CommandTracker::new();
+        let records: Vec<CommandRecord> = stream.iter()
+            .flat_map(|byte| tracker.feed(std::slice::from_ref(byte)))
+            .collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].id, records[0].command.as_str()), (1, "ls -l"));
+        assert_eq!(records[0].output, "total 0\n");
+        assert_eq!(records[0].exit_code, Some(0));
+        let span = records[0].output_start as usize..records[0].output_end.unwrap() as usize;
+        assert_eq!(&stream[span], b"\x1b[1mtotal\x1b[0m 0\r\n");
+        assert_eq!((records[1].command.as_str(), records[1].exit_code), ("false", Some(1)));
+        assert!(tracker.running().is_none());
+    }
+
+    #[test]
+    fn test_tracker_ignores_other_escapes() {
+        let mut tracker = CommandTracker::new();
+        assert!(tracker.feed(b"\x1b]133;C;cmdline_url=cat%20%C3%A9\x1b\\").is_empty());
+        assert!(tracker.feed(b"\x1b]0;title\x07partial ").is_empty());
+        let running = tracker.running().unwrap();
+        assert_eq!((running.command.as_str(), running.output.as_str()), ("cat é", "partial "));
+
+        let records = tracker.feed(b"\x1b]133;A\x07");
+        assert_eq!(records[0].exit_code, None);
+    }
+
+    #[test]
+    fn test_launch_arguments() {
+        let dir = Path::new("/data/shell-integration");
+        let (args, env) = launch("/usr/bin/bash", dir, None);
+        assert_eq!(args, ["--rcfile", "/data/shell-integration/bashrc"]);
+        assert!(env.is_empty());
+
+        let (args, env) = launch("/bin/zsh", dir, Some("/home/me/.config/zsh".to_string()));
+        assert!(args.is_empty());
+        assert_eq!(env[0], ("ZDOTDIR".to_string(), "/data/shell-integration/zsh".to_string()));
+        assert_eq!(env[1].1, "/home/me/.config/zsh");
+
+        assert_eq!(launch("/bin/sh", dir, None), (Vec::new(), Vec::new()));
+    }
+}
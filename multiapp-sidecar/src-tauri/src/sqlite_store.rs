@@ -0,0 +1,492 @@
+// Embedded SQLite state store for MultiappV1 Sidecar
+// Keeps operation metadata in indexed columns so history can be queried without loading everything
+
+use crate::crypto::{ArtifactBinding, EncryptedData};
+use crate::payload_crypto;
+use crate::schema::{self, CURRENT_SCHEMA_VERSION};
+use crate::state::{NotificationEvent, PendingOperation, PersistedState, SidecarConfig};
+use crate::store::{self, HistoryQuery, LoadFailure, LoadOutcome, SharedCrypto, StateStore, StoreBackend, StoreError};
+use chrono::SecondsFormat;
+use rusqlite::{params, params_from_iter, types::Value as SqlValue, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Prefix for column values sealed with the state passphrase
+const SEALED_PREFIX: &str = "enc:";
+
+// Sealed values are bound to their table and row id, so they can't be swapped between rows
+const ARTIFACT_OPERATION_PAYLOAD: &str = "operation-payload";
+const ARTIFACT_NOTIFICATION: &str = "notification";
+const ARTIFACT_TERMINAL_SESSION: &str = "terminal-session";
+const ARTIFACT_CONFIG: &str = "config";
+
+// Version 1 layout. Each later schema version appends the SQL that upgrades to it,
+// so SQL_MIGRATIONS[n] takes a database from user_version n to n + 1.
+const SCHEMA_V1: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS operations (
+        id TEXT PRIMARY KEY,
+        seq INTEGER NOT NULL,
+        operation_type TEXT NOT NULL,
+        status TEXT NOT NULL,
+        source TEXT NOT NULL,
+        changeset_id TEXT,
+        timestamp TEXT NOT NULL,
+        payload TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_operations_status_time ON operations(status, timestamp);
+    CREATE INDEX IF NOT EXISTS idx_operations_type_time ON operations(operation_type, timestamp);
+    CREATE INDEX IF NOT EXISTS idx_operations_changeset ON operations(changeset_id);
+    CREATE INDEX IF NOT EXISTS idx_operations_time ON operations(timestamp);
+    CREATE TABLE IF NOT EXISTS notifications (
+        id TEXT PRIMARY KEY,
+        seq INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS terminal_sessions (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+";
+
+// Version 2 keeps the wrapped payload key in meta; no table changes
+const SCHEMA_V2: &str = "";
+
+const SQL_MIGRATIONS: &[&str] = &[SCHEMA_V1, SCHEMA_V2];
+
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+    crypto: SharedCrypto,
+    read_only: Option<String>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path, crypto: SharedCrypto) -> Result<Self, StoreError> {
+        let (conn, read_only) = open_connection(path)?;
+        Ok(Self { conn, path: path.to_path_buf(), crypto, read_only })
+    }
+
+    /// Encrypt a column value when a passphrase is configured
+    fn seal(&self, kind: &str, row_path: String, plain: String) -> Result<String, StoreError> {
+        let mut crypto = self.crypto.lock().unwrap();
+        if !crypto.is_encryption_enabled() {
+            return Ok(plain);
+        }
+        let binding = ArtifactBinding::new(kind, row_path, CURRENT_SCHEMA_VERSION);
+        let encrypted = crypto.encrypt_bound(plain.as_bytes(), &binding)?;
+        Ok(format!("{}{}", SEALED_PREFIX, serde_json::to_string(&encrypted)?))
+    }
+
+    fn open_value(&self, kind: &str, row_path: &str, stored: String) -> Result<String, StoreError> {
+        match stored.strip_prefix(SEALED_PREFIX) {
+            Some(sealed) => {
+                let encrypted: EncryptedData = serde_json::from_str(sealed)?;
+                if let Some(binding) = &encrypted.binding {
+                    schema::check_version(binding.schema_version)?;
+                }
+                let plain = self.crypto.lock().unwrap().decrypt_bound(&encrypted, kind, row_path)?;
+                Ok(String::from_utf8_lossy(&plain).into_owned())
+            }
+            None => Ok(stored),
+        }
+    }
+
+    fn row_to_operation(&self, row: OperationRow) -> Result<PendingOperation, StoreError> {
+        let payload = self.open_value(ARTIFACT_OPERATION_PAYLOAD, &format!("operations/{}", row.id), row.payload)?;
+        let payload = serde_json::from_str(&payload)?;
+        let timestamp = chrono::DateTime::parse_from_rfc3339(&row.timestamp)
+            .map(|t| t.with_timezone(&chrono::Utc))
+            .unwrap_or_else(|_| chrono::Utc::now());
+
+        Ok(PendingOperation {
+            id: row.id,
+            operation_type: row.operation_type,
+            payload,
+            status: row.status,
+            timestamp,
+            source: row.source,
+            changeset_id: row.changeset_id,
+        })
+    }
+
+    fn load_rows(&self) -> Result<LoadOutcome, StoreError> {
+        let config_json: Option<String> = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'config'", [], |row| row.get(0))
+            .optional()?;
+        let Some(config_json) = config_json else {
+            return Ok(LoadOutcome { state: None, recovered_from: None, encrypted: false });
+        };
+        let config_json = self.open_value(ARTIFACT_CONFIG, "meta/config", config_json)?;
+        let config: SidecarConfig = serde_json::from_str(&config_json)?;
+        let payload_key: Option<String> = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'payload_key'", [], |row| row.get(0))
+            .optional()?;
+        let payload_key = payload_key.map(|key| serde_json::from_str(&key)).transpose()?;
+
+        let pending_operations = self.select_operations("", "ORDER BY seq", Vec::new())?;
+
+        let notification_rows: Vec<(String, String)> = self.conn
+            .prepare("SELECT id, data FROM notifications ORDER BY seq")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        let notifications = notification_rows.into_iter()
+            .map(|(id, data)| {
+                let data = self.open_value(ARTIFACT_NOTIFICATION, &format!("notifications/{}", id), data)?;
+                Ok(serde_json::from_str::<NotificationEvent>(&data)?)
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        let session_rows: Vec<(String, String)> = self.conn
+            .prepare("SELECT id, data FROM terminal_sessions")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        let terminal_sessions = session_rows.into_iter()
+            .map(|(id, data)| {
+                let data = self.open_value(ARTIFACT_TERMINAL_SESSION, &format!("terminal_sessions/{}", id), data)?;
+                Ok((id, serde_json::from_str(&data)?))
+            })
+            .collect::<Result<HashMap<_, _>, StoreError>>()?;
+
+        let encrypted: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM operations WHERE payload LIKE ?1)
+                 OR EXISTS(SELECT 1 FROM notifications WHERE data LIKE ?1)
+                 OR EXISTS(SELECT 1 FROM terminal_sessions WHERE data LIKE ?1)
+                 OR EXISTS(SELECT 1 FROM meta WHERE key = 'config' AND value LIKE ?1)",
+            params![format!("{}%", SEALED_PREFIX)],
+            |row| row.get(0),
+        )?;
+
+        Ok(LoadOutcome {
+            state: Some(PersistedState {
+                config,
+                pending_operations,
+                notifications,
+                terminal_sessions,
+                payload_key,
+            }),
+            recovered_from: None,
+            encrypted,
+        })
+    }
+
+    fn select_operations(&self, where_sql: &str, order_sql: &str, values: Vec<SqlValue>) -> Result<Vec<PendingOperation>, StoreError> {
+        let sql = format!(
+            "SELECT id, operation_type, status, source, changeset_id, timestamp, payload \
+             FROM operations {} {}",
+            where_sql, order_sql
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok(OperationRow {
+                    id: row.get(0)?,
+                    operation_type: row.get(1)?,
+                    status: row.get(2)?,
+                    source: row.get(3)?,
+                    changeset_id: row.get(4)?,
+                    timestamp: row.get(5)?,
+                    payload: row.get(6)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter().map(|row| self.row_to_operation(row)).collect()
+    }
+}
+
+struct OperationRow {
+    id: String,
+    operation_type: String,
+    status: String,
+    source: String,
+    changeset_id: Option<String>,
+    timestamp: String,
+    payload: String,
+}
+
+/// The database file plus its WAL and shared-memory companions
+pub(crate) fn database_files(path: &Path) -> Vec<PathBuf> {
+    ["", "-wal", "-shm"].iter()
+        .map(|suffix| {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(suffix);
+            path.with_file_name(name)
+        })
+        .collect()
+}
+
+fn open_connection(path: &Path) -> Result<(Connection, Option<String>), StoreError> {
+    let mut conn = Connection::open(path)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "synchronous", "FULL")?;
+
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    // A database from a newer build is opened but never written
+    let read_only = match schema::check_version(version) {
+        Ok(()) => {
+            migrate_database(&mut conn, version)?;
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+
+    Ok((conn, read_only))
+}
+
+/// Apply every pending schema step in one transaction and record the new user_version
+fn migrate_database(conn: &mut Connection, from: u32) -> Result<(), StoreError> {
+    if from >= CURRENT_SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    for step in &SQL_MIGRATIONS[from as usize..] {
+        tx.execute_batch(step)?;
+    }
+    tx.pragma_update(None, "user_version", CURRENT_SCHEMA_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn sql_timestamp(timestamp: &chrono::DateTime<chrono::Utc>) -> String {
+    // Fixed-width UTC so string comparison in SQL matches chronological order
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+impl StateStore for SqliteStore {
+    fn backend(&self) -> StoreBackend {
+        StoreBackend::Sqlite
+    }
+
+    fn load(&mut self) -> Result<LoadOutcome, StoreError> {
+        if let Some(reason) = &self.read_only {
+            return Err(StoreError::ReadOnly(reason.clone()));
+        }
+
+        let result = self.load_rows();
+        if let Err(e) = &result {
+            // Rows sealed by a newer build must not be rewritten by this one
+            if e.load_failure() == LoadFailure::UnknownVersion {
+                self.read_only = Some(e.to_string());
+            }
+        }
+        result
+    }
+
+    fn save(&mut self, state: &PersistedState) -> Result<(), StoreError> {
+        if let Some(reason) = &self.read_only {
+            return Err(StoreError::ReadOnly(reason.clone()));
+        }
+
+        // Seal outside the transaction so the crypto lock isn't held across SQL work
+        let operations = state.pending_operations.iter()
+            .map(|op| {
+                let payload = serde_json::to_string(&op.payload)?;
+                Ok((op, self.seal(ARTIFACT_OPERATION_PAYLOAD, format!("operations/{}", op.id), payload)?))
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        let notifications = state.notifications.iter()
+            .map(|n| Ok((n, self.seal(ARTIFACT_NOTIFICATION, format!("notifications/{}", n.id), serde_json::to_string(n)?)?)))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        let terminal_sessions = state.terminal_sessions.iter()
+            .map(|(id, session)| {
+                let data = serde_json::to_string(session)?;
+                Ok((id, self.seal(ARTIFACT_TERMINAL_SESSION, format!("terminal_sessions/{}", id), data)?))
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        let config = self.seal(ARTIFACT_CONFIG, "meta/config".to_string(), serde_json::to_string(&state.config)?)?;
+        let payload_key = state.payload_key.as_ref().map(serde_json::to_string).transpose()?;
+        let operation_ids = serde_json::to_string(
+            &state.pending_operations.iter().map(|op| &op.id).collect::<Vec<_>>()
+        )?;
+
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('config', ?1)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![config],
+        )?;
+        match &payload_key {
+            Some(key) => tx.execute(
+                "INSERT INTO meta (key, value) VALUES ('payload_key', ?1)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key],
+            )?,
+            None => tx.execute("DELETE FROM meta WHERE key = 'payload_key'", [])?,
+        };
+
+        tx.execute(
+            "DELETE FROM operations WHERE id NOT IN (SELECT value FROM json_each(?1))",
+            params![operation_ids],
+        )?;
+        for (seq, (op, payload)) in operations.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO operations (id, seq, operation_type, status, source, changeset_id, timestamp, payload)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT(id) DO UPDATE SET
+                    seq = excluded.seq, status = excluded.status, payload = excluded.payload",
+                params![
+                    op.id, seq as i64, op.operation_type, op.status, op.source,
+                    op.changeset_id, sql_timestamp(&op.timestamp), payload
+                ],
+            )?;
+        }
+
+        tx.execute("DELETE FROM notifications", [])?;
+        for (seq, (notification, data)) in notifications.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO notifications (id, seq, data) VALUES (?1, ?2, ?3)",
+                params![notification.id, seq as i64, data],
+            )?;
+        }
+
+        tx.execute("DELETE FROM terminal_sessions", [])?;
+        for (id, data) in &terminal_sessions {
+            tx.execute(
+                "INSERT INTO terminal_sessions (id, data) VALUES (?1, ?2)",
+                params![id, data],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn rekey(&mut self, previous: SharedCrypto) -> Result<(), StoreError> {
+        if let Some(reason) = &self.read_only {
+            return Err(StoreError::ReadOnly(reason.clone()));
+        }
+
+        let current = std::mem::replace(&mut self.crypto, previous);
+        let loaded = self.load();
+        let previous = std::mem::replace(&mut self.crypto, current);
+
+        // save() rewrites every sealed column in one transaction
+        if let Some(mut state) = loaded?.state {
+            payload_crypto::rewrap(&mut state, &previous, &self.crypto)?;
+            self.save(&state)?;
+        }
+        // Drop free pages and WAL frames that may still hold the old bytes
+        self.conn.execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")?;
+        Ok(())
+    }
+
+    fn quarantine(&mut self, dir: &Path) -> Result<Vec<PathBuf>, StoreError> {
+        // Close the database (checkpointing the WAL) before moving its files
+        drop(std::mem::replace(&mut self.conn, Connection::open_in_memory()?));
+
+        let moved = match store::move_files(&database_files(&self.path), dir) {
+            Ok(moved) => moved,
+            Err(e) => {
+                // Leave the in-memory connection in place so nothing reaches the original
+                self.read_only = Some(format!("quarantine failed: {}", e));
+                return Err(e);
+            }
+        };
+
+        // Without a fresh database every later save would only reach the in-memory one
+        let (conn, read_only) = match open_connection(&self.path) {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.read_only = Some(format!("reopening after quarantine failed: {}", e));
+                return Err(e);
+            }
+        };
+        self.conn = conn;
+        self.read_only = read_only;
+        Ok(moved)
+    }
+
+    fn read_only_reason(&self) -> Option<String> {
+        self.read_only.clone()
+    }
+
+    fn query_operations(&mut self, query: &HistoryQuery) -> Result<Vec<PendingOperation>, StoreError> {
+        if let Some(reason) = &self.read_only {
+            return Err(StoreError::ReadOnly(reason.clone()));
+        }
+
+        let mut clauses = Vec::new();
+        let mut values = Vec::new();
+
+        if let Some(status) = &query.status {
+            clauses.push("status = ?");
+            values.push(SqlValue::Text(status.clone()));
+        }
+        if let Some(operation_type) = &query.operation_type {
+            clauses.push("operation_type = ?");
+            values.push(SqlValue::Text(operation_type.clone()));
+        }
+        if let Some(changeset_id) = &query.changeset_id {
+            clauses.push("changeset_id = ?");
+            values.push(SqlValue::Text(changeset_id.clone()));
+        }
+        if let Some(since) = &query.since {
+            clauses.push("timestamp >= ?");
+            values.push(SqlValue::Text(sql_timestamp(since)));
+        }
+        if let Some(until) = &query.until {
+            clauses.push("timestamp <= ?");
+            values.push(SqlValue::Text(sql_timestamp(until)));
+        }
+
+        let where_sql = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+        let order_sql = format!(
+            "ORDER BY timestamp DESC LIMIT {} OFFSET {}",
+            query.limit.map_or(-1, |l| l as i64),
+            query.offset.unwrap_or(0)
+        );
+
+        self.select_operations(&where_sql, &order_sql, values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoManager;
+    use crate::passphrase::Secret;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    fn crypto(passphrase: Option<&str>) -> SharedCrypto {
+        Arc::new(Mutex::new(CryptoManager::new(passphrase.map(|p| Secret::new(p.to_string())))))
+    }
+
+    #[test]
+    fn test_sessions_and_config_are_sealed() {
+        let dir = std::env::temp_dir().join(format!("multiapp-sqlite-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.db");
+        let mut state = PersistedState::default();
+        state.terminal_sessions.insert("s1".to_string(), serde_json::json!({"cwd": "/home/secret-project"}));
+
+        let mut store = SqliteStore::open(&path, crypto(Some("pass"))).unwrap();
+        store.save(&state).unwrap();
+        let (session, config): (String, String) = store.conn.query_row(
+            "SELECT (SELECT data FROM terminal_sessions), (SELECT value FROM meta WHERE key = 'config')",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert!(session.starts_with(SEALED_PREFIX) && !session.contains("secret-project"));
+        assert!(config.starts_with(SEALED_PREFIX));
+
+        let outcome = store.load().unwrap();
+        assert!(outcome.encrypted);
+        assert_eq!(outcome.state.unwrap().terminal_sessions, state.terminal_sessions);
+        drop(store);
+
+        let mut locked = SqliteStore::open(&path, crypto(None)).unwrap();
+        assert!(locked.load().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
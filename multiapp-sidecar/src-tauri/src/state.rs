@@ -1,10 +1,19 @@
 // Application state management for MultiappV1 Sidecar
 // Handles persistent encrypted state and configuration
 
-use crate::crypto::{CryptoManager, EncryptedData, CryptoError};
+use crate::crypto::{CryptoManager, CryptoError, EncryptedData};
+use crate::passphrase::Secret;
+use crate::payload_crypto::{self, PayloadKey};
+use crate::notifications::{self, NotificationAction, NotificationThrottle, RateDecision};
+use crate::persistence::PersistHandle;
+use crate::recording::Recordings;
+use crate::store::{self, HistoryQuery, LoadFailure, SharedCrypto, SharedStore, StoreBackend, StoreError};
+use crate::terminal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tauri::{AppHandle, Manager};
 use tokio::fs;
 use uuid::Uuid;
@@ -15,7 +24,30 @@ pub struct SidecarConfig {
     pub auto_approve_read_ops: bool,
     pub show_notifications: bool,
     pub terminal_shell: String,
+    #[serde(default)]
+    pub terminal_persist_scrollback: bool, // save recent output of shells running at shutdown
+    #[serde(default = "default_shell_integration")]
+    pub terminal_shell_integration: bool, // bash and zsh report each command (OSC 133)
+    #[serde(default)]
+    pub terminal_record_sessions: bool, // save each session as an asciicast recording
+    #[serde(default)]
+    pub terminal_http_api: bool, // GeanyLua may open sessions and run commands over HTTP
     pub encryption_enabled: bool,
+    #[serde(default = "default_max_notifications")]
+    pub max_notifications: usize, // oldest entries are evicted past this cap
+    #[serde(default)]
+    pub storage_backend: StoreBackend,
+}
+
+const MISSING_PASSPHRASE: &str =
+    "State encryption is enabled but no passphrase is available; configure a passphrase source (e.g. MULTIAPP_PASSPHRASE_FILE) and restart";
+
+fn default_max_notifications() -> usize {
+    200
+}
+
+fn default_shell_integration() -> bool {
+    true
 }
 
 impl Default for SidecarConfig {
@@ -25,7 +57,13 @@ impl Default for SidecarConfig {
             auto_approve_read_ops: true,
             show_notifications: true,
             terminal_shell: "/bin/bash".to_string(),
+            terminal_persist_scrollback: false,
+            terminal_shell_integration: default_shell_integration(),
+            terminal_record_sessions: false,
+            terminal_http_api: false,
             encryption_enabled: false,
+            max_notifications: default_max_notifications(),
+            storage_backend: StoreBackend::default(),
         }
     }
 }
@@ -38,6 +76,8 @@ pub struct PendingOperation {
     pub status: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
     pub source: String, // "geanylua" or "sidecar"
+    #[serde(default)]
+    pub changeset_id: Option<String>, // groups operations imported from one patch
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -47,6 +87,22 @@ pub struct NotificationEvent {
     pub level: String, // "info", "warning", "error"
     pub timestamp: chrono::DateTime<chrono::Utc>,
     pub dismissed: bool,
+    #[serde(default = "default_notification_source")]
+    pub source: String, // "geanylua" or "sidecar"
+    #[serde(default)]
+    pub operation_id: Option<String>,
+    #[serde(default = "default_notification_count")]
+    pub count: u32, // occurrences collapsed into this entry
+    #[serde(default)]
+    pub actions: Vec<NotificationAction>,
+}
+
+fn default_notification_source() -> String {
+    "sidecar".to_string()
+}
+
+fn default_notification_count() -> u32 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,6 +111,8 @@ pub struct PersistedState {
     pub pending_operations: Vec<PendingOperation>,
     pub notifications: Vec<NotificationEvent>,
     pub terminal_sessions: HashMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_key: Option<EncryptedData>, // data key for sealed payload fields, wrapped under the passphrase
 }
 
 impl Default for PersistedState {
@@ -64,100 +122,561 @@ impl Default for PersistedState {
             pending_operations: Vec::new(),
             notifications: Vec::new(),
             terminal_sessions: HashMap::new(),
+            payload_key: None,
         }
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct RecoveryResult {
+    pub recovered_from: String,
+    pub operations: usize,
+    pub notifications: usize,
+}
+
 pub struct AppState {
     pub config: SidecarConfig,
     pub pending_operations: Vec<PendingOperation>,
     pub notifications: Vec<NotificationEvent>,
     pub terminal_sessions: HashMap<String, serde_json::Value>,
-    crypto: CryptoManager,
-    state_file: PathBuf,
+    notification_throttle: NotificationThrottle,
+    persist_handle: Option<PersistHandle>,
+    payload_key: Option<PayloadKey>, // None while locked; sealed payloads then stay sealed in memory
+    wrapped_payload_key: Option<EncryptedData>,
+    crypto: SharedCrypto,
+    store: SharedStore,
+    store_writes: Arc<AtomicU64>, // bumped under the store lock by each save outside the persistence task
+    recordings: Recordings,
+    app_dir: PathBuf,
     app_handle: AppHandle,
 }
 
 impl AppState {
-    pub fn new(app_handle: &AppHandle) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(app_handle: &AppHandle, passphrase: Option<Secret>) -> Result<Self, Box<dyn std::error::Error>> {
         let app_dir = app_handle.path().app_data_dir()?;
         
         // Ensure app directory exists
         std::fs::create_dir_all(&app_dir)?;
         
-        let state_file = app_dir.join("sidecar_state.json");
-        let crypto = CryptoManager::default();
+        let crypto: SharedCrypto = Arc::new(std::sync::Mutex::new(CryptoManager::new(passphrase)));
+        let backend = store::detect_backend(&app_dir);
+        let (store, open_failure) = match store::open_store(&app_dir, backend, crypto.clone()) {
+            Ok(store) => (store, None),
+            Err(e) => {
+                // A database that can't even be opened can't quarantine itself
+                let dir = store::new_quarantine_dir(&app_dir);
+                store::move_files(&store::backend_files(&app_dir, backend), &dir)?;
+                (store::open_store(&app_dir, backend, crypto.clone())?, Some((e, dir)))
+            }
+        };
         
+        let recordings = Recordings::new(&app_dir, crypto.clone());
         let mut state = Self {
             config: SidecarConfig::default(),
             pending_operations: Vec::new(),
             notifications: Vec::new(),
             terminal_sessions: HashMap::new(),
+            notification_throttle: NotificationThrottle::new(),
+            persist_handle: None,
+            payload_key: None,
+            wrapped_payload_key: None,
             crypto,
-            state_file,
+            store: Arc::new(std::sync::Mutex::new(store)),
+            store_writes: Arc::new(AtomicU64::new(0)),
+            recordings,
+            app_dir,
             app_handle: app_handle.clone(),
         };
         
         // Load persisted state if it exists
-        if let Err(e) = state.load_state() {
-            eprintln!("Warning: Failed to load persisted state: {}", e);
-            // Add notification about state loading failure
-            state.add_notification("Failed to load persisted state", "warning");
+        let load_result = match open_failure {
+            Some((e, dir)) => {
+                eprintln!("Warning: Failed to open persisted state: {}", e);
+                state.report_load_failure(&e, Some(&dir));
+                false
+            }
+            None => match state.load_state() {
+                Ok(()) => true,
+                // Not a reason to throw the data away; the user just needs to supply the passphrase
+                Err(e) if e.load_failure() == LoadFailure::PassphraseRequired => {
+                    return Err(MISSING_PASSPHRASE.into());
+                }
+                Err(e) => {
+                    eprintln!("Warning: Failed to load persisted state: {}", e);
+                    state.quarantine_state(&e);
+                    false
+                }
+            },
+        };
+        
+        {
+            let mut crypto = state.crypto.lock().unwrap();
+            if !load_result && crypto.has_passphrase() {
+                state.config.encryption_enabled = true;
+            }
+            if state.config.encryption_enabled && !crypto.has_passphrase() {
+                return Err(MISSING_PASSPHRASE.into());
+            }
+            crypto.set_enabled(state.config.encryption_enabled);
+        }
+        state.unlock_payloads();
+        
+        // A rekey the sidecar stopped in the middle of; the state says which settings won
+        if load_result {
+            let errors = state.recordings.recover();
+            if !errors.is_empty() {
+                eprintln!("Warning: Failed to recover recordings: {}", errors.join("; "));
+            }
+        }
+        
+        // No shell survives a restart
+        if terminal::reconcile_sessions(&mut state.terminal_sessions) > 0 {
+            state.request_save();
+        }
+        
+        // The environment can request a backend before any config exists
+        let requested = match std::env::var("MULTIAPP_STORAGE_BACKEND").ok().as_deref() {
+            Some("sqlite") => StoreBackend::Sqlite,
+            Some("json") => StoreBackend::Json,
+            _ => state.config.storage_backend,
+        };
+        if requested != backend {
+            if let Err(e) = state.switch_backend(requested) {
+                eprintln!("Warning: Failed to migrate state to {:?}: {}", requested, e);
+                state.add_notification(&format!("Failed to migrate state storage: {}", e), "error");
+            }
         }
+        state.config.storage_backend = state.store_backend();
         
         Ok(state)
     }
     
-    /// Load state from encrypted file
-    fn load_state(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if !self.state_file.exists() {
-            return Ok(()); // No state file yet, use defaults
+    /// Load state from the configured store
+    fn load_state(&mut self) -> Result<(), StoreError> {
+        let outcome = self.store.lock().unwrap().load()?;
+        
+        let fresh = outcome.state.is_none();
+        if let Some(persisted_state) = outcome.state {
+            self.apply_persisted_state(persisted_state);
         }
         
-        let file_content = std::fs::read_to_string(&self.state_file)?;
+        // Before the setting was honoured, state was encrypted whenever a passphrase was set
+        let mut crypto = self.crypto.lock().unwrap();
+        if outcome.encrypted || (fresh && crypto.has_passphrase()) {
+            self.config.encryption_enabled = true;
+        }
+        crypto.set_enabled(self.config.encryption_enabled);
+        drop(crypto);
         
-        if self.crypto.is_encryption_enabled() {
-            // Try to decrypt the state
-            let encrypted_data: EncryptedData = serde_json::from_str(&file_content)?;
-            let persisted_state: PersistedState = self.crypto.decrypt_json(&encrypted_data)?;
-            
-            self.config = persisted_state.config;
-            self.pending_operations = persisted_state.pending_operations;
-            self.notifications = persisted_state.notifications;
-            self.terminal_sessions = persisted_state.terminal_sessions;
-        } else {
-            // Load unencrypted state
-            let persisted_state: PersistedState = serde_json::from_str(&file_content)?;
-            
-            self.config = persisted_state.config;
-            self.pending_operations = persisted_state.pending_operations;
-            self.notifications = persisted_state.notifications;
-            self.terminal_sessions = persisted_state.terminal_sessions;
+        if let Some(backup) = outcome.recovered_from {
+            self.add_notification(
+                &format!("State file was unreadable; restored from backup {}", backup),
+                "warning"
+            );
         }
         
         Ok(())
     }
     
-    /// Save state to encrypted file
-    pub fn save_state(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let persisted_state = PersistedState {
+    /// Move unreadable state files aside so the next save can't overwrite them.
+    /// Files from a newer schema stay where they are; the store is read-only instead.
+    fn quarantine_state(&mut self, error: &StoreError) {
+        let dir = match error.load_failure() {
+            LoadFailure::UnknownVersion => None,
+            _ => {
+                let dir = store::new_quarantine_dir(&self.app_dir);
+                match self.store.lock().unwrap().quarantine(&dir) {
+                    Ok(_) => Some(dir),
+                    Err(e) => {
+                        eprintln!("Warning: Failed to quarantine state: {}", e);
+                        None
+                    }
+                }
+            }
+        };
+        self.report_load_failure(error, dir.as_deref());
+    }
+    
+    fn report_load_failure(&mut self, error: &StoreError, quarantined: Option<&Path>) {
+        let failure = error.load_failure();
+        let reason = match failure {
+            LoadFailure::BadPassphrase => "Saved state could not be decrypted; the passphrase is wrong".to_string(),
+            LoadFailure::PassphraseRequired => "Saved state is encrypted but no passphrase is set".to_string(),
+            LoadFailure::Corrupt => format!("Saved state is corrupt ({})", error),
+            LoadFailure::UnknownVersion => format!("Saved state was written by a newer sidecar ({})", error),
+            LoadFailure::Unreadable => format!("Saved state could not be read ({})", error),
+        };
+        let message = match quarantined {
+            Some(dir) if matches!(failure, LoadFailure::BadPassphrase | LoadFailure::PassphraseRequired) => format!(
+                "{}. It was moved to {}; use Recover State to load it with the right passphrase.",
+                reason, dir.display()
+            ),
+            Some(dir) => format!("{}. It was moved to {}.", reason, dir.display()),
+            None => format!("{}. It was left in place and changes will not be saved.", reason),
+        };
+        self.add_notification(&message, "error");
+    }
+    
+    /// Retry the most recent quarantined state with a different passphrase. On success
+    /// it replaces the current state (keeping anything added since startup) and the
+    /// passphrase becomes the active one.
+    pub fn recover_state(&mut self, passphrase: Secret) -> Result<RecoveryResult, Box<dyn std::error::Error>> {
+        let dir = store::latest_quarantine(&self.app_dir)
+            .ok_or("No quarantined state to recover")?;
+        
+        let crypto: SharedCrypto = Arc::new(std::sync::Mutex::new(CryptoManager::new(Some(passphrase.clone()))));
+        let mut quarantined = store::open_store(&dir, store::detect_backend(&dir), crypto)?;
+        let outcome = quarantined.load()?;
+        let recovered = outcome.state.ok_or("Quarantined state is empty")?;
+        drop(quarantined);
+        
+        // Anything saved since startup used the old passphrase; bring it under the new one
+        let enabled = recovered.config.encryption_enabled || outcome.encrypted;
+        self.rekey_store(|crypto| {
+            let previous = crypto.fork();
+            crypto.set_passphrase(Some(passphrase));
+            crypto.set_enabled(enabled);
+            Ok(previous)
+        })?;
+        
+        let backend = self.store_backend();
+        // Taken as they are in memory; snapshot() would seal them under a key that's about to be replaced
+        let since_startup = (
+            std::mem::take(&mut self.pending_operations),
+            std::mem::take(&mut self.notifications),
+            std::mem::take(&mut self.terminal_sessions),
+        );
+        self.apply_persisted_state(recovered);
+        terminal::reconcile_sessions(&mut self.terminal_sessions);
+        self.config.storage_backend = backend;
+        self.config.encryption_enabled = enabled;
+        for op in since_startup.0 {
+            if !self.pending_operations.iter().any(|existing| existing.id == op.id) {
+                self.pending_operations.push(op);
+            }
+        }
+        self.notifications.extend(since_startup.1);
+        self.terminal_sessions.extend(since_startup.2);
+        self.unlock_payloads();
+        
+        store::retire_quarantine(&dir)?;
+        
+        let result = RecoveryResult {
+            recovered_from: dir.display().to_string(),
+            operations: self.pending_operations.len(),
+            notifications: self.notifications.len(),
+        };
+        self.add_notification(&format!("Recovered saved state from {}", result.recovered_from), "info");
+        Ok(result)
+    }
+    
+    fn apply_persisted_state(&mut self, persisted_state: PersistedState) {
+        self.config = persisted_state.config;
+        self.pending_operations = persisted_state.pending_operations;
+        self.notifications = persisted_state.notifications;
+        self.terminal_sessions = persisted_state.terminal_sessions;
+        self.wrapped_payload_key = persisted_state.payload_key;
+        self.payload_key = None;
+    }
+    
+    /// Copy of everything that gets persisted, with payload bodies sealed when the
+    /// payload key is unlocked. Fails rather than let a payload that should be sealed
+    /// reach the store in the clear.
+    pub fn snapshot(&self) -> Result<PersistedState, CryptoError> {
+        let pending_operations = self.pending_operations.iter()
+            .map(|op| match &self.payload_key {
+                Some(key) if !payload_crypto::is_sealed(&op.payload) => Ok(PendingOperation {
+                    payload: key.seal(&op.id, &op.payload)?,
+                    ..op.clone()
+                }),
+                _ => Ok(op.clone()),
+            })
+            .collect::<Result<_, CryptoError>>()?;
+        
+        Ok(PersistedState {
             config: self.config.clone(),
-            pending_operations: self.pending_operations.clone(),
+            pending_operations,
             notifications: self.notifications.clone(),
             terminal_sessions: self.terminal_sessions.clone(),
+            payload_key: self.wrapped_payload_key.clone(),
+        })
+    }
+    
+    /// Unwrap the payload key (creating one the first time a passphrase is available)
+    /// and open the payloads sealed under it. Without a passphrase this is a no-op and
+    /// payloads stay locked; ones added meanwhile are sealed on the first save after unlock.
+    fn unlock_payloads(&mut self) {
+        if self.payload_key.is_none() {
+            let mut crypto = self.crypto.lock().unwrap();
+            if !crypto.has_passphrase() {
+                return;
+            }
+            let generated = self.wrapped_payload_key.is_none();
+            let unlocked = match &self.wrapped_payload_key {
+                Some(wrapped) => PayloadKey::unwrap(wrapped, &mut crypto),
+                None => {
+                    let key = PayloadKey::generate();
+                    key.wrap(&mut crypto).map(|wrapped| {
+                        self.wrapped_payload_key = Some(wrapped);
+                        key
+                    })
+                }
+            };
+            drop(crypto);
+            
+            match unlocked {
+                Ok(key) => {
+                    self.payload_key = Some(key);
+                    // Seal payloads that were written in the clear
+                    if generated {
+                        self.request_save();
+                    }
+                }
+                Err(e) => {
+                    self.add_notification(
+                        &format!("Operation payloads stay locked; the passphrase does not open their key ({})", e),
+                        "error"
+                    );
+                    return;
+                }
+            }
+        }
+        
+        let key = self.payload_key.as_ref().expect("unlocked above");
+        let failed = key.open_all(&mut self.pending_operations);
+        if failed > 0 {
+            self.add_notification(&format!("{} operation payloads could not be decrypted", failed), "error");
+        }
+    }
+    
+    /// Payload of an operation, decrypted if it is sealed. Fails while locked.
+    pub fn reveal_payload(&self, operation_id: &str) -> Result<serde_json::Value, String> {
+        let operation = self.pending_operations.iter()
+            .find(|op| op.id == operation_id)
+            .ok_or_else(|| "Operation not found".to_string())?;
+        if !payload_crypto::is_sealed(&operation.payload) {
+            return Ok(operation.payload.clone());
+        }
+        self.payload_key.as_ref()
+            .ok_or_else(|| payload_crypto::LOCKED_MESSAGE.to_string())?
+            .open(&operation.id, &operation.payload)
+            .map_err(|e| e.to_string())
+    }
+    
+    /// Save state to the store immediately, on the calling thread
+    pub fn save_state(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let snapshot = self.snapshot()?;
+        let mut store = self.store.lock().unwrap();
+        self.store_writes.fetch_add(1, Ordering::SeqCst);
+        store.save(&snapshot)?;
+        Ok(())
+    }
+    
+    pub fn store(&self) -> SharedStore {
+        self.store.clone()
+    }
+    
+    /// Count of saves made outside the persistence task; a snapshot taken before
+    /// the count last changed must not be written over them
+    pub fn store_writes(&self) -> Arc<AtomicU64> {
+        self.store_writes.clone()
+    }
+    
+    pub fn store_backend(&self) -> StoreBackend {
+        self.store.lock().unwrap().backend()
+    }
+    
+    /// Migrate all state into another backend and make it the active store
+    pub fn switch_backend(&mut self, backend: StoreBackend) -> Result<(), Box<dyn std::error::Error>> {
+        let current = self.store_backend();
+        if current == backend {
+            return Ok(());
+        }
+        if let Some(reason) = self.store.lock().unwrap().read_only_reason() {
+            self.config.storage_backend = current;
+            return Err(format!("State store is read-only: {}", reason).into());
+        }
+        
+        let mut snapshot = self.snapshot()?;
+        snapshot.config.storage_backend = backend;
+        
+        // Hold the store lock so the background writer can't touch the old backend mid-switch
+        let mut active = self.store.lock().unwrap();
+        self.store_writes.fetch_add(1, Ordering::SeqCst);
+        let migrated = match store::migrate_store(&self.app_dir, current, backend, &snapshot, self.crypto.clone()) {
+            Ok(migrated) => migrated,
+            Err(e) => {
+                drop(active);
+                self.config.storage_backend = current;
+                return Err(e.into());
+            }
         };
+        *active = migrated;
+        drop(active);
+        self.config.storage_backend = backend;
         
-        let file_content = if self.crypto.is_encryption_enabled() {
-            let encrypted_data = self.crypto.encrypt_json(&persisted_state)?;
-            serde_json::to_string_pretty(&encrypted_data)?
-        } else {
-            serde_json::to_string_pretty(&persisted_state)?
+        self.add_notification(&format!("State storage migrated to {:?}", backend), "info");
+        Ok(())
+    }
+    
+    /// Provide the passphrase at runtime, e.g. when no passphrase source is configured.
+    /// Changing an existing passphrase goes through rotate_passphrase instead.
+    pub fn set_passphrase(&mut self, passphrase: Secret) -> Result<(), Box<dyn std::error::Error>> {
+        if passphrase.is_empty() {
+            return Err("Passphrase must not be empty".into());
+        }
+        if self.crypto.lock().unwrap().has_passphrase() {
+            return Err("A passphrase is already set; use rotate_passphrase to change it".into());
+        }
+        let enabled = self.config.encryption_enabled;
+        self.set_encryption(enabled, Some(passphrase))
+    }
+    
+    pub fn enable_encryption(&mut self, passphrase: Option<Secret>) -> Result<(), Box<dyn std::error::Error>> {
+        self.set_encryption(true, passphrase)?;
+        self.add_notification("State encryption enabled", "info");
+        Ok(())
+    }
+    
+    pub fn disable_encryption(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.set_encryption(false, None)?;
+        self.add_notification("State encryption disabled; saved state is now stored in plain text", "warning");
+        Ok(())
+    }
+    
+    /// Switch the crypto settings and rewrite the stored state (and backups) to match
+    fn set_encryption(&mut self, enabled: bool, passphrase: Option<Secret>) -> Result<(), Box<dyn std::error::Error>> {
+        self.rekey_store(|crypto| {
+            let previous = crypto.fork();
+            if passphrase.is_some() {
+                crypto.set_passphrase(passphrase);
+            }
+            if enabled && !crypto.has_passphrase() {
+                *crypto = previous;
+                return Err("Encryption needs a passphrase; set one first or pass it along".into());
+            }
+            crypto.set_enabled(enabled);
+            Ok(previous)
+        })?;
+        
+        self.config.encryption_enabled = enabled;
+        self.save_state()
+    }
+    
+    /// Change the passphrase. `old` must match the current one and must decrypt the
+    /// stored state; everything is then re-encrypted under `new` with a fresh salt.
+    pub fn rotate_passphrase(&mut self, old: &str, new: Secret) -> Result<(), Box<dyn std::error::Error>> {
+        if new.is_empty() {
+            return Err("Passphrase must not be empty".into());
+        }
+        self.rekey_store(|crypto| Ok(crypto.rotate_passphrase(old, new)?))?;
+        
+        self.add_notification("Passphrase changed; saved state re-encrypted", "info");
+        Ok(())
+    }
+    
+    /// Apply `change` to the crypto settings (it returns the settings to read existing
+    /// data with) and have the store and the recordings rewrite everything. On failure
+    /// nothing changes.
+    fn rekey_store<F>(&mut self, change: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&mut CryptoManager) -> Result<CryptoManager, Box<dyn std::error::Error>>,
+    {
+        // Store before crypto, the same order a save takes them in
+        let mut store = self.store.lock().unwrap();
+        self.store_writes.fetch_add(1, Ordering::SeqCst);
+        let previous = change(&mut self.crypto.lock().unwrap())?;
+        let previous: SharedCrypto = Arc::new(std::sync::Mutex::new(previous));
+        let restore = |crypto: &SharedCrypto| {
+            std::mem::swap(&mut *crypto.lock().unwrap(), &mut *previous.lock().unwrap());
+        };
+        
+        // The store rewraps its copy of the payload key; ours has to follow, or saves
+        // after the rotation would put the old wrapping back
+        let wrapped = match &self.payload_key {
+            Some(key) => match key.wrap(&mut self.crypto.lock().unwrap()) {
+                Ok(wrapped) => Some(wrapped),
+                Err(e) => {
+                    restore(&self.crypto);
+                    return Err(format!("Failed to rewrap the payload key: {}", e).into());
+                }
+            },
+            None => None,
+        };
+        
+        // Recordings are staged first and follow the store's commit, so a crash
+        // leaves either all old or all new settings; a recording that can't be read
+        // keeps its old settings
+        let skipped = self.recordings.rekey(&previous, || {
+            store.rekey(previous.clone()).map_err(Box::<dyn std::error::Error>::from)
+        });
+        let skipped = match skipped {
+            Ok(skipped) => skipped,
+            Err(e) => {
+                restore(&self.crypto);
+                return Err(e);
+            }
         };
+        drop(store);
         
-        std::fs::write(&self.state_file, file_content)?;
+        if wrapped.is_some() {
+            self.wrapped_payload_key = wrapped;
+        }
+        self.unlock_payloads();
+        if !skipped.is_empty() {
+            eprintln!("Failed to re-encrypt recordings: {}", skipped.join("; "));
+            self.add_notification(
+                &format!("{} terminal recording(s) could not be re-encrypted", skipped.len()),
+                "warning"
+            );
+        }
         Ok(())
     }
     
+    /// Terminal recordings in the app data directory
+    pub fn recordings(&self) -> Recordings {
+        self.recordings.clone()
+    }
+    
+    /// Where new terminal sessions are recorded, while recording is on
+    pub fn recording_target(&self) -> Option<Recordings> {
+        self.config.terminal_record_sessions.then(|| self.recordings())
+    }
+    
+    /// Operation history from the store, newest first
+    pub fn query_operations(&self, query: &HistoryQuery) -> Result<Vec<PendingOperation>, String> {
+        let mut operations = self.store.lock().unwrap()
+            .query_operations(query)
+            .map_err(|e| e.to_string())?;
+        // Locked payloads come back sealed; their metadata is still there to list and filter
+        if let Some(key) = &self.payload_key {
+            key.open_all(&mut operations);
+        }
+        Ok(operations)
+    }
+    
+    /// Hand state writes to the background persistence task
+    pub fn attach_persistence(&mut self, handle: PersistHandle) {
+        self.persist_handle = Some(handle);
+    }
+    
+    pub fn persist_handle(&self) -> Option<PersistHandle> {
+        self.persist_handle.clone()
+    }
+    
+    /// Mark state dirty; the persistence task coalesces these into one write.
+    /// Before the task is attached (during startup) this saves synchronously.
+    pub fn request_save(&mut self) {
+        match &self.persist_handle {
+            Some(handle) => handle.mark_dirty(),
+            None => {
+                if let Err(e) = self.save_state() {
+                    eprintln!("Failed to save state: {}", e);
+                }
+            }
+        }
+    }
+    
     /// Add a new pending operation
     pub fn add_operation(&mut self, operation_type: String, payload: serde_json::Value, source: String) -> String {
         let id = Uuid::new_v4().to_string();
@@ -168,6 +687,7 @@ impl AppState {
             status: "pending".to_string(),
             timestamp: chrono::Utc::now(),
             source,
+            changeset_id: None,
         };
         
         self.pending_operations.push(operation);
@@ -178,13 +698,43 @@ impl AppState {
         }
         
         // Auto-save state
-        if let Err(e) = self.save_state() {
-            eprintln!("Failed to save state after adding operation: {}", e);
-        }
+        self.request_save();
         
         id
     }
     
+    /// Add several pending operations that belong to one changeset
+    pub fn add_changeset(&mut self, operations: Vec<(String, serde_json::Value)>, source: String) -> (String, Vec<String>) {
+        let changeset_id = Uuid::new_v4().to_string();
+        let timestamp = chrono::Utc::now();
+        let mut ids = Vec::new();
+        
+        for (operation_type, payload) in operations {
+            let operation = PendingOperation {
+                id: Uuid::new_v4().to_string(),
+                operation_type,
+                payload,
+                status: "pending".to_string(),
+                timestamp,
+                source: source.clone(),
+                changeset_id: Some(changeset_id.clone()),
+            };
+            ids.push(operation.id.clone());
+            
+            // Emit event to frontend
+            if let Err(e) = self.app_handle.emit("operation_added", &operation) {
+                eprintln!("Failed to emit operation_added event: {}", e);
+            }
+            
+            self.pending_operations.push(operation);
+        }
+        
+        // Save once for the whole changeset
+        self.request_save();
+        
+        (changeset_id, ids)
+    }
+    
     /// Update operation status
     pub fn update_operation_status(&mut self, id: &str, status: String) -> Result<(), String> {
         if let Some(op) = self.pending_operations.iter_mut().find(|o| o.id == id) {
@@ -196,9 +746,7 @@ impl AppState {
             }
             
             // Auto-save state
-            if let Err(e) = self.save_state() {
-                eprintln!("Failed to save state after updating operation: {}", e);
-            }
+            self.request_save();
             
             Ok(())
         } else {
@@ -213,22 +761,85 @@ impl AppState {
         
         if self.pending_operations.len() != initial_len {
             // Auto-save state
-            if let Err(e) = self.save_state() {
-                eprintln!("Failed to save state after cleaning operations: {}", e);
-            }
+            self.request_save();
         }
     }
     
     /// Add notification
     pub fn add_notification(&mut self, message: &str, level: &str) {
-        let notification = NotificationEvent {
+        self.add_notification_from(message, level, "sidecar", None);
+    }
+    
+    /// Add notification about a specific operation, grouped with earlier ones for it
+    pub fn add_operation_notification(&mut self, operation_id: &str, message: &str, level: &str, source: &str) {
+        self.add_notification_from(message, level, source, Some(operation_id));
+    }
+    
+    /// Add notification, collapsing duplicates and rate-limiting per origin
+    pub fn add_notification_from(&mut self, message: &str, level: &str, source: &str, operation_id: Option<&str>) {
+        let now = chrono::Utc::now();
+        
+        match self.notification_throttle.check_rate(source, now) {
+            RateDecision::Allow => {}
+            RateDecision::Drop { first } => {
+                if first {
+                    // Bypasses the limiter so the user knows messages are being dropped
+                    self.push_notification(NotificationEvent {
+                        id: Uuid::new_v4().to_string(),
+                        message: format!(
+                            "Too many notifications from {}; suppressing for {}s",
+                            notifications::rate_origin(source), notifications::RATE_LIMIT_WINDOW_SECS
+                        ),
+                        level: "warning".to_string(),
+                        timestamp: now,
+                        dismissed: false,
+                        source: "sidecar".to_string(),
+                        operation_id: None,
+                        count: 1,
+                        actions: Vec::new(),
+                    });
+                }
+                return;
+            }
+        }
+        
+        let actions = operation_id
+            .and_then(|id| self.pending_operations.iter().find(|op| op.id == id))
+            .map(notifications::actions_for)
+            .unwrap_or_default();
+        
+        if let Some(index) = notifications::find_merge_target(&self.notifications, message, level, operation_id, now) {
+            let existing = &mut self.notifications[index];
+            existing.message = message.to_string();
+            existing.level = level.to_string();
+            existing.timestamp = now;
+            existing.count += 1;
+            existing.actions = actions;
+            
+            // Emit event to frontend
+            if let Err(e) = self.app_handle.emit("notification_updated", &self.notifications[index]) {
+                eprintln!("Failed to emit notification_updated event: {}", e);
+            }
+            
+            // Auto-save state
+            self.request_save();
+            return;
+        }
+        
+        self.push_notification(NotificationEvent {
             id: Uuid::new_v4().to_string(),
             message: message.to_string(),
             level: level.to_string(),
-            timestamp: chrono::Utc::now(),
+            timestamp: now,
             dismissed: false,
-        };
-        
+            source: source.to_string(),
+            operation_id: operation_id.map(str::to_string),
+            count: 1,
+            actions,
+        });
+    }
+    
+    fn push_notification(&mut self, notification: NotificationEvent) {
         self.notifications.push(notification);
         
         // Emit event to frontend
@@ -236,23 +847,35 @@ impl AppState {
             eprintln!("Failed to emit notification_added event: {}", e);
         }
         
+        notifications::evict_oldest(&mut self.notifications, self.config.max_notifications);
+        
         // Auto-save state
-        if let Err(e) = self.save_state() {
-            eprintln!("Failed to save state after adding notification: {}", e);
-        }
+        self.request_save();
     }
     
-    /// Clear dismissed notifications
-    pub fn clear_notifications(&mut self) {
-        let initial_len = self.notifications.len();
-        self.notifications.retain(|n| !n.dismissed);
+    /// Dismiss a single notification
+    pub fn dismiss_notification(&mut self, id: &str) -> Result<(), String> {
+        let notification = self.notifications.iter_mut()
+            .find(|n| n.id == id)
+            .ok_or_else(|| "Notification not found".to_string())?;
+        notification.dismissed = true;
         
-        if self.notifications.len() != initial_len {
-            // Auto-save state
-            if let Err(e) = self.save_state() {
-                eprintln!("Failed to save state after clearing notifications: {}", e);
-            }
+        // Auto-save state
+        self.request_save();
+        
+        Ok(())
+    }
+    
+    /// Remove all notifications from memory and the persisted state
+    pub fn clear_notifications(&mut self) {
+        if self.notifications.is_empty() {
+            return;
         }
+        
+        self.notifications.clear();
+        
+        // Auto-save state
+        self.request_save();
     }
 }
 
@@ -271,7 +894,90 @@ pub async fn update_app_config(
     state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
 ) -> Result<(), String> {
     let mut state = state.lock().await;
-    state.config = config;
-    state.save_state().map_err(|e| e.to_string())?;
+    // Encryption is toggled through set_encryption so the stored data follows the
+    // setting. The other fields are applied only once that and the backend switch worked.
+    if config.encryption_enabled != state.config.encryption_enabled {
+        let result = if config.encryption_enabled {
+            state.enable_encryption(None)
+        } else {
+            state.disable_encryption()
+        };
+        result.map_err(|e| e.to_string())?;
+    }
+    state.switch_backend(config.storage_backend).map_err(|e| e.to_string())?;
+    state.config = SidecarConfig {
+        // A cap of 0 would evict every notification as soon as it arrives
+        max_notifications: config.max_notifications.max(1),
+        ..config
+    };
+    if !state.config.terminal_persist_scrollback {
+        terminal::forget_scrollback(&mut state.terminal_sessions);
+    }
+    let limit = state.config.max_notifications;
+    notifications::evict_oldest(&mut state.notifications, limit);
+    state.request_save();
     Ok(())
 }
+
+#[tauri::command]
+pub async fn query_operation_history(
+    query: Option<HistoryQuery>,
+    state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
+) -> Result<Vec<PendingOperation>, String> {
+    let state = state.lock().await;
+    state.query_operations(&query.unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn reveal_operation_payload(
+    operation_id: String,
+    state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
+) -> Result<serde_json::Value, String> {
+    let state = state.lock().await;
+    state.reveal_payload(&operation_id)
+}
+
+#[tauri::command]
+pub async fn recover_state(
+    passphrase: String,
+    state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
+) -> Result<RecoveryResult, String> {
+    let mut state = state.lock().await;
+    state.recover_state(Secret::new(passphrase)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_passphrase(
+    passphrase: String,
+    state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
+) -> Result<(), String> {
+    let mut state = state.lock().await;
+    state.set_passphrase(Secret::new(passphrase)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn enable_encryption(
+    passphrase: Option<String>,
+    state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
+) -> Result<(), String> {
+    let mut state = state.lock().await;
+    state.enable_encryption(passphrase.map(Secret::new)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn disable_encryption(
+    state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
+) -> Result<(), String> {
+    let mut state = state.lock().await;
+    state.disable_encryption().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn rotate_passphrase(
+    old_passphrase: String,
+    new_passphrase: String,
+    state: tauri::State<'_, std::sync::Arc<tokio::sync::Mutex<AppState>>>
+) -> Result<(), String> {
+    let mut state = state.lock().await;
+    state.rotate_passphrase(&Secret::new(old_passphrase), Secret::new(new_passphrase)).map_err(|e| e.to_string())
+}
@@ -0,0 +1,602 @@
+// Pluggable state storage for MultiappV1 Sidecar
+// Defines the StateStore trait and the JSON-file backend; see sqlite_store.rs for SQLite
+
+use crate::crypto::{ArtifactBinding, CryptoError, CryptoManager, EncryptedData};
+use crate::payload_crypto;
+use crate::persistence;
+use crate::schema::{self, PersistedEnvelope, SchemaError, CURRENT_SCHEMA_VERSION};
+use crate::sqlite_store::{self, SqliteStore};
+use crate::state::{PendingOperation, PersistedState};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+pub const JSON_STATE_FILE: &str = "sidecar_state.json";
+pub const SQLITE_STATE_FILE: &str = "sidecar_state.db";
+
+/// Number of rotating `sidecar_state.json.N` backups kept next to the state file
+pub const STATE_BACKUP_COUNT: usize = 3;
+
+/// Artifact kinds the JSON store binds its ciphertexts to. Backups get their own
+/// kind so one copied over the live file is caught rather than silently loaded.
+pub const ARTIFACT_STATE: &str = "state";
+pub const ARTIFACT_STATE_BACKUP: &str = "state-backup";
+
+/// Unreadable state is moved to `<app_dir>/quarantine/<timestamp>/` instead of being overwritten
+pub const QUARANTINE_DIR: &str = "quarantine";
+const RECOVERED_SUFFIX: &str = "-recovered";
+
+pub type SharedCrypto = Arc<Mutex<CryptoManager>>;
+pub type SharedStore = Arc<Mutex<Box<dyn StateStore>>>;
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Encryption error: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("{0}")]
+    Schema(#[from] SchemaError),
+    #[error("State store is read-only: {0}")]
+    ReadOnly(String),
+}
+
+/// Why stored state could not be loaded, for user-facing messages
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadFailure {
+    BadPassphrase,
+    PassphraseRequired,
+    Corrupt,
+    UnknownVersion,
+    Unreadable,
+}
+
+impl StoreError {
+    pub fn load_failure(&self) -> LoadFailure {
+        match self {
+            StoreError::Crypto(CryptoError::DecryptionFailed(_)) => LoadFailure::BadPassphrase,
+            StoreError::Crypto(CryptoError::InvalidPassphrase) => LoadFailure::PassphraseRequired,
+            StoreError::Crypto(CryptoError::SerializationError(_)) => LoadFailure::Corrupt,
+            StoreError::Crypto(CryptoError::UnsupportedEnvelope(_)) => LoadFailure::UnknownVersion,
+            StoreError::Crypto(CryptoError::BindingMismatch(_)) => LoadFailure::Corrupt,
+            StoreError::Serialization(_) | StoreError::Sqlite(_) => LoadFailure::Corrupt,
+            StoreError::Schema(SchemaError::UnsupportedVersion { .. }) | StoreError::ReadOnly(_) => LoadFailure::UnknownVersion,
+            StoreError::Schema(_) => LoadFailure::Corrupt,
+            StoreError::Crypto(_) | StoreError::Io(_) => LoadFailure::Unreadable,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+    #[default]
+    Json,
+    Sqlite,
+}
+
+/// Result of loading a store; `recovered_from` is set when a fallback copy was used
+/// and `encrypted` when any of the loaded data was stored encrypted
+pub struct LoadOutcome {
+    pub state: Option<PersistedState>,
+    pub recovered_from: Option<String>,
+    pub encrypted: bool,
+}
+
+/// Filter for operation history queries. All fields are optional.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HistoryQuery {
+    pub status: Option<String>,
+    pub operation_type: Option<String>,
+    pub changeset_id: Option<String>,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl HistoryQuery {
+    pub fn matches(&self, op: &PendingOperation) -> bool {
+        self.status.as_ref().map_or(true, |s| &op.status == s)
+            && self.operation_type.as_ref().map_or(true, |t| &op.operation_type == t)
+            && self.changeset_id.as_ref().map_or(true, |c| op.changeset_id.as_ref() == Some(c))
+            && self.since.map_or(true, |since| op.timestamp >= since)
+            && self.until.map_or(true, |until| op.timestamp <= until)
+    }
+}
+
+/// Storage for the sidecar's operations, history, notifications, terminal sessions and config
+pub trait StateStore: Send {
+    fn backend(&self) -> StoreBackend;
+
+    /// Load the full state; `Ok` with `state: None` means nothing has been stored yet
+    fn load(&mut self) -> Result<LoadOutcome, StoreError>;
+
+    /// Persist a full snapshot of the state
+    fn save(&mut self, state: &PersistedState) -> Result<(), StoreError>;
+
+    /// Move this store's files into `dir` and start over empty. Used when they can't
+    /// be loaded, so the next save doesn't destroy data that may still be recoverable.
+    fn quarantine(&mut self, dir: &Path) -> Result<Vec<PathBuf>, StoreError>;
+
+    /// Rewrite everything stored: read it with `previous` and write it back with the
+    /// store's current crypto settings (used when encryption is toggled)
+    fn rekey(&mut self, previous: SharedCrypto) -> Result<(), StoreError>;
+
+    /// Set when the stored data can't be handled by this build; such a store refuses
+    /// writes so the original is left untouched for a newer version to read
+    fn read_only_reason(&self) -> Option<String> {
+        None
+    }
+
+    /// Newest-first operation history matching the query
+    fn query_operations(&mut self, query: &HistoryQuery) -> Result<Vec<PendingOperation>, StoreError> {
+        let mut operations: Vec<PendingOperation> = self.load()?
+            .state
+            .map(|state| state.pending_operations)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|op| query.matches(op))
+            .collect();
+        operations.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+
+        Ok(operations.into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+}
+
+/// Today's format: one (optionally encrypted) JSON document with rotating backups
+pub struct JsonFileStore {
+    path: PathBuf,
+    crypto: SharedCrypto,
+    read_only: Option<String>,
+}
+
+impl JsonFileStore {
+    pub fn new(path: PathBuf, crypto: SharedCrypto) -> Self {
+        Self { path, crypto, read_only: None }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Logical path ciphertexts are bound to. Only the file name, so quarantined
+    /// copies in another directory still decrypt.
+    fn logical_path(&self) -> String {
+        self.path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    fn artifact_kind(&self, path: &Path) -> &'static str {
+        if path == self.path { ARTIFACT_STATE } else { ARTIFACT_STATE_BACKUP }
+    }
+
+    /// Read and decode one state file (primary or backup). Whether it is encrypted is
+    /// decided by its contents, not by the current setting, so toggling never strands a file.
+    fn read_file(&self, path: &Path) -> Result<(PersistedState, bool), StoreError> {
+        let file_content = std::fs::read_to_string(path)?;
+        let document: serde_json::Value = serde_json::from_str(&file_content)?;
+
+        match EncryptedData::deserialize(&document) {
+            Ok(encrypted_data) => {
+                if let Some(binding) = &encrypted_data.binding {
+                    schema::check_version(binding.schema_version)?;
+                }
+                let document = self.crypto.lock().unwrap()
+                    .decrypt_json_bound(&encrypted_data, self.artifact_kind(path), &self.logical_path())?;
+                Ok((schema::decode(document)?, true))
+            }
+            Err(_) => Ok((schema::decode(document)?, false)),
+        }
+    }
+
+    /// Serialize a snapshot for the file of artifact `kind`, encrypting it if encryption is on
+    fn encode(&self, state: &PersistedState, kind: &str) -> Result<String, StoreError> {
+        let envelope = PersistedEnvelope::new(state);
+        let mut crypto = self.crypto.lock().unwrap();
+        if crypto.is_encryption_enabled() {
+            let binding = ArtifactBinding::new(kind, self.logical_path(), CURRENT_SCHEMA_VERSION);
+            let encrypted_data = crypto.encrypt_json_bound(&envelope, &binding)?;
+            Ok(serde_json::to_string_pretty(&encrypted_data)?)
+        } else {
+            Ok(serde_json::to_string_pretty(&envelope)?)
+        }
+    }
+
+    /// The outgoing state file re-sealed as a backup, or `None` if it doesn't decode:
+    /// a damaged file must not push a good backup out. Bound live state is re-sealed
+    /// as `state-backup`; other readable state is kept byte for byte.
+    fn reseal_as_backup(&self, current: Vec<u8>) -> Option<Vec<u8>> {
+        let document: serde_json::Value = match serde_json::from_slice(&current) {
+            Ok(document) => document,
+            Err(e) => {
+                eprintln!("Warning: Not keeping the previous state as a backup: {}", e);
+                return None;
+            }
+        };
+        let Ok(encrypted_data) = EncryptedData::deserialize(&document) else {
+            return match schema::decode(document) {
+                Ok(_) => Some(current),
+                Err(e) => {
+                    eprintln!("Warning: Not keeping the previous state as a backup: {}", e);
+                    None
+                }
+            };
+        };
+
+        let logical_path = self.logical_path();
+        let path = encrypted_data.binding.as_ref().map_or(&logical_path, |binding| &binding.path);
+        let mut crypto = self.crypto.lock().unwrap();
+        let plain = match crypto.decrypt_bound(&encrypted_data, ARTIFACT_STATE, path) {
+            Ok(plain) => plain,
+            // Without a passphrase it can't be checked; keep it rather than lose it
+            Err(CryptoError::InvalidPassphrase) => return Some(current),
+            Err(e) => {
+                eprintln!("Warning: Not keeping the previous state as a backup: {}", e);
+                return None;
+            }
+        };
+        let Some(binding) = &encrypted_data.binding else {
+            return Some(current);
+        };
+
+        let backup = ArtifactBinding::new(ARTIFACT_STATE_BACKUP, binding.path.clone(), binding.schema_version);
+        let resealed = crypto.encrypt_bound(&plain, &backup)
+            .and_then(|sealed| Ok(serde_json::to_vec_pretty(&sealed)?));
+        match resealed {
+            Ok(resealed) => Some(resealed),
+            Err(e) => {
+                eprintln!("Warning: Keeping previous state as an unresealed backup: {}", e);
+                Some(current)
+            }
+        }
+    }
+}
+
+impl StateStore for JsonFileStore {
+    fn backend(&self) -> StoreBackend {
+        StoreBackend::Json
+    }
+
+    /// Load the state file, falling back to the newest readable backup
+    fn load(&mut self) -> Result<LoadOutcome, StoreError> {
+        finish_rekey(&self.path)?;
+
+        if !self.path.exists() {
+            return Ok(LoadOutcome { state: None, recovered_from: None, encrypted: false });
+        }
+
+        let primary_error = match self.read_file(&self.path) {
+            Ok((state, encrypted)) => {
+                return Ok(LoadOutcome { state: Some(state), recovered_from: None, encrypted });
+            }
+            // Written by a newer build: falling back to an older backup would get it overwritten
+            Err(e) if e.load_failure() == LoadFailure::UnknownVersion => {
+                self.read_only = Some(e.to_string());
+                return Err(e);
+            }
+            Err(e) => e,
+        };
+
+        for backup in persistence::existing_backups(&self.path, STATE_BACKUP_COUNT) {
+            match self.read_file(&backup) {
+                Ok((state, encrypted)) => {
+                    eprintln!("Warning: State file unreadable ({}), restored from {}", primary_error, backup.display());
+                    return Ok(LoadOutcome {
+                        state: Some(state),
+                        recovered_from: Some(backup.display().to_string()),
+                        encrypted,
+                    });
+                }
+                Err(e) => eprintln!("Warning: Backup {} is unreadable: {}", backup.display(), e),
+            }
+        }
+
+        Err(primary_error)
+    }
+
+    fn save(&mut self, state: &PersistedState) -> Result<(), StoreError> {
+        if let Some(reason) = &self.read_only {
+            return Err(StoreError::ReadOnly(reason.clone()));
+        }
+
+        let file_content = self.encode(state, ARTIFACT_STATE)?;
+        persistence::write_with_backups_via(&self.path, file_content.as_bytes(), STATE_BACKUP_COUNT, |current| {
+            self.reseal_as_backup(current)
+        })?;
+        Ok(())
+    }
+
+    fn rekey(&mut self, previous: SharedCrypto) -> Result<(), StoreError> {
+        if let Some(reason) = &self.read_only {
+            return Err(StoreError::ReadOnly(reason.clone()));
+        }
+
+        // Decode everything first so a bad primary aborts before anything is rewritten
+        let reader = JsonFileStore::new(self.path.clone(), previous.clone());
+        let mut rewritten = Vec::new();
+        for path in json_files(&self.path).into_iter().filter(|path| path.exists()) {
+            let decoded = reader.read_file(&path).and_then(|(mut state, _)| {
+                payload_crypto::rewrap(&mut state, &previous, &self.crypto)?;
+                Ok(state)
+            });
+            match decoded {
+                Ok(state) => {
+                    let content = self.encode(&state, self.artifact_kind(&path))?;
+                    rewritten.push((path, content));
+                }
+                Err(e) if path == self.path => return Err(e),
+                Err(e) => eprintln!("Warning: Skipping unreadable backup {}: {}", path.display(), e),
+            }
+        }
+
+        // Stage every file, then commit with a marker; see finish_rekey
+        let staged = rewritten.into_iter()
+            .map(|(path, content)| {
+                let staging = rekey_staging_path(&path);
+                persistence::write_atomic(&staging, content.as_bytes())?;
+                Ok(path)
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        persistence::write_atomic(&rekey_marker_path(&self.path), serde_json::to_string(&staged)?.as_bytes())?;
+
+        finish_rekey(&self.path)
+    }
+
+    fn quarantine(&mut self, dir: &Path) -> Result<Vec<PathBuf>, StoreError> {
+        move_files(&json_files(&self.path), dir).map_err(|e| {
+            // Couldn't get the files out of the way; make sure they're not overwritten instead
+            self.read_only = Some(format!("quarantine failed: {}", e));
+            e
+        })
+    }
+
+    fn read_only_reason(&self) -> Option<String> {
+        self.read_only.clone()
+    }
+}
+
+fn rekey_staging_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rekey");
+    path.with_file_name(name)
+}
+
+fn rekey_marker_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rekey-commit");
+    path.with_file_name(name)
+}
+
+/// Complete or undo a rekey. Once the commit marker exists every staged file it
+/// lists is moved into place (also after a crash); without it staged files are
+/// discarded. Either way all files end up under a single key.
+fn finish_rekey(path: &Path) -> Result<(), StoreError> {
+    let marker = rekey_marker_path(path);
+
+    if marker.exists() {
+        let files: Vec<PathBuf> = serde_json::from_slice(&std::fs::read(&marker)?)?;
+        for file in files {
+            let staging = rekey_staging_path(&file);
+            if staging.exists() {
+                std::fs::rename(staging, &file)?;
+            }
+        }
+        persistence::sync_parent_dir(path)?;
+        std::fs::remove_file(&marker)?;
+    } else {
+        for file in json_files(path) {
+            let staging = rekey_staging_path(&file);
+            if staging.exists() {
+                std::fs::remove_file(staging)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn json_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    files.extend(persistence::existing_backups(path, STATE_BACKUP_COUNT));
+    files
+}
+
+/// Every file a backend keeps in `app_dir`
+pub fn backend_files(app_dir: &Path, backend: StoreBackend) -> Vec<PathBuf> {
+    match backend {
+        StoreBackend::Json => json_files(&app_dir.join(JSON_STATE_FILE)),
+        StoreBackend::Sqlite => sqlite_store::database_files(&app_dir.join(SQLITE_STATE_FILE)),
+    }
+}
+
+/// Move existing `files` into `dir`, keeping their names
+pub(crate) fn move_files(files: &[PathBuf], dir: &Path) -> Result<Vec<PathBuf>, StoreError> {
+    std::fs::create_dir_all(dir)?;
+    let mut moved = Vec::new();
+    for file in files.iter().filter(|file| file.exists()) {
+        let target = dir.join(file.file_name().unwrap_or_default());
+        std::fs::rename(file, &target)?;
+        moved.push(target);
+    }
+    Ok(moved)
+}
+
+/// Fresh timestamped directory for files that failed to load. It is created here,
+/// so a second quarantine within the same second gets a numbered one of its own.
+pub fn new_quarantine_dir(app_dir: &Path) -> PathBuf {
+    let parent = app_dir.join(QUARANTINE_DIR);
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let _ = std::fs::create_dir_all(&parent);
+    let mut dir = parent.join(&stamp);
+    let mut n = 1;
+    // Any failure other than a taken name shows when the files are moved in
+    while matches!(std::fs::create_dir(&dir), Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists) {
+        n += 1;
+        dir = parent.join(format!("{}-{:02}", stamp, n));
+    }
+    dir
+}
+
+/// Most recent quarantine directory that hasn't been recovered yet
+pub fn latest_quarantine(app_dir: &Path) -> Option<PathBuf> {
+    let entries = std::fs::read_dir(app_dir.join(QUARANTINE_DIR)).ok()?;
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .filter(|path| !path.to_string_lossy().ends_with(RECOVERED_SUFFIX))
+        .max()
+}
+
+/// Mark a quarantine directory as recovered so it isn't offered again
+pub fn retire_quarantine(dir: &Path) -> Result<(), StoreError> {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(RECOVERED_SUFFIX);
+    std::fs::rename(dir, dir.with_file_name(name))?;
+    Ok(())
+}
+
+/// Backend whose files are present in `app_dir`. A SQLite database wins over a
+/// JSON file, since a switch to SQLite retires the JSON file only after the copy.
+pub fn detect_backend(app_dir: &Path) -> StoreBackend {
+    if app_dir.join(SQLITE_STATE_FILE).exists() {
+        StoreBackend::Sqlite
+    } else {
+        StoreBackend::Json
+    }
+}
+
+pub fn open_store(app_dir: &Path, backend: StoreBackend, crypto: SharedCrypto) -> Result<Box<dyn StateStore>, StoreError> {
+    Ok(match backend {
+        StoreBackend::Json => Box::new(JsonFileStore::new(app_dir.join(JSON_STATE_FILE), crypto)),
+        StoreBackend::Sqlite => Box::new(SqliteStore::open(&app_dir.join(SQLITE_STATE_FILE), crypto)?),
+    })
+}
+
+/// One-shot migration: copy `state` into a freshly opened `target` backend and
+/// retire the old backend's file as `<name>.migrated` so it is not picked up again
+pub fn migrate_store(
+    app_dir: &Path,
+    from: StoreBackend,
+    target: StoreBackend,
+    state: &PersistedState,
+    crypto: SharedCrypto,
+) -> Result<Box<dyn StateStore>, StoreError> {
+    let mut store = open_store(app_dir, target, crypto)?;
+    store.save(state)?;
+
+    let old_file = match from {
+        StoreBackend::Json => app_dir.join(JSON_STATE_FILE),
+        StoreBackend::Sqlite => app_dir.join(SQLITE_STATE_FILE),
+    };
+    if from != target && old_file.exists() {
+        let mut retired = old_file.file_name().unwrap_or_default().to_os_string();
+        retired.push(".migrated");
+        std::fs::rename(&old_file, old_file.with_file_name(retired))?;
+    }
+
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::passphrase::Secret;
+
+    fn app_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("multiapp-store-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn encrypting(passphrase: &str) -> SharedCrypto {
+        let mut crypto = CryptoManager::new(Some(Secret::new(passphrase.to_string())));
+        crypto.set_enabled(true);
+        Arc::new(Mutex::new(crypto))
+    }
+
+    fn state(shell: &str) -> PersistedState {
+        let mut state = PersistedState::default();
+        state.config.terminal_shell = shell.to_string();
+        state
+    }
+
+    fn loaded_shell(outcome: LoadOutcome) -> String {
+        outcome.state.unwrap().config.terminal_shell
+    }
+
+    #[test]
+    fn test_corrupt_state_falls_back_to_newest_readable_backup() {
+        let dir = app_dir();
+        let mut store = JsonFileStore::new(dir.join(JSON_STATE_FILE), encrypting("pass"));
+        for shell in ["/bin/first", "/bin/second", "/bin/third"] {
+            store.save(&state(shell)).unwrap();
+        }
+        let backups = persistence::existing_backups(store.path(), STATE_BACKUP_COUNT);
+        assert_eq!(backups.len(), 2);
+
+        std::fs::write(store.path(), "{damaged").unwrap();
+        let outcome = store.load().unwrap();
+        assert_eq!(outcome.recovered_from, Some(backups[0].display().to_string()));
+        assert!(outcome.encrypted);
+        assert_eq!(loaded_shell(outcome), "/bin/second");
+
+        std::fs::write(&backups[0], "{damaged").unwrap();
+        let outcome = store.load().unwrap();
+        assert_eq!(outcome.recovered_from, Some(backups[1].display().to_string()));
+        assert_eq!(loaded_shell(outcome), "/bin/first");
+
+        // With every copy damaged, the primary's error is reported
+        std::fs::write(&backups[1], "{damaged").unwrap();
+        assert_eq!(store.load().err().map(|e| e.load_failure()), Some(LoadFailure::Corrupt));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_rekey_rolls_forward_or_back() {
+        let dir = app_dir();
+        let path = dir.join(JSON_STATE_FILE);
+        let (old, new) = (encrypting("old"), encrypting("new"));
+        JsonFileStore::new(path.clone(), old.clone()).save(&state("/bin/sh")).unwrap();
+        let staged = JsonFileStore::new(path.clone(), new.clone()).encode(&state("/bin/sh"), ARTIFACT_STATE).unwrap();
+
+        // Stopped before the commit marker: the staged file is dropped
+        std::fs::write(rekey_staging_path(&path), &staged).unwrap();
+        assert_eq!(loaded_shell(JsonFileStore::new(path.clone(), old.clone()).load().unwrap()), "/bin/sh");
+        assert!(!rekey_staging_path(&path).exists());
+
+        // Stopped after it: the staged file is moved into place
+        std::fs::write(rekey_staging_path(&path), &staged).unwrap();
+        std::fs::write(rekey_marker_path(&path), serde_json::to_string(&[&path]).unwrap()).unwrap();
+        assert_eq!(loaded_shell(JsonFileStore::new(path.clone(), new).load().unwrap()), "/bin/sh");
+        assert!(!rekey_staging_path(&path).exists() && !rekey_marker_path(&path).exists());
+        assert!(JsonFileStore::new(path, old).load().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_quarantines_never_share_a_directory() {
+        let dir = app_dir();
+        let files = backend_files(&dir, StoreBackend::Json);
+        std::fs::write(&files[0], "first").unwrap();
+        let first = new_quarantine_dir(&dir);
+        move_files(&files, &first).unwrap();
+        std::fs::write(&files[0], "second").unwrap();
+        let second = new_quarantine_dir(&dir);
+        move_files(&files, &second).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read_to_string(first.join(JSON_STATE_FILE)).unwrap(), "first");
+        assert_eq!(latest_quarantine(&dir), Some(second.clone()));
+        retire_quarantine(&second).unwrap();
+        assert_eq!(latest_quarantine(&dir), Some(first));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,210 +1,824 @@
 // Terminal emulator integration for MultiappV1 Sidecar
-// Provides embedded terminal functionality
+// Runs shells on real PTYs and streams their output to the frontend
 
+use crate::passphrase;
+use crate::recording::{self, Recorder, Recordings, SharedRecorder};
+use crate::shell_integration::{self, CommandLog, CommandRecord};
 use crate::state::AppState;
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use crate::terminal_output::{self, Frame, OutputPipeline, ScrollbackChunk};
+use portable_pty::{native_pty_system, Child, ChildKiller, CommandBuilder, MasterPty, PtySize};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
-use tauri::State;
+use tauri::{AppHandle, Emitter, Manager, State};
 use tokio::sync::{mpsc, Mutex};
 use uuid::Uuid;
 
+pub const OUTPUT_EVENT: &str = "terminal_output";
+pub const EXIT_EVENT: &str = "terminal_exit";
+pub const CREATED_EVENT: &str = "terminal_created";
+
+pub const SESSION_NOT_FOUND: &str = "Terminal session not found";
+pub const EXEC_BUSY: &str = "A command is already running in this terminal session";
+
+const READ_BUFFER_SIZE: usize = 8192;
+
+/// Output saved per session at shutdown when `terminal_persist_scrollback` is on
+const PERSISTED_SCROLLBACK_BYTES: usize = 64 * 1024;
+
+/// Records of sessions no longer running that are kept; older ones are dropped
+const KEPT_SESSION_RECORDS: usize = 20;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TerminalSession {
     pub id: String,
     pub shell: String,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>, // set on top of the sidecar's environment
     pub rows: u16,
     pub cols: u16,
     pub active: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub exit_code: Option<u32>,
+    #[serde(default)]
+    pub shell_integration: bool, // the shell marks its commands; see get_terminal_commands
+    #[serde(default)]
+    pub recorded: bool, // an asciicast recording named after the session id is being written
+    #[serde(default)]
+    pub interrupted: bool, // still running when the sidecar stopped; restore_terminal can respawn it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scrollback: Option<String>,
+}
+
+/// How to start a shell. restore_terminal takes these from the old session's record.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    pub shell: String,
+    pub cwd: Option<String>, // defaults to $HOME
+    pub env: HashMap<String, String>,
+    pub rows: u16,
+    pub cols: u16,
+    pub shell_integration: bool, // only bash and zsh get it
+    pub recording: Option<Recordings>, // where to record the session, if at all
+}
+
+impl SessionOptions {
+    pub fn new(shell: &str, rows: u16, cols: u16) -> Self {
+        Self {
+            shell: shell.to_string(),
+            cwd: None,
+            env: HashMap::new(),
+            rows,
+            cols,
+            shell_integration: true,
+            recording: None,
+        }
+    }
+
+    fn from_record(session: &TerminalSession) -> Self {
+        Self {
+            shell: session.shell.clone(),
+            cwd: session.cwd.clone(),
+            env: session.env.clone(),
+            rows: session.rows,
+            cols: session.cols,
+            shell_integration: session.shell_integration,
+            recording: None,
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// One coalesced frame of output. The frontend acknowledges `seq` once written.
+#[derive(Debug, Serialize, Clone)]
 pub struct TerminalOutput {
     pub session_id: String,
+    pub seq: u64,
+    pub offset: u64,
     pub data: String,
+    pub resync: bool, // reset the screen first; output was skipped while the frontend lagged
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl TerminalOutput {
+    fn from_frame(session_id: &str, frame: Frame) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            seq: frame.seq,
+            offset: frame.offset,
+            data: frame.data,
+            resync: frame.resync,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TerminalScrollback {
+    pub session_id: String,
+    pub offset: u64,      // later than the requested offset if older output was dropped
+    pub data: String,
+    pub next_offset: u64, // frames from here on continue the history
+}
+
+impl TerminalScrollback {
+    fn from_chunk(session_id: &str, chunk: ScrollbackChunk) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            offset: chunk.offset,
+            data: chunk.data,
+            next_offset: chunk.next_offset,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TerminalExit {
+    pub session_id: String,
+    pub exit_code: Option<u32>, // None when the exit status couldn't be collected
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+/// What a tap on a session sees: raw output, and Ctrl-C written to the shell
+#[derive(Debug, Clone)]
+pub enum TapEvent {
+    Output(Vec<u8>),
+    Interrupt,
+}
+
+type Taps = Arc<std::sync::Mutex<Vec<mpsc::UnboundedSender<TapEvent>>>>;
+
+fn notify_taps(taps: &Taps, event: TapEvent) {
+    taps.lock().unwrap().retain(|tap| tap.send(event.clone()).is_ok());
+}
+
+/// Marks a session's exec as finished when dropped
+pub struct ExecGuard(Arc<AtomicBool>);
+
+impl Drop for ExecGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Receives what a session's reader thread produces
+pub trait TerminalSink: Send + Sync + 'static {
+    fn output(&self, output: TerminalOutput);
+    fn exited(&self, exit: TerminalExit);
+
+    /// A session was started outside the frontend (the HTTP API)
+    fn created(&self, _session: &TerminalSession) {}
+}
+
+/// Handles of one running shell. The child itself lives on the reader thread,
+/// which waits for it once the PTY reaches end of file.
+struct PtySession {
+    info: TerminalSession,
+    master: Box<dyn MasterPty + Send>,
+    writer: Arc<std::sync::Mutex<Box<dyn Write + Send>>>,
+    killer: Box<dyn ChildKiller + Send + Sync>,
+    output: Arc<OutputPipeline>,
+    taps: Taps,
+    exec_running: Arc<AtomicBool>,
+    commands: Option<Arc<std::sync::Mutex<CommandLog>>>, // with shell integration only
+    recording: Option<SharedRecorder>,
+}
+
+fn with_recorder(recording: &Option<SharedRecorder>, record: impl FnOnce(&mut Recorder)) {
+    if let Some(recording) = recording {
+        if let Some(recorder) = recording.lock().unwrap().as_mut() {
+            record(recorder);
+        }
+    }
+}
+
+fn finish_recording(session_id: &str, recording: &SharedRecorder) {
+    if let Err(e) = recording::finish_recorder(recording) {
+        eprintln!("Failed to finish recording of terminal {}: {}", session_id, e);
+    }
+}
+
+/// Undo a session whose threads couldn't start: stop and reap the shell, close its recording
+fn abandon_session(session_id: &str, mut child: Box<dyn Child + Send + Sync>, recording: &Option<SharedRecorder>) {
+    if let Err(e) = child.kill() {
+        eprintln!("Failed to stop terminal {}: {}", session_id, e);
+    }
+    let _ = child.wait();
+    if let Some(recording) = recording {
+        finish_recording(session_id, recording);
+    }
+}
+
+/// Commands run in a session, oldest first; a running one comes last
+#[derive(Debug, Serialize, Clone)]
+pub struct TerminalCommands {
+    pub session_id: String,
+    pub shell_integration: bool, // without it there are no records
+    pub commands: Vec<CommandRecord>,
+}
+
+/// Running PTY sessions. Clones share the same sessions.
+#[derive(Clone, Default)]
 pub struct TerminalManager {
-    sessions: HashMap<String, TerminalSession>,
-    // In a full implementation, we'd store PTY handles here
+    sessions: Arc<std::sync::Mutex<HashMap<String, PtySession>>>,
+    integration_dir: Option<Arc<PathBuf>>,
 }
 
 impl TerminalManager {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A manager that starts bash and zsh with the scripts installed in `dir`
+    pub fn with_shell_integration(dir: PathBuf) -> Self {
         Self {
-            sessions: HashMap::new(),
+            integration_dir: Some(Arc::new(dir)),
+            ..Self::default()
         }
     }
 
-    pub fn create_session(&mut self, shell: String, rows: u16, cols: u16) -> Result<String, String> {
-        let session_id = Uuid::new_v4().to_string();
-        
-        let session = TerminalSession {
-            id: session_id.clone(),
-            shell: shell.clone(),
+    /// Spawn a shell on a new PTY and start streaming its output to `sink`
+    pub fn create_session(&self, options: &SessionOptions, sink: Arc<dyn TerminalSink>) -> Result<TerminalSession, String> {
+        let (shell, rows, cols) = (options.shell.as_str(), options.rows, options.cols);
+        if rows == 0 || cols == 0 {
+            return Err(format!("Invalid terminal size {}x{}", cols, rows));
+        }
+
+        let pty_pair = native_pty_system()
+            .openpty(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| format!("Failed to create PTY: {}", e))?;
+
+        // A directory that has since disappeared falls back to $HOME
+        let cwd = options.cwd.clone()
+            .filter(|dir| std::path::Path::new(dir).is_dir())
+            .or_else(|| std::env::var("HOME").ok());
+
+        // zsh finds its startup files through ZDOTDIR, so the integration moves the user's aside
+        let integration = self.integration_dir.as_deref()
+            .filter(|_| options.shell_integration)
+            .map(|dir| {
+                let user_zdotdir = options.env.get("ZDOTDIR").cloned().or_else(|| std::env::var("ZDOTDIR").ok());
+                shell_integration::launch(shell, dir, user_zdotdir)
+            })
+            .filter(|(args, env)| !args.is_empty() || !env.is_empty());
+
+        let mut cmd = CommandBuilder::new(shell);
+        cmd.env("TERM", "xterm-256color");
+        if let Some(cwd) = &cwd {
+            cmd.cwd(cwd);
+        }
+        for (key, value) in &options.env {
+            cmd.env(key, value);
+        }
+        if let Some((args, env)) = &integration {
+            cmd.args(args);
+            for (key, value) in env {
+                cmd.env(key, value);
+            }
+        }
+        // Shells must not inherit passphrases, not even through an override
+        for var in passphrase::SENSITIVE_ENV_VARS {
+            cmd.env_remove(var);
+        }
+        let child = pty_pair
+            .slave
+            .spawn_command(cmd)
+            .map_err(|e| format!("Failed to spawn shell {}: {}", shell, e))?;
+        // Only the child may hold the slave side, or the reader never sees end of file
+        drop(pty_pair.slave);
+
+        let reader = pty_pair.master.try_clone_reader()
+            .map_err(|e| format!("Failed to read from PTY: {}", e))?;
+        let writer = pty_pair.master.take_writer()
+            .map_err(|e| format!("Failed to write to PTY: {}", e))?;
+
+        let id = Uuid::new_v4().to_string();
+        // A recording that can't be started doesn't keep the shell from starting
+        let recording = options.recording.as_ref()
+            .and_then(|recordings| recordings.start(&id, shell, cols, rows)
+                .map_err(|e| eprintln!("Failed to record terminal {}: {}", id, e))
+                .ok());
+
+        let info = TerminalSession {
+            id,
+            shell: shell.to_string(),
+            cwd,
+            env: options.env.clone(),
             rows,
             cols,
             active: true,
             created_at: chrono::Utc::now(),
+            exit_code: None,
+            shell_integration: integration.is_some(),
+            recorded: recording.is_some(),
+            interrupted: false,
+            scrollback: None,
+        };
+        let output = Arc::new(OutputPipeline::new());
+        let taps = Taps::default();
+        let commands = integration.map(|_| Arc::new(std::sync::Mutex::new(CommandLog::new())));
+        let session = PtySession {
+            info: info.clone(),
+            master: pty_pair.master,
+            writer: Arc::new(std::sync::Mutex::new(writer)),
+            killer: child.clone_killer(),
+            output: output.clone(),
+            taps: taps.clone(),
+            exec_running: Arc::new(AtomicBool::new(false)),
+            commands: commands.clone(),
+            recording: recording.clone(),
+        };
+
+        let (frames, frame_sink, session_id) = (output.clone(), sink.clone(), info.id.clone());
+        let flusher = match thread::Builder::new()
+            .name(format!("pty-frames-{}", info.id))
+            .spawn(move || frames.run(|frame| frame_sink.output(TerminalOutput::from_frame(&session_id, frame))))
+        {
+            Ok(flusher) => flusher,
+            Err(e) => {
+                abandon_session(&info.id, child, &recording);
+                return Err(format!("Failed to start PTY output: {}", e));
+            }
         };
-        
-        // In a full implementation, we would:
-        // 1. Create a PTY pair
-        // 2. Spawn the shell process
-        // 3. Set up async readers/writers
-        // 4. Store PTY handles for communication
-        
-        self.sessions.insert(session_id.clone(), session);
-        
-        Ok(session_id)
+
+        // The reader waits until the session is registered, so its exit always finds the entry
+        let (start_tx, start_rx) = std::sync::mpsc::channel();
+        let manager = self.clone();
+        let session_id = info.id.clone();
+        let (reader_output, reader_recording) = (output.clone(), recording.clone());
+        let spawned = thread::Builder::new()
+            .name(format!("pty-{}", session_id))
+            .spawn(move || {
+                if let Ok((child, flusher)) = start_rx.recv() {
+                    manager.pump_output(session_id, reader, reader_output, taps, commands, reader_recording, flusher, child, sink);
+                }
+            });
+        if let Err(e) = spawned {
+            output.close();
+            let _ = flusher.join();
+            abandon_session(&info.id, child, &recording);
+            return Err(format!("Failed to start PTY reader: {}", e));
+        }
+
+        self.sessions.lock().unwrap().insert(info.id.clone(), session);
+        // Only fails if the reader is gone, and then it has nothing left to clean up
+        let _ = start_tx.send((child, flusher));
+
+        Ok(info)
+    }
+
+    pub fn get_session(&self, session_id: &str) -> Option<TerminalSession> {
+        self.sessions.lock().unwrap().get(session_id).map(|s| s.info.clone())
+    }
+
+    /// Running sessions, oldest first
+    pub fn list_sessions(&self) -> Vec<TerminalSession> {
+        let mut sessions: Vec<TerminalSession> = self.sessions.lock().unwrap()
+            .values()
+            .map(|s| s.info.clone())
+            .collect();
+        sessions.sort_by_key(|s| s.created_at);
+        sessions
+    }
+
+    /// Send input to the shell. The session map isn't held while writing, so a
+    /// shell that stops reading its input only blocks writes to itself.
+    pub fn write_to_session(&self, session_id: &str, data: &[u8]) -> Result<(), String> {
+        let (writer, taps) = self.sessions.lock().unwrap()
+            .get(session_id)
+            .map(|s| (s.writer.clone(), s.taps.clone()))
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        if data.contains(&0x03) {
+            notify_taps(&taps, TapEvent::Interrupt);
+        }
+
+        let mut writer = writer.lock().unwrap();
+        writer.write_all(data)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("Failed to write to terminal: {}", e))
+    }
+
+    /// Resize the PTY; the kernel delivers SIGWINCH to the shell's foreground job
+    pub fn resize_session(&self, session_id: &str, rows: u16, cols: u16) -> Result<TerminalSession, String> {
+        if rows == 0 || cols == 0 {
+            return Err(format!("Invalid terminal size {}x{}", cols, rows));
+        }
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(session_id)
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        session.master
+            .resize(PtySize { rows, cols, pixel_width: 0, pixel_height: 0 })
+            .map_err(|e| format!("Failed to resize terminal: {}", e))?;
+        session.info.rows = rows;
+        session.info.cols = cols;
+        with_recorder(&session.recording, |recorder| recorder.resize(cols, rows));
+        Ok(session.info.clone())
     }
 
-    pub fn get_session(&self, session_id: &str) -> Option<&TerminalSession> {
-        self.sessions.get(session_id)
+    /// The frontend has written every frame up to `seq`; see terminal_output
+    pub fn ack_output(&self, session_id: &str, seq: u64) -> Result<(), String> {
+        let output = self.sessions.lock().unwrap()
+            .get(session_id)
+            .map(|s| s.output.clone())
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        output.ack(seq);
+        Ok(())
     }
 
-    pub fn list_sessions(&self) -> Vec<&TerminalSession> {
-        self.sessions.values().collect()
+    /// Output kept since `from_offset`, for a frontend reattaching to the session
+    pub fn scrollback(&self, session_id: &str, from_offset: u64) -> Result<TerminalScrollback, String> {
+        let output = self.sessions.lock().unwrap()
+            .get(session_id)
+            .map(|s| s.output.clone())
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        Ok(TerminalScrollback::from_chunk(session_id, output.replay(from_offset)))
     }
 
-    pub fn kill_session(&mut self, session_id: &str) -> Result<(), String> {
-        if let Some(session) = self.sessions.get_mut(session_id) {
-            session.active = false;
-            // In a full implementation, we would:
-            // 1. Send SIGTERM/SIGKILL to the shell process
-            // 2. Close PTY file descriptors
-            // 3. Clean up any async tasks
-            Ok(())
-        } else {
-            Err("Session not found".to_string())
+    /// Raw output of the session from now on, until the receiver is dropped or the shell exits
+    pub fn tap(&self, session_id: &str) -> Result<mpsc::UnboundedReceiver<TapEvent>, String> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.sessions.lock().unwrap()
+            .get(session_id)
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?
+            .taps.lock().unwrap()
+            .push(tx);
+        Ok(rx)
+    }
+
+    /// Claim the session for an exec; fails while another exec runs in it
+    pub fn begin_exec(&self, session_id: &str) -> Result<ExecGuard, String> {
+        let running = self.sessions.lock().unwrap()
+            .get(session_id)
+            .map(|s| s.exec_running.clone())
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        if running.swap(true, Ordering::SeqCst) {
+            return Err(EXEC_BUSY.to_string());
         }
+        Ok(ExecGuard(running))
     }
 
-    pub fn write_to_session(&self, session_id: &str, data: &str) -> Result<(), String> {
-        if let Some(session) = self.sessions.get(session_id) {
-            if !session.active {
-                return Err("Session is not active".to_string());
+    /// The last `limit` commands run in the session, from its shell integration
+    pub fn commands(&self, session_id: &str, limit: usize) -> Result<TerminalCommands, String> {
+        let commands = self.sessions.lock().unwrap()
+            .get(session_id)
+            .map(|s| s.commands.clone())
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        Ok(TerminalCommands {
+            session_id: session_id.to_string(),
+            shell_integration: commands.is_some(),
+            commands: commands.map_or_else(Vec::new, |log| log.lock().unwrap().recent(limit)),
+        })
+    }
+
+    /// Record the session's next command as `command` (exec's own input line is a
+    /// wrapper) and mark where it starts in the session's recording
+    pub fn label_next_command(&self, session_id: &str, command: &str) -> Result<(), String> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(session_id)
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        if let Some(commands) = &session.commands {
+            commands.lock().unwrap().label_next(command);
+        }
+        with_recorder(&session.recording, |recorder| recorder.marker(command));
+        Ok(())
+    }
+
+    /// Hang up on the shell and close the PTY. The reader thread reports the exit.
+    pub fn kill_session(&self, session_id: &str) -> Result<(), String> {
+        let mut session = self.sessions.lock().unwrap()
+            .remove(session_id)
+            .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+        session.killer.kill()
+            .map_err(|e| format!("Failed to kill terminal: {}", e))
+    }
+
+    pub fn kill_all(&self) {
+        let sessions: Vec<PtySession> = self.sessions.lock().unwrap().drain().map(|(_, s)| s).collect();
+        for mut session in sessions {
+            if let Err(e) = session.killer.kill() {
+                eprintln!("Failed to kill terminal {}: {}", session.info.id, e);
+            }
+            // The process is about to exit, so don't wait for the reader thread to do it
+            if let Some(recording) = &session.recording {
+                finish_recording(&session.info.id, recording);
             }
-            
-            // In a full implementation, we would write to the PTY master
-            // For now, we just simulate the write
-            println!("Terminal {}: Writing '{}' to shell", session_id, data);
-            Ok(())
-        } else {
-            Err("Session not found".to_string())
         }
     }
 
-    pub fn resize_session(&mut self, session_id: &str, rows: u16, cols: u16) -> Result<(), String> {
-        if let Some(session) = self.sessions.get_mut(session_id) {
-            if !session.active {
-                return Err("Session is not active".to_string());
+    /// Reader thread body: feed output to the pipeline until the PTY closes, let the
+    /// last frames go out, then reap the shell
+    #[allow(clippy::too_many_arguments)]
+    fn pump_output(
+        &self,
+        session_id: String,
+        mut reader: Box<dyn Read + Send>,
+        output: Arc<OutputPipeline>,
+        taps: Taps,
+        commands: Option<Arc<std::sync::Mutex<CommandLog>>>,
+        recording: Option<SharedRecorder>,
+        flusher: thread::JoinHandle<()>,
+        mut child: Box<dyn Child + Send + Sync>,
+        sink: Arc<dyn TerminalSink>,
+    ) {
+        let mut buffer = [0u8; READ_BUFFER_SIZE];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    output.push(&buffer[..n]);
+                    if let Some(commands) = &commands {
+                        commands.lock().unwrap().feed(&buffer[..n]);
+                    }
+                    with_recorder(&recording, |recorder| recorder.output(&buffer[..n]));
+                    notify_taps(&taps, TapEvent::Output(buffer[..n].to_vec()));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break, // Linux reports a hung-up PTY as EIO
+            }
+        }
+        output.close();
+        taps.lock().unwrap().clear(); // ends every tap
+        if let Some(recording) = &recording {
+            finish_recording(&session_id, recording);
+        }
+        if flusher.join().is_err() {
+            eprintln!("Terminal {} output thread panicked", session_id);
+        }
+
+        let exit_code = match child.wait() {
+            Ok(status) => Some(status.exit_code()),
+            Err(e) => {
+                eprintln!("Failed to reap terminal {}: {}", session_id, e);
+                None
             }
-            
-            session.rows = rows;
-            session.cols = cols;
-            
-            // In a full implementation, we would:
-            // 1. Call ioctl to resize the PTY
-            // 2. Send SIGWINCH to the shell process
-            
-            Ok(())
-        } else {
-            Err("Session not found".to_string())
+        };
+        self.sessions.lock().unwrap().remove(&session_id);
+        sink.exited(TerminalExit {
+            session_id,
+            exit_code,
+            timestamp: chrono::Utc::now(),
+        });
+    }
+}
+
+/// Persisted metadata for a session, so the history survives restarts
+fn record_session(app_state: &mut AppState, session: &TerminalSession) {
+    match serde_json::to_value(session) {
+        Ok(value) => {
+            app_state.terminal_sessions.insert(session.id.clone(), value);
+            app_state.request_save();
+        }
+        Err(e) => eprintln!("Failed to serialize terminal session: {}", e),
+    }
+}
+
+fn load_record(app_state: &AppState, session_id: &str) -> Option<TerminalSession> {
+    app_state.terminal_sessions.get(session_id)
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+}
+
+fn mark_exited(app_state: &mut AppState, session_id: &str, exit_code: Option<u32>) {
+    let Some(session) = load_record(app_state, session_id) else {
+        return;
+    };
+    let session = TerminalSession {
+        active: false,
+        exit_code: exit_code.or(session.exit_code),
+        ..session
+    };
+    record_session(app_state, &session);
+    prune_sessions(&mut app_state.terminal_sessions);
+}
+
+/// Drop the oldest records of sessions that no longer run, beyond
+/// KEPT_SESSION_RECORDS; returns how many were dropped
+fn prune_sessions(sessions: &mut HashMap<String, serde_json::Value>) -> usize {
+    let mut stopped: Vec<(chrono::DateTime<chrono::Utc>, String)> = sessions.iter()
+        .filter_map(|(id, value)| serde_json::from_value::<TerminalSession>(value.clone()).ok()
+            .filter(|session| !session.active)
+            .map(|session| (session.created_at, id.clone())))
+        .collect();
+    let excess = stopped.len().saturating_sub(KEPT_SESSION_RECORDS);
+    stopped.sort();
+    for (_, id) in stopped.into_iter().take(excess) {
+        sessions.remove(&id);
+    }
+    excess
+}
+
+/// Mark records of sessions that were running when the sidecar last stopped as
+/// interrupted and prune old records; returns how many changed
+pub fn reconcile_sessions(sessions: &mut HashMap<String, serde_json::Value>) -> usize {
+    let mut changed = 0;
+    for value in sessions.values_mut() {
+        let Ok(session) = serde_json::from_value::<TerminalSession>(value.clone()) else {
+            continue;
+        };
+        if !session.active {
+            continue;
+        }
+        let session = TerminalSession { active: false, interrupted: true, ..session };
+        if let Ok(updated) = serde_json::to_value(&session) {
+            *value = updated;
+            changed += 1;
+        }
+    }
+    changed + prune_sessions(sessions)
+}
+
+/// Drop saved output from every record, once scrollback persistence is turned off
+pub fn forget_scrollback(sessions: &mut HashMap<String, serde_json::Value>) {
+    for value in sessions.values_mut() {
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("scrollback");
+        }
+    }
+}
+
+/// Called before the shells are hung up at shutdown: flag them as interrupted and,
+/// if enabled, keep their recent output for restore_terminal
+pub fn record_shutdown(app_state: &mut AppState, terminals: &TerminalManager) {
+    let keep_scrollback = app_state.config.terminal_persist_scrollback;
+    for running in terminals.list_sessions() {
+        let scrollback = keep_scrollback
+            .then(|| terminals.scrollback(&running.id, 0).ok())
+            .flatten()
+            .map(|chunk| {
+                let mut bytes = chunk.data.into_bytes();
+                terminal_output::trim_tail(&mut bytes, PERSISTED_SCROLLBACK_BYTES);
+                String::from_utf8_lossy(&bytes).into_owned()
+            });
+        let session = load_record(app_state, &running.id).unwrap_or(running);
+        record_session(app_state, &TerminalSession { interrupted: true, scrollback, ..session });
+    }
+}
+
+/// Start a session on behalf of GeanyLua and let the frontend know, so it can show it
+pub fn start_shared_session(
+    app_state: &mut AppState,
+    terminals: &TerminalManager,
+    options: &SessionOptions,
+    sink: Arc<dyn TerminalSink>,
+) -> Result<TerminalSession, String> {
+    let session = terminals.create_session(options, sink.clone())?;
+    record_session(app_state, &session);
+    sink.created(&session);
+    Ok(session)
+}
+
+impl TerminalSink for AppHandle {
+    fn output(&self, output: TerminalOutput) {
+        if let Err(e) = self.emit(OUTPUT_EVENT, &output) {
+            eprintln!("Failed to emit terminal output: {}", e);
+        }
+    }
+
+    fn created(&self, session: &TerminalSession) {
+        if let Err(e) = self.emit(CREATED_EVENT, session) {
+            eprintln!("Failed to emit terminal created: {}", e);
+        }
+    }
+
+    fn exited(&self, exit: TerminalExit) {
+        // Runs on the reader thread, outside the async runtime
+        let state = self.state::<Arc<Mutex<AppState>>>();
+        mark_exited(&mut state.blocking_lock(), &exit.session_id, exit.exit_code);
+        if let Err(e) = self.emit(EXIT_EVENT, &exit) {
+            eprintln!("Failed to emit terminal exit: {}", e);
         }
     }
 }
 
 // Tauri command handlers
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn create_terminal(
     shell: Option<String>,
+    cwd: Option<String>,
+    env: Option<HashMap<String, String>>,
     rows: Option<u16>,
     cols: Option<u16>,
-    state: State<'_, Arc<Mutex<AppState>>>
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<AppState>>>,
+    terminals: State<'_, TerminalManager>
 ) -> Result<TerminalCreateResponse, String> {
-    let app_state = state.lock().await;
+    // Held until the session is recorded, so an immediate exit can't be recorded first
+    let mut app_state = state.lock().await;
     let shell = shell.unwrap_or_else(|| app_state.config.terminal_shell.clone());
-    let rows = rows.unwrap_or(24);
-    let cols = cols.unwrap_or(80);
-    
-    // For now, we'll just create a mock session since implementing a full
-    // terminal emulator is complex. In a production implementation, you'd:
-    // 1. Use portable-pty to create a real PTY
-    // 2. Spawn the shell process
-    // 3. Set up async I/O handling
-    // 4. Emit terminal output events to the frontend
-    
-    let session_id = Uuid::new_v4().to_string();
-    let session = TerminalSession {
-        id: session_id.clone(),
-        shell: shell.clone(),
-        rows,
-        cols,
-        active: true,
-        created_at: chrono::Utc::now(),
+    let options = SessionOptions {
+        cwd,
+        env: env.unwrap_or_default(),
+        shell_integration: app_state.config.terminal_shell_integration,
+        recording: app_state.recording_target(),
+        ..SessionOptions::new(&shell, rows.unwrap_or(24), cols.unwrap_or(80))
     };
+    let session = terminals.create_session(&options, Arc::new(app))?;
+    record_session(&mut app_state, &session);
+
+    Ok(TerminalCreateResponse::new(session, None))
+}
 
-    // Store session info in the terminal_sessions map
-    drop(app_state); // Release the lock
+/// Sessions interrupted by a sidecar restart, oldest first
+#[tauri::command]
+pub async fn list_restorable_terminals(
+    state: State<'_, Arc<Mutex<AppState>>>
+) -> Result<Vec<TerminalSession>, String> {
+    let app_state = state.lock().await;
+    let mut sessions: Vec<TerminalSession> = app_state.terminal_sessions.values()
+        .filter_map(|value| serde_json::from_value::<TerminalSession>(value.clone()).ok())
+        .filter(|session| session.interrupted && !session.active)
+        .map(|session| TerminalSession { scrollback: None, ..session })
+        .collect();
+    sessions.sort_by_key(|s| s.created_at);
+    Ok(sessions)
+}
+
+/// Respawn an interrupted session with its shell, cwd, environment and size. The new
+/// session gets its own id; the saved output, if any, comes back once with the response.
+#[tauri::command]
+pub async fn restore_terminal(
+    session_id: String,
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<AppState>>>,
+    terminals: State<'_, TerminalManager>
+) -> Result<TerminalCreateResponse, String> {
     let mut app_state = state.lock().await;
-    app_state.terminal_sessions.insert(
-        session_id.clone(),
-        serde_json::to_value(&session).map_err(|e| e.to_string())?
-    );
-    
-    if let Err(e) = app_state.save_state() {
-        eprintln!("Failed to save state after creating terminal: {}", e);
+    let previous = load_record(&app_state, &session_id)
+        .ok_or_else(|| SESSION_NOT_FOUND.to_string())?;
+    if previous.active {
+        return Err("Terminal session is still running".to_string());
     }
 
-    Ok(TerminalCreateResponse {
-        session_id,
-        shell,
-        rows,
-        cols,
-    })
+    let options = SessionOptions {
+        shell_integration: app_state.config.terminal_shell_integration,
+        recording: app_state.recording_target(),
+        ..SessionOptions::from_record(&previous)
+    };
+    let session = terminals.create_session(&options, Arc::new(app))?;
+    // The new session's record takes over; the old one and its output go
+    app_state.terminal_sessions.remove(&session_id);
+    record_session(&mut app_state, &session);
+
+    Ok(TerminalCreateResponse::new(session, previous.scrollback))
 }
 
 #[tauri::command]
 pub async fn write_to_terminal(
     session_id: String,
     data: String,
-    state: State<'_, Arc<Mutex<AppState>>>
+    terminals: State<'_, TerminalManager>
 ) -> Result<(), String> {
-    let app_state = state.lock().await;
-    
-    if let Some(session_data) = app_state.terminal_sessions.get(&session_id) {
-        let session: TerminalSession = serde_json::from_value(session_data.clone())
-            .map_err(|e| format!("Failed to deserialize session: {}", e))?;
-        
-        if !session.active {
-            return Err("Terminal session is not active".to_string());
-        }
-        
-        // In a real implementation, this would write to the PTY
-        println!("Terminal {}: Input: {}", session_id, data);
-        
-        // For demonstration, simulate some output
-        let output = TerminalOutput {
-            session_id: session_id.clone(),
-            data: format!("$ {}\nCommand processed\n", data),
-            timestamp: chrono::Utc::now(),
-        };
-        
-        // Emit output to frontend
-        if let Err(e) = app_state.app_handle.emit("terminal_output", &output) {
-            eprintln!("Failed to emit terminal output: {}", e);
-        }
-        
-        Ok(())
-    } else {
-        Err("Terminal session not found".to_string())
-    }
+    let terminals = terminals.inner().clone();
+    // A full PTY input buffer blocks the write, so keep it off the async workers
+    tokio::task::spawn_blocking(move || terminals.write_to_session(&session_id, data.as_bytes()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub async fn ack_terminal_output(
+    session_id: String,
+    seq: u64,
+    terminals: State<'_, TerminalManager>
+) -> Result<(), String> {
+    terminals.ack_output(&session_id, seq)
+}
+
+#[tauri::command]
+pub async fn list_terminals(terminals: State<'_, TerminalManager>) -> Result<Vec<TerminalSession>, String> {
+    Ok(terminals.list_sessions())
+}
+
+#[tauri::command]
+pub async fn get_terminal_scrollback(
+    session_id: String,
+    from_offset: u64,
+    terminals: State<'_, TerminalManager>
+) -> Result<TerminalScrollback, String> {
+    terminals.scrollback(&session_id, from_offset)
+}
+
+/// Recent commands of a session with their output and exit codes, e.g. as context for the AI
+#[tauri::command]
+pub async fn get_terminal_commands(
+    session_id: String,
+    limit: Option<usize>,
+    terminals: State<'_, TerminalManager>
+) -> Result<TerminalCommands, String> {
+    terminals.commands(&session_id, limit.unwrap_or(shell_integration::MAX_COMMAND_RECORDS))
 }
 
 #[tauri::command]
@@ -212,101 +826,280 @@ pub async fn resize_terminal(
     session_id: String,
     rows: u16,
     cols: u16,
-    state: State<'_, Arc<Mutex<AppState>>>
+    state: State<'_, Arc<Mutex<AppState>>>,
+    terminals: State<'_, TerminalManager>
 ) -> Result<(), String> {
-    let mut app_state = state.lock().await;
-    
-    if let Some(session_data) = app_state.terminal_sessions.get_mut(&session_id) {
-        let mut session: TerminalSession = serde_json::from_value(session_data.clone())
-            .map_err(|e| format!("Failed to deserialize session: {}", e))?;
-        
-        session.rows = rows;
-        session.cols = cols;
-        
-        *session_data = serde_json::to_value(&session)
-            .map_err(|e| format!("Failed to serialize session: {}", e))?;
-        
-        if let Err(e) = app_state.save_state() {
-            eprintln!("Failed to save state after resizing terminal: {}", e);
-        }
-        
-        // In a real implementation, this would resize the PTY and send SIGWINCH
-        println!("Terminal {} resized to {}x{}", session_id, cols, rows);
-        
-        Ok(())
-    } else {
-        Err("Terminal session not found".to_string())
-    }
+    let session = terminals.resize_session(&session_id, rows, cols)?;
+    record_session(&mut *state.lock().await, &session);
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn kill_terminal(
     session_id: String,
-    state: State<'_, Arc<Mutex<AppState>>>
+    state: State<'_, Arc<Mutex<AppState>>>,
+    terminals: State<'_, TerminalManager>
 ) -> Result<(), String> {
-    let mut app_state = state.lock().await;
-    
-    if let Some(session_data) = app_state.terminal_sessions.get_mut(&session_id) {
-        let mut session: TerminalSession = serde_json::from_value(session_data.clone())
-            .map_err(|e| format!("Failed to deserialize session: {}", e))?;
-        
-        session.active = false;
-        
-        *session_data = serde_json::to_value(&session)
-            .map_err(|e| format!("Failed to serialize session: {}", e))?;
-        
-        if let Err(e) = app_state.save_state() {
-            eprintln!("Failed to save state after killing terminal: {}", e);
-        }
-        
-        // In a real implementation, this would:
-        // 1. Send SIGTERM/SIGKILL to the shell process
-        // 2. Close PTY file descriptors
-        // 3. Clean up any async tasks
-        
-        println!("Terminal {} killed", session_id);
-        
-        Ok(())
-    } else {
-        Err("Terminal session not found".to_string())
-    }
+    terminals.kill_session(&session_id)?;
+    mark_exited(&mut *state.lock().await, &session_id, None);
+    Ok(())
 }
 
 #[derive(Serialize)]
 pub struct TerminalCreateResponse {
     pub session_id: String,
     pub shell: String,
+    pub cwd: Option<String>,
     pub rows: u16,
     pub cols: u16,
+    pub scrollback: Option<String>, // output of the session this one restores
 }
 
-// Example of how to implement a real terminal emulator (for reference)
-#[allow(dead_code)]
-async fn create_real_terminal_example(shell: &str, rows: u16, cols: u16) -> Result<String, String> {
-    let pty_system = native_pty_system();
-    
-    let pty_pair = pty_system
-        .openpty(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| format!("Failed to create PTY: {}", e))?;
+impl TerminalCreateResponse {
+    fn new(session: TerminalSession, scrollback: Option<String>) -> Self {
+        Self {
+            session_id: session.id,
+            shell: session.shell,
+            cwd: session.cwd,
+            rows: session.rows,
+            cols: session.cols,
+            scrollback,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    enum Event {
+        Output(String),
+        Exit(Option<u32>),
+    }
+
+    struct ChannelSink(std::sync::Mutex<mpsc::Sender<Event>>);
+
+    impl TerminalSink for ChannelSink {
+        fn output(&self, output: TerminalOutput) {
+            let _ = self.0.lock().unwrap().send(Event::Output(output.data));
+        }
+
+        fn exited(&self, exit: TerminalExit) {
+            let _ = self.0.lock().unwrap().send(Event::Exit(exit.exit_code));
+        }
+    }
+
+    fn sink() -> (Arc<dyn TerminalSink>, mpsc::Receiver<Event>) {
+        let (tx, rx) = mpsc::channel();
+        (Arc::new(ChannelSink(std::sync::Mutex::new(tx))), rx)
+    }
+
+    /// Collect output until the shell exits
+    fn run_to_exit(rx: &mpsc::Receiver<Event>) -> (String, Option<u32>) {
+        let mut output = String::new();
+        loop {
+            match rx.recv_timeout(Duration::from_secs(10)).expect("terminal did not exit") {
+                Event::Output(data) => output.push_str(&data),
+                Event::Exit(code) => return (output, code),
+            }
+        }
+    }
+
+    #[test]
+    fn test_shell_round_trip() {
+        let terminals = TerminalManager::new();
+        let (sink, rx) = sink();
+        let session = terminals.create_session(&SessionOptions::new("/bin/sh", 24, 80), sink).unwrap();
+
+        terminals.resize_session(&session.id, 40, 120).unwrap();
+        terminals.write_to_session(&session.id, b"stty size; echo marker-$((6*7)); exit 3\n").unwrap();
+
+        let (output, code) = run_to_exit(&rx);
+        assert!(output.contains("40 120"), "{}", output);
+        assert!(output.contains("marker-42"), "{}", output);
+        assert_eq!(code, Some(3));
+        assert!(terminals.get_session(&session.id).is_none());
+    }
+
+    #[test]
+    fn test_scrollback_replays_running_session() {
+        let terminals = TerminalManager::new();
+        let (sink, rx) = sink();
+        let session = terminals.create_session(&SessionOptions::new("/bin/sh", 24, 80), sink).unwrap();
+        terminals.write_to_session(&session.id, b"echo replay-$((6*7))\n").unwrap();
+
+        // Wait for the echo itself, not the input echoed back by the PTY
+        let mut output = String::new();
+        while !output.contains("replay-42") {
+            match rx.recv_timeout(Duration::from_secs(10)).expect("no output") {
+                Event::Output(data) => output.push_str(&data),
+                Event::Exit(_) => panic!("shell exited: {}", output),
+            }
+        }
+
+        let scrollback = terminals.scrollback(&session.id, 0).unwrap();
+        assert_eq!(scrollback.offset, 0);
+        assert!(scrollback.data.contains("replay-42"), "{}", scrollback.data);
+        assert_eq!(scrollback.next_offset, scrollback.data.len() as u64);
+        assert_eq!(terminals.list_sessions().len(), 1);
+
+        terminals.kill_session(&session.id).unwrap();
+        run_to_exit(&rx);
+    }
+
+    #[test]
+    fn test_session_options_are_applied() {
+        let terminals = TerminalManager::new();
+        let (sink, rx) = sink();
+        let cwd = std::env::temp_dir().canonicalize().unwrap().display().to_string();
+        let options = SessionOptions {
+            cwd: Some(cwd.clone()),
+            env: HashMap::from([
+                ("RESTORED_VAR".to_string(), "kept".to_string()),
+                (passphrase::SENSITIVE_ENV_VARS[0].to_string(), "leaked".to_string()),
+            ]),
+            ..SessionOptions::new("/bin/sh", 24, 80)
+        };
+        let session = terminals.create_session(&options, sink).unwrap();
+        assert_eq!(session.cwd.as_deref(), Some(cwd.as_str()));
+
+        let script = format!("pwd; echo var-$RESTORED_VAR; echo secret-${}; exit\n", passphrase::SENSITIVE_ENV_VARS[0]);
+        terminals.write_to_session(&session.id, script.as_bytes()).unwrap();
+        let (output, _) = run_to_exit(&rx);
+        assert!(output.lines().any(|line| line.trim_end().ends_with(cwd.as_str())), "{}", output);
+        assert!(output.contains("var-kept"), "{}", output);
+        assert!(!output.contains("secret-leaked"), "{}", output);
+    }
+
+    fn record(active: bool, created_at: chrono::DateTime<chrono::Utc>) -> serde_json::Value {
+        serde_json::to_value(TerminalSession {
+            id: "s".to_string(),
+            shell: "/bin/sh".to_string(),
+            cwd: None,
+            env: HashMap::new(),
+            rows: 24,
+            cols: 80,
+            active,
+            created_at,
+            exit_code: None,
+            shell_integration: false,
+            recorded: false,
+            interrupted: false,
+            scrollback: Some("old output".to_string()),
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_reconcile_marks_running_sessions_interrupted() {
+        let now = chrono::Utc::now();
+        let mut sessions = HashMap::from([("a".to_string(), record(true, now)), ("b".to_string(), record(false, now))]);
+
+        assert_eq!(reconcile_sessions(&mut sessions), 1);
+        assert_eq!(sessions["a"]["active"], false);
+        assert_eq!(sessions["a"]["interrupted"], true);
+        assert_eq!(sessions["b"]["interrupted"], false);
+
+        forget_scrollback(&mut sessions);
+        assert!(sessions.values().all(|s| s.get("scrollback").is_none()));
+    }
 
-    let cmd = CommandBuilder::new(shell);
-    let mut child = pty_pair
-        .slave
-        .spawn_command(cmd)
-        .map_err(|e| format!("Failed to spawn shell: {}", e))?;
+    #[test]
+    fn test_old_session_records_are_pruned() {
+        let start = chrono::Utc::now();
+        let mut sessions: HashMap<String, serde_json::Value> = (0..KEPT_SESSION_RECORDS + 5)
+            .map(|n| (format!("s{}", n), record(false, start + chrono::Duration::seconds(n as i64))))
+            .collect();
+        // Older than all of them, but still running
+        sessions.insert("running".to_string(), record(true, start - chrono::Duration::seconds(1)));
 
-    let session_id = Uuid::new_v4().to_string();
+        assert_eq!(prune_sessions(&mut sessions), 5);
+        assert_eq!(sessions.len(), KEPT_SESSION_RECORDS + 1);
+        assert!(sessions.contains_key("running") && sessions.contains_key("s5"));
+        assert!(!sessions.contains_key("s4"));
+        assert_eq!(prune_sessions(&mut sessions), 0);
+    }
 
-    // In a real implementation, you would:
-    // 1. Store the PTY master and child process handles
-    // 2. Spawn async tasks to read from PTY and emit events
-    // 3. Set up write channels for user input
-    // 4. Handle process cleanup
+    #[test]
+    fn test_shell_integration_records_commands() {
+        if !std::path::Path::new("/bin/bash").exists() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("multiapp-shell-{}", Uuid::new_v4()));
+        shell_integration::install(&dir).unwrap();
+        let terminals = TerminalManager::with_shell_integration(dir.clone());
+        let (sink, rx) = sink();
+        // An empty home keeps the user's .bashrc out of it
+        let options = SessionOptions {
+            env: HashMap::from([("HOME".to_string(), dir.display().to_string())]),
+            ..SessionOptions::new("/bin/bash", 24, 80)
+        };
+        let session = terminals.create_session(&options, sink).unwrap();
+        assert!(session.shell_integration);
 
-    Ok(session_id)
+        let finished = |count: usize| {
+            for _ in 0..100 {
+                let records = terminals.commands(&session.id, 10).unwrap().commands;
+                if records.len() == count && records.iter().all(|r| r.exit_code.is_some()) {
+                    return records;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            panic!("commands did not finish: {:?}", terminals.commands(&session.id, 10));
+        };
+        terminals.write_to_session(&session.id, b"echo one; echo two; (exit 3)\n").unwrap();
+        let records = finished(1);
+        assert_eq!(records[0].command, "echo one; echo two; (exit 3)");
+        assert_eq!(records[0].output, "one\ntwo\n");
+        assert_eq!(records[0].exit_code, Some(3));
+
+        terminals.label_next_command(&session.id, "labelled").unwrap();
+        terminals.write_to_session(&session.id, b"true\n").unwrap();
+        let records = finished(2);
+        assert_eq!((records[1].command.as_str(), records[1].exit_code), ("labelled", Some(0)));
+        assert_eq!(terminals.commands(&session.id, 1).unwrap().commands, records[1..]);
+
+        terminals.write_to_session(&session.id, b"exit\n").unwrap();
+        run_to_exit(&rx);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_session_is_recorded() {
+        let dir = std::env::temp_dir().join(format!("multiapp-recorded-{}", Uuid::new_v4()));
+        let crypto = Arc::new(std::sync::Mutex::new(crate::crypto::CryptoManager::new(None)));
+        let recordings = Recordings::new(&dir, crypto);
+        let terminals = TerminalManager::new();
+        let (sink, rx) = sink();
+        let options = SessionOptions {
+            recording: Some(recordings.clone()),
+            ..SessionOptions::new("/bin/sh", 24, 80)
+        };
+        let session = terminals.create_session(&options, sink).unwrap();
+        assert!(session.recorded);
+
+        terminals.resize_session(&session.id, 30, 100).unwrap();
+        terminals.write_to_session(&session.id, b"echo recorded-$((6*7)); exit\n").unwrap();
+        run_to_exit(&rx);
+
+        let export = recordings.export(&session.id, &dir).unwrap();
+        assert!(export.complete);
+        let content = std::fs::read_to_string(&export.path).unwrap();
+        assert!(content.contains("recorded-42"), "{}", content);
+        assert!(content.contains(r#""r","100x30""#), "{}", content);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_kill_session() {
+        let terminals = TerminalManager::new();
+        let (sink, rx) = sink();
+        let session = terminals.create_session(&SessionOptions::new("/bin/sh", 24, 80), sink).unwrap();
+
+        terminals.kill_session(&session.id).unwrap();
+        let (_, code) = run_to_exit(&rx);
+        assert_ne!(code, Some(0));
+        assert!(terminals.write_to_session(&session.id, b"echo\n").is_err());
+    }
 }
@@ -0,0 +1,331 @@
+// Command execution in shared terminal sessions for MultiappV1 Sidecar
+// Runs a command where the user can watch it and captures its output and exit status
+
+use crate::terminal::{ExecGuard, TapEvent, TerminalManager};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+/// Output returned by one exec; the terminal itself still shows all of it
+pub const MAX_EXEC_OUTPUT: usize = 1024 * 1024;
+
+pub const DEFAULT_EXEC_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long an interrupted command gets to report its exit status
+const INTERRUPT_GRACE: Duration = Duration::from_millis(500);
+
+/// After an interrupt, how long a shell without shell integration must stay quiet
+/// to count as back at its prompt
+const SETTLE_QUIET: Duration = Duration::from_secs(1);
+
+/// OSC 133 prompt start, written by the shell integration
+const PROMPT_MARK: &[u8] = b"\x1b]133;A";
+
+// Private OSC sequences around the command's output; terminals don't display them
+const MARKER_PREFIX: &str = "\x1b]777;multiapp-exec;";
+const MARKER_END: u8 = 0x07;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecStatus {
+    Completed,
+    Interrupted, // the user pressed Ctrl-C in the terminal
+    TimedOut,    // the command was interrupted with Ctrl-C
+    ShellExited,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ExecResult {
+    pub session_id: String,
+    pub status: ExecStatus,
+    pub exit_code: Option<i32>,
+    pub output: String,
+    pub truncated: bool,
+}
+
+/// Shell input that runs `command` between a start and an end marker. Everything goes
+/// on one line, so the shell echoes it before the start marker. `eval` keeps a comment
+/// or trailing `&` in the command from swallowing the end marker. The leading space
+/// keeps the line out of history where the shell ignores such lines.
+pub fn exec_input(command: &str, token: &str) -> String {
+    let quoted = command.replace('\'', r"'\''");
+    format!(
+        " printf '\\033]777;multiapp-exec;start;%s\\007' {token}; eval '{quoted}'; printf '\\033]777;multiapp-exec;end;%s;%d\\007' {token} $?\n"
+    )
+}
+
+/// Picks the output between the markers of one exec out of the raw PTY stream
+pub struct ExecCapture {
+    start: Vec<u8>,
+    end: Vec<u8>,
+    started: bool,
+    pending: Vec<u8>,
+    output: Vec<u8>,
+    truncated: bool,
+}
+
+impl ExecCapture {
+    pub fn new(token: &str) -> Self {
+        Self {
+            start: format!("{}start;{}\x07", MARKER_PREFIX, token).into_bytes(),
+            end: format!("{}end;{};", MARKER_PREFIX, token).into_bytes(),
+            started: false,
+            pending: Vec::new(),
+            output: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    /// Feed raw output; returns the exit status once the end marker has arrived
+    pub fn feed(&mut self, data: &[u8]) -> Option<i32> {
+        self.pending.extend_from_slice(data);
+
+        if !self.started {
+            match find(&self.pending, &self.start) {
+                Some(at) => {
+                    self.pending.drain(..at + self.start.len());
+                    self.started = true;
+                }
+                None => {
+                    // Only a partial marker at the end can still matter
+                    let keep = self.pending.len().min(self.start.len() - 1);
+                    self.pending.drain(..self.pending.len() - keep);
+                    return None;
+                }
+            }
+        }
+
+        if let Some(at) = find(&self.pending, &self.end) {
+            let status_start = at + self.end.len();
+            let status_len = self.pending[status_start..].iter().position(|&b| b == MARKER_END)?;
+            let status = String::from_utf8_lossy(&self.pending[status_start..status_start + status_len])
+                .parse()
+                .unwrap_or(-1);
+            let output: Vec<u8> = self.pending.drain(..at).collect();
+            self.keep(&output);
+            self.pending.clear();
+            return Some(status);
+        }
+
+        let safe = self.pending.len().saturating_sub(self.end.len() - 1);
+        let output: Vec<u8> = self.pending.drain(..safe).collect();
+        self.keep(&output);
+        None
+    }
+
+    fn keep(&mut self, bytes: &[u8]) {
+        let room = MAX_EXEC_OUTPUT - self.output.len();
+        if bytes.len() > room {
+            self.truncated = true;
+        }
+        self.output.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+
+    /// Captured output with the PTY's CRLF line endings turned back into LF
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output).replace("\r\n", "\n")
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Run `command` in a running session and wait for it to finish, be interrupted,
+/// or time out. Only one exec runs in a session at a time; one that times out is
+/// interrupted, and the session stays claimed until the shell is back at its prompt.
+pub async fn exec(terminals: &TerminalManager, session_id: &str, command: &str, timeout: Duration) -> Result<ExecResult, String> {
+    let running = terminals.begin_exec(session_id)?;
+    // Tapped before the command is written, so none of its output is missed
+    let mut events = terminals.tap(session_id)?;
+    terminals.label_next_command(session_id, command)?;
+
+    let token = Uuid::new_v4().simple().to_string();
+    let input = exec_input(command, &token);
+    let writer = terminals.clone();
+    let id = session_id.to_string();
+    tokio::task::spawn_blocking(move || writer.write_to_session(&id, input.as_bytes()))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let mut capture = ExecCapture::new(&token);
+    let deadline = Instant::now() + timeout;
+    let mut interrupted_at: Option<Instant> = None;
+
+    let (status, exit_code) = loop {
+        let wait_until = interrupted_at.map_or(deadline, |at| deadline.min(at + INTERRUPT_GRACE));
+        match tokio::time::timeout_at(wait_until, events.recv()).await {
+            Ok(Some(TapEvent::Output(data))) => {
+                if let Some(code) = capture.feed(&data) {
+                    let status = if interrupted_at.is_some() { ExecStatus::Interrupted } else { ExecStatus::Completed };
+                    break (status, Some(code));
+                }
+            }
+            Ok(Some(TapEvent::Interrupt)) => {
+                interrupted_at.get_or_insert_with(Instant::now);
+            }
+            Ok(None) => break (ExecStatus::ShellExited, None),
+            Err(_) if interrupted_at.is_some() && Instant::now() < deadline => break (ExecStatus::Interrupted, None),
+            Err(_) => break (ExecStatus::TimedOut, None),
+        }
+    };
+
+    let result = ExecResult {
+        session_id: session_id.to_string(),
+        status,
+        exit_code,
+        output: capture.output(),
+        truncated: capture.truncated,
+    };
+
+    if status == ExecStatus::TimedOut {
+        let writer = terminals.clone();
+        let id = session_id.to_string();
+        let interrupt = tokio::task::spawn_blocking(move || writer.write_to_session(&id, b"\x03"))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+        if let Err(e) = interrupt {
+            eprintln!("Failed to interrupt timed out exec in terminal {}: {}", session_id, e);
+        }
+    }
+    // Until the command is gone, the next exec would be typed into it
+    if matches!(status, ExecStatus::TimedOut | ExecStatus::Interrupted) && exit_code.is_none() {
+        let integrated = terminals.commands(session_id, 0).is_ok_and(|commands| commands.shell_integration);
+        tokio::spawn(settle(running, events, capture, integrated));
+    }
+    Ok(result)
+}
+
+/// Hold the session's exec claim until its end marker arrives, the shell prompts
+/// again or the shell exits. A shell without shell integration doesn't mark its
+/// prompt, so it is released once its output pauses for `SETTLE_QUIET`.
+async fn settle(_running: ExecGuard, mut events: UnboundedReceiver<TapEvent>, mut capture: ExecCapture, integrated: bool) {
+    let mut tail: Vec<u8> = Vec::new();
+    loop {
+        let event = if integrated {
+            events.recv().await
+        } else {
+            match tokio::time::timeout(SETTLE_QUIET, events.recv()).await {
+                Ok(event) => event,
+                Err(_) => return,
+            }
+        };
+        match event {
+            Some(TapEvent::Output(data)) => {
+                if capture.feed(&data).is_some() {
+                    return;
+                }
+                tail.extend_from_slice(&data);
+                if find(&tail, PROMPT_MARK).is_some() {
+                    return;
+                }
+                let keep = tail.len().min(PROMPT_MARK.len() - 1);
+                tail.drain(..tail.len() - keep);
+            }
+            Some(TapEvent::Interrupt) => {}
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markers(token: &str, output: &str, status: i32) -> Vec<u8> {
+        format!(
+            "$ echoed input\r\n{p}start;{t}\x07{o}{p}end;{t};{s}\x07$ ",
+            p = MARKER_PREFIX, t = token, o = output, s = status
+        ).into_bytes()
+    }
+
+    #[test]
+    fn test_capture_across_chunks() {
+        let stream = markers("abc", "line one\r\nline two\r\n", 3);
+        // Feed one byte at a time so every marker is split
+        let mut capture = ExecCapture::new("abc");
+        let mut status = None;
+        for byte in &stream {
+            if let Some(code) = capture.feed(std::slice::from_ref(byte)) {
+                status = Some(code);
+                break;
+            }
+        }
+        assert_eq!(status, Some(3));
+        assert_eq!(capture.output(), "line one\nline two\n");
+
+        // Markers of another exec are ignored
+        let mut capture = ExecCapture::new("xyz");
+        assert_eq!(capture.feed(&stream), None);
+        assert_eq!(capture.output(), "");
+    }
+
+    #[test]
+    fn test_capture_is_bounded() {
+        let mut capture = ExecCapture::new("abc");
+        let output = "x".repeat(MAX_EXEC_OUTPUT + 10);
+        assert_eq!(capture.feed(&markers("abc", &output, 0)), Some(0));
+        assert!(capture.truncated);
+        assert_eq!(capture.output().len(), MAX_EXEC_OUTPUT);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_in_shell() {
+        use crate::terminal::{SessionOptions, TerminalExit, TerminalOutput, TerminalSink};
+        use std::sync::Arc;
+
+        struct NullSink;
+        impl TerminalSink for NullSink {
+            fn output(&self, _output: TerminalOutput) {}
+            fn exited(&self, _exit: TerminalExit) {}
+        }
+
+        let terminals = TerminalManager::new();
+        let session = terminals.create_session(&SessionOptions::new("/bin/sh", 24, 80), Arc::new(NullSink)).unwrap();
+        let id = session.id.as_str();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let timeout = Duration::from_secs(10);
+
+        let result = runtime.block_on(exec(&terminals, id, "echo one; echo 'two # quoted'; false # comment", timeout)).unwrap();
+        assert_eq!(result.status, ExecStatus::Completed);
+        assert_eq!(result.exit_code, Some(1));
+        assert_eq!(result.output, "one\ntwo # quoted\n");
+
+        // One exec at a time
+        let running = terminals.begin_exec(id).unwrap();
+        assert!(runtime.block_on(exec(&terminals, id, "true", timeout)).is_err());
+        drop(running);
+
+        // Ctrl-C from the user ends the wait
+        let (result, _) = runtime.block_on(async {
+            tokio::join!(exec(&terminals, id, "sleep 5", timeout), async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                terminals.write_to_session(id, b"\x03")
+            })
+        });
+        assert_eq!(result.unwrap().status, ExecStatus::Interrupted);
+        std::thread::sleep(SETTLE_QUIET * 2);
+
+        // A timed out command is interrupted, and the session stays claimed until it is gone
+        let result = runtime.block_on(exec(&terminals, id, "sleep 30", Duration::from_secs(1))).unwrap();
+        assert_eq!(result.status, ExecStatus::TimedOut);
+        assert!(terminals.begin_exec(id).is_err());
+        std::thread::sleep(SETTLE_QUIET * 2);
+        let result = runtime.block_on(exec(&terminals, id, "echo after", timeout)).unwrap();
+        assert_eq!(result.status, ExecStatus::Completed);
+        assert_eq!(result.output, "after\n");
+
+        let result = runtime.block_on(exec(&terminals, id, "exit 4", timeout)).unwrap();
+        assert_eq!(result.status, ExecStatus::ShellExited);
+    }
+
+    #[test]
+    fn test_exec_input_quotes_command() {
+        let input = exec_input("echo 'it''s' # done &", "abc");
+        assert!(input.contains(r"eval 'echo '\''it'\'''\''s'\'' # done &';"));
+        assert!(input.ends_with(" abc $?\n"));
+    }
+}
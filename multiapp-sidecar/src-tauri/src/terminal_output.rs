@@ -0,0 +1,444 @@
+// Terminal output pipeline for MultiappV1 Sidecar
+// Coalesces PTY output into bounded frames and falls back to a resync when the frontend lags
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long output collects before it is sent as one frame
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+pub const MAX_FRAME_BYTES: usize = 64 * 1024;
+
+// Past either limit the session stops streaming and switches to sync mode
+const MAX_PENDING_BYTES: usize = 1024 * 1024;
+const MAX_UNACKED_BYTES: usize = 512 * 1024;
+
+/// Output kept while in sync mode; it is replayed on a reset screen, starting with the resync frame
+pub const SYNC_TAIL_BYTES: usize = 256 * 1024;
+
+/// Output each session keeps for replay after the frontend reloads
+pub const SCROLLBACK_BYTES: usize = 1024 * 1024;
+
+/// Frames not acknowledged within this time are assumed lost (e.g. the webview reloaded)
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub seq: u64,
+    pub offset: u64,   // position of `data` in the session's output stream
+    pub data: String,
+    pub resync: bool,  // the frontend should reset the screen before writing `data`
+}
+
+/// Replayed history: `data` starts at stream position `offset`, and output from
+/// `next_offset` on arrives as frames
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrollbackChunk {
+    pub offset: u64,
+    pub data: String,
+    pub next_offset: u64,
+}
+
+/// Ring buffer of the most recent SCROLLBACK_BYTES of output
+struct Scrollback {
+    data: VecDeque<u8>,
+    start: u64, // stream position of the oldest byte kept
+}
+
+impl Scrollback {
+    fn new() -> Self {
+        Self { data: VecDeque::new(), start: 0 }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+        if self.data.len() > SCROLLBACK_BYTES {
+            let excess = self.data.len() - SCROLLBACK_BYTES;
+            self.data.drain(..excess);
+            self.start += excess as u64;
+        }
+        // Never start in the middle of a character
+        while self.data.front().is_some_and(|&b| b & 0xC0 == 0x80) {
+            self.data.pop_front();
+            self.start += 1;
+        }
+    }
+
+    /// Output from `from_offset`, or from the oldest byte kept if that has been dropped.
+    /// A character still being written is left for the next frame.
+    fn read_from(&self, from_offset: u64, closed: bool) -> ScrollbackChunk {
+        let end = self.start + self.data.len() as u64;
+        let mut offset = from_offset.clamp(self.start, end);
+        let mut bytes: Vec<u8> = self.data.range((offset - self.start) as usize..).copied().collect();
+
+        let lead = bytes.iter().position(|&b| b & 0xC0 != 0x80).unwrap_or(bytes.len());
+        bytes.drain(..lead);
+        offset += lead as u64;
+        bytes.truncate(frame_len(&bytes, bytes.len(), closed));
+
+        ScrollbackChunk {
+            offset,
+            next_offset: offset + bytes.len() as u64,
+            data: String::from_utf8_lossy(&bytes).into_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Live, // frames carry every byte
+    Sync, // output goes to `tail` until the frontend catches up
+}
+
+/// Frame bookkeeping for one session, independent of threads and clocks
+pub struct OutputBuffer {
+    mode: Mode,
+    pending: Vec<u8>, // live output not yet framed
+    tail: Vec<u8>,    // sync mode: the most recent output
+    scrollback: Scrollback,
+    received: u64,
+    next_seq: u64,
+    unacked: VecDeque<(u64, usize)>,
+    unacked_bytes: usize,
+    last_ack: Instant,
+    closed: bool,
+}
+
+impl OutputBuffer {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            mode: Mode::Live,
+            pending: Vec::new(),
+            tail: Vec::new(),
+            scrollback: Scrollback::new(),
+            received: 0,
+            next_seq: 1,
+            unacked: VecDeque::new(),
+            unacked_bytes: 0,
+            last_ack: now,
+            closed: false,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.received += data.len() as u64;
+        self.scrollback.push(data);
+        match self.mode {
+            Mode::Live => {
+                self.pending.extend_from_slice(data);
+                if self.pending.len() > MAX_PENDING_BYTES {
+                    self.enter_sync();
+                }
+            }
+            Mode::Sync => {
+                self.tail.extend_from_slice(data);
+                trim_tail(&mut self.tail, SYNC_TAIL_BYTES);
+            }
+        }
+    }
+
+    /// The frontend has written every frame up to and including `seq`
+    pub fn ack(&mut self, seq: u64, now: Instant) {
+        while let Some(&(sent, len)) = self.unacked.front() {
+            if sent > seq {
+                break;
+            }
+            self.unacked.pop_front();
+            self.unacked_bytes -= len;
+        }
+        self.last_ack = now;
+    }
+
+    /// History for a frontend that is (re)attaching. Frames sent so far count as
+    /// acknowledged: the replay carries their output.
+    pub fn replay(&mut self, from_offset: u64, now: Instant) -> ScrollbackChunk {
+        self.ack(self.next_seq - 1, now);
+        self.scrollback.read_from(from_offset, self.closed)
+    }
+
+    /// No more output will be pushed; remaining output is sent without waiting for acks
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn has_output(&self) -> bool {
+        !self.pending.is_empty() || !self.tail.is_empty()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.closed && !self.has_output()
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.mode == Mode::Sync
+    }
+
+    /// The next frame to send, if any
+    pub fn next_frame(&mut self, now: Instant) -> Option<Frame> {
+        match self.mode {
+            Mode::Live => {
+                if self.unacked_bytes > MAX_UNACKED_BYTES && !self.closed {
+                    self.enter_sync();
+                    return None;
+                }
+                let len = frame_len(&self.pending, MAX_FRAME_BYTES, self.closed);
+                if len == 0 {
+                    return None;
+                }
+                let offset = self.received - self.pending.len() as u64;
+                let bytes: Vec<u8> = self.pending.drain(..len).collect();
+                Some(self.emit(offset, &bytes, false))
+            }
+            Mode::Sync => {
+                let caught_up = self.unacked.is_empty()
+                    || now.duration_since(self.last_ack) >= ACK_TIMEOUT
+                    || self.closed;
+                if !caught_up {
+                    return None;
+                }
+                // Whatever is still unacknowledged is superseded by the reset
+                self.unacked.clear();
+                self.unacked_bytes = 0;
+                self.last_ack = now;
+                self.mode = Mode::Live;
+
+                // The resync frame is bounded like any other; the rest of the
+                // tail follows as live frames
+                let offset = self.received - self.tail.len() as u64;
+                let mut bytes = std::mem::take(&mut self.tail);
+                self.pending = bytes.split_off(frame_len(&bytes, MAX_FRAME_BYTES, self.closed));
+                Some(self.emit(offset, &bytes, true))
+            }
+        }
+    }
+
+    fn emit(&mut self, offset: u64, bytes: &[u8], resync: bool) -> Frame {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.unacked.push_back((seq, bytes.len()));
+        self.unacked_bytes += bytes.len();
+        Frame {
+            seq,
+            offset,
+            data: String::from_utf8_lossy(bytes).into_owned(),
+            resync,
+        }
+    }
+
+    fn enter_sync(&mut self) {
+        self.mode = Mode::Sync;
+        self.tail.append(&mut self.pending);
+        trim_tail(&mut self.tail, SYNC_TAIL_BYTES);
+    }
+}
+
+/// How many bytes of `pending` make the next frame: at most `limit`, never ending
+/// inside a UTF-8 character unless the stream is closing. Invalid bytes count as
+/// one character each and come out as replacement characters.
+fn frame_len(pending: &[u8], limit: usize, closing: bool) -> usize {
+    let end = pending.len().min(limit);
+    if closing && end == pending.len() {
+        return end;
+    }
+
+    // Find the last lead byte and check its character fits before `end`
+    let start = end.saturating_sub(3);
+    match (start..end).rev().find(|&i| pending[i] & 0xC0 != 0x80) {
+        Some(lead) if lead + utf8_width(pending[lead]) > end => lead,
+        _ => end,
+    }
+}
+
+fn utf8_width(lead: u8) -> usize {
+    match lead {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    }
+}
+
+/// Keep the last `limit` bytes, starting at a line and character boundary
+pub fn trim_tail(tail: &mut Vec<u8>, limit: usize) {
+    if tail.len() <= limit {
+        return;
+    }
+    let mut cut = tail.len() - limit;
+    match tail[cut..].iter().position(|&b| b == b'\n') {
+        Some(newline) => cut += newline + 1,
+        None => {
+            while cut < tail.len() && tail[cut] & 0xC0 == 0x80 {
+                cut += 1;
+            }
+        }
+    }
+    tail.drain(..cut);
+}
+
+/// Thread-safe OutputBuffer: the PTY reader pushes, `run` emits frames on its own thread
+pub struct OutputPipeline {
+    buffer: Mutex<OutputBuffer>,
+    wake: Condvar,
+}
+
+impl Default for OutputPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputPipeline {
+    pub fn new() -> Self {
+        Self {
+            buffer: Mutex::new(OutputBuffer::new(Instant::now())),
+            wake: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, data: &[u8]) {
+        self.buffer.lock().unwrap().push(data);
+        self.wake.notify_one();
+    }
+
+    pub fn ack(&self, seq: u64) {
+        self.buffer.lock().unwrap().ack(seq, Instant::now());
+        self.wake.notify_one();
+    }
+
+    pub fn close(&self) {
+        self.buffer.lock().unwrap().close();
+        self.wake.notify_one();
+    }
+
+    pub fn replay(&self, from_offset: u64) -> ScrollbackChunk {
+        let chunk = self.buffer.lock().unwrap().replay(from_offset, Instant::now());
+        self.wake.notify_one();
+        chunk
+    }
+
+    /// Emit frames until the pipeline is closed and drained
+    pub fn run(&self, mut emit: impl FnMut(Frame)) {
+        loop {
+            {
+                let mut buffer = self.buffer.lock().unwrap();
+                while !buffer.has_output() {
+                    if buffer.is_finished() {
+                        return;
+                    }
+                    buffer = self.wake.wait(buffer).unwrap();
+                }
+            }
+
+            // Let output pile up for a frame's worth of time
+            thread::sleep(FRAME_INTERVAL);
+
+            let frame = self.buffer.lock().unwrap().next_frame(Instant::now());
+            if let Some(frame) = frame {
+                emit(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_never_split_characters() {
+        let text = "é".repeat(10);
+        assert_eq!(frame_len(text.as_bytes(), 5, false), 4);
+        assert_eq!(frame_len(&text.as_bytes()[..3], 64, false), 2);
+        assert_eq!(frame_len(&text.as_bytes()[..3], 64, true), 3);
+        assert_eq!(frame_len(b"\xff\xfe", 64, false), 2);
+
+        let start = Instant::now();
+        let mut buffer = OutputBuffer::new(start);
+        buffer.push(&"é".as_bytes()[..1]);
+        assert_eq!(buffer.next_frame(start), None);
+        buffer.push(&"é".as_bytes()[1..]);
+        let frame = buffer.next_frame(start).unwrap();
+        assert_eq!((frame.seq, frame.offset, frame.data.as_str()), (1, 0, "é"));
+    }
+
+    #[test]
+    fn test_lagging_frontend_switches_to_sync() {
+        let start = Instant::now();
+        let mut buffer = OutputBuffer::new(start);
+        let line = format!("{}\n", "x".repeat(1023));
+
+        // Nothing is acknowledged, so streaming stops once the window is full
+        let mut sent = 0;
+        while !buffer.is_syncing() {
+            buffer.push(line.as_bytes());
+            if let Some(frame) = buffer.next_frame(start) {
+                sent += frame.data.len();
+            }
+        }
+        assert!(sent > MAX_UNACKED_BYTES && sent <= MAX_UNACKED_BYTES + MAX_FRAME_BYTES);
+
+        // Output keeps arriving but only the tail is kept
+        for _ in 0..2048 {
+            buffer.push(line.as_bytes());
+        }
+        assert_eq!(buffer.next_frame(start), None);
+
+        buffer.push(&"é".as_bytes()[..1]);
+
+        // Once the frontend catches up it gets a resync frame, then the rest of the
+        // recent output as ordinary frames
+        buffer.ack(u64::MAX, start);
+        let frame = buffer.next_frame(start).unwrap();
+        assert!(frame.resync && !buffer.is_syncing());
+        assert!(frame.data.len() == MAX_FRAME_BYTES && frame.data.starts_with('x'));
+        let mut end = frame.offset + frame.data.len() as u64;
+        let mut replayed = frame.data.len();
+        while let Some(frame) = buffer.next_frame(start) {
+            assert!(!frame.resync && frame.offset == end && frame.data.len() <= MAX_FRAME_BYTES);
+            end += frame.data.len() as u64;
+            replayed += frame.data.len();
+        }
+        assert!(replayed < SYNC_TAIL_BYTES);
+        // The split character waits for its second byte
+        assert_eq!(end, buffer.received - 1);
+        buffer.push(&"é".as_bytes()[1..]);
+        assert_eq!(buffer.next_frame(start).unwrap().data, "é");
+    }
+
+    #[test]
+    fn test_scrollback_keeps_recent_output() {
+        let start = Instant::now();
+        let mut buffer = OutputBuffer::new(start);
+        buffer.push(b"hello ");
+        buffer.push(&"é".as_bytes()[..1]);
+
+        // The split character waits for its second byte
+        let chunk = buffer.replay(0, start);
+        assert_eq!((chunk.offset, chunk.data.as_str(), chunk.next_offset), (0, "hello ", 6));
+        buffer.push(&"é".as_bytes()[1..]);
+        assert_eq!(buffer.replay(6, start).data, "é");
+
+        // Bounded, and trimmed to a character boundary
+        buffer.push(&"é".repeat(SCROLLBACK_BYTES / 2).into_bytes());
+        buffer.push(b"!");
+        let chunk = buffer.replay(0, start);
+        assert!(chunk.offset > 0 && chunk.data.len() <= SCROLLBACK_BYTES);
+        assert!(chunk.data.starts_with('é') && chunk.data.ends_with('!'));
+        assert_eq!(chunk.next_offset, buffer.received);
+    }
+
+    #[test]
+    fn test_unacked_sync_times_out() {
+        let start = Instant::now();
+        let mut buffer = OutputBuffer::new(start);
+        buffer.push(&vec![b'a'; MAX_PENDING_BYTES + 1]);
+        assert!(buffer.is_syncing());
+
+        let frame = buffer.next_frame(start).unwrap();
+        assert!(frame.resync);
+        buffer.push(&vec![b'b'; MAX_PENDING_BYTES + 1]);
+        assert_eq!(buffer.next_frame(start), None);
+        assert!(buffer.next_frame(start + ACK_TIMEOUT).unwrap().resync);
+    }
+}
@@ -76,6 +76,15 @@ const App = () => {
           notifications: [...prev.notifications, event.payload],
           unread_notifications: prev.unread_notifications + 1
         }));
+      }),
+
+      listen('notification_updated', (event) => {
+        setEvents(prev => ({
+          ...prev,
+          notifications: prev.notifications.map(n =>
+            n.id === event.payload.id ? event.payload : n
+          )
+        }));
       })
     ];
 
@@ -113,7 +122,7 @@ const App = () => {
       await invoke('clear_notifications');
       setEvents(prev => ({
         ...prev,
-        notifications: prev.notifications.map(n => ({ ...n, dismissed: true })),
+        notifications: [],
         unread_notifications: 0
       }));
     } catch (error) {
@@ -121,6 +130,21 @@ const App = () => {
     }
   };
 
+  const handleDismissNotification = async (notificationId) => {
+    try {
+      await invoke('dismiss_notification', { notificationId });
+      setEvents(prev => ({
+        ...prev,
+        notifications: prev.notifications.map(n =>
+          n.id === notificationId ? { ...n, dismissed: true } : n
+        ),
+        unread_notifications: Math.max(0, prev.unread_notifications - 1)
+      }));
+    } catch (error) {
+      console.error('Failed to dismiss notification:', error);
+    }
+  };
+
   const renderPanel = () => {
     switch (activePanel) {
       case 'operations':
@@ -136,6 +160,7 @@ const App = () => {
           <NotificationsPanel
             notifications={events.notifications.filter(n => !n.dismissed)}
             onClear={handleClearNotifications}
+            onDismiss={handleDismissNotification}
           />
         );
       case 'terminal':
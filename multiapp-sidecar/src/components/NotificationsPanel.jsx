@@ -1,7 +1,31 @@
-import React from 'react';
-import { Bell, Info, AlertTriangle, XCircle, Trash2, BellOff } from 'lucide-react';
+import React, { useState } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { Bell, Info, AlertTriangle, XCircle, Trash2, BellOff, X } from 'lucide-react';
+
+const ACTION_LABELS = {
+  approve: 'Approve',
+  reject: 'Reject',
+  view_diff: 'View diff',
+  revert: 'Revert'
+};
+
+const NotificationsPanel = ({ notifications, onClear, onDismiss }) => {
+  const [diffs, setDiffs] = useState({});
+
+  const handleAction = async (notification, action) => {
+    try {
+      const response = await invoke('invoke_notification_action', {
+        notificationId: notification.id,
+        action
+      });
+      if (response.diff) {
+        setDiffs(prev => ({ ...prev, [notification.id]: response.diff }));
+      }
+    } catch (error) {
+      console.error(`Failed to ${action} from notification:`, error);
+    }
+  };
 
-const NotificationsPanel = ({ notifications, onClear }) => {
   const getNotificationIcon = (level) => {
     switch (level) {
       case 'error':
@@ -104,11 +128,42 @@ const NotificationsPanel = ({ notifications, onClear }) => {
                       <div className="flex-1 min-w-0">
                         <div className="font-medium mb-1">
                           {notification.message}
+                          {notification.count > 1 && (
+                            <span className="ml-2 inline-flex items-center px-2 rounded-full text-xs bg-dark-700 text-dark-200">
+                              ×{notification.count}
+                            </span>
+                          )}
                         </div>
                         <div className="text-sm opacity-75">
                           {formatTimestamp(notification.timestamp)}
                         </div>
+                        {notification.actions?.length > 0 && (
+                          <div className="flex flex-wrap gap-2 mt-2">
+                            {notification.actions.map((action) => (
+                              <button
+                                key={action}
+                                onClick={() => handleAction(notification, action)}
+                                className="button-secondary text-xs px-2 py-1"
+                              >
+                                {ACTION_LABELS[action] || action}
+                              </button>
+                            ))}
+                          </div>
+                        )}
+                        {diffs[notification.id] && (
+                          <pre className="mt-2 p-2 rounded bg-dark-900 text-dark-200 text-xs overflow-x-auto max-h-64 custom-scrollbar">
+                            {diffs[notification.id]}
+                          </pre>
+                        )}
                       </div>
+
+                      <button
+                        onClick={() => onDismiss(notification.id)}
+                        className="p-1 rounded opacity-60 hover:opacity-100"
+                        title="Dismiss"
+                      >
+                        <X className="w-4 h-4" />
+                      </button>
                     </div>
                   </div>
                 );
@@ -8,10 +8,19 @@ const SettingsPanel = () => {
     auto_approve_read_ops: true,
     show_notifications: true,
     terminal_shell: '/bin/bash',
-    encryption_enabled: false
+    terminal_persist_scrollback: false,
+    terminal_shell_integration: true,
+    terminal_record_sessions: false,
+    terminal_http_api: false,
+    encryption_enabled: false,
+    max_notifications: 200
   });
   const [isLoading, setIsLoading] = useState(true);
   const [isSaving, setIsSaving] = useState(false);
+  const [savedEncryption, setSavedEncryption] = useState(false);
+  const [encryptionPassphrase, setEncryptionPassphrase] = useState('');
+  const [recoveryPassphrase, setRecoveryPassphrase] = useState('');
+  const [recoveryStatus, setRecoveryStatus] = useState(null);
 
   // Load current configuration
   useEffect(() => {
@@ -19,6 +28,7 @@ const SettingsPanel = () => {
       try {
         const currentConfig = await invoke('get_app_config');
         setConfig(currentConfig);
+        setSavedEncryption(currentConfig.encryption_enabled);
       } catch (error) {
         console.error('Failed to load config:', error);
       } finally {
@@ -32,6 +42,14 @@ const SettingsPanel = () => {
   const handleSave = async () => {
     setIsSaving(true);
     try {
+      // Toggling encryption re-encrypts or decrypts the stored state
+      if (config.encryption_enabled && !savedEncryption) {
+        await invoke('enable_encryption', { passphrase: encryptionPassphrase || null });
+      } else if (!config.encryption_enabled && savedEncryption) {
+        await invoke('disable_encryption');
+      }
+      setSavedEncryption(config.encryption_enabled);
+      setEncryptionPassphrase('');
       await invoke('update_app_config', { config });
       // Show success notification (you could add a toast here)
       console.log('Configuration saved successfully');
@@ -42,13 +60,29 @@ const SettingsPanel = () => {
     }
   };
 
+  const handleRecover = async () => {
+    try {
+      const result = await invoke('recover_state', { passphrase: recoveryPassphrase });
+      setRecoveryStatus(`Recovered ${result.operations} operations from ${result.recovered_from}`);
+      setRecoveryPassphrase('');
+      setConfig(await invoke('get_app_config'));
+    } catch (error) {
+      setRecoveryStatus(`Recovery failed: ${error}`);
+    }
+  };
+
   const handleReset = () => {
     setConfig({
       theme: 'dark',
       auto_approve_read_ops: true,
       show_notifications: true,
       terminal_shell: '/bin/bash',
-      encryption_enabled: false
+      terminal_persist_scrollback: false,
+      terminal_shell_integration: true,
+    terminal_record_sessions: false,
+    terminal_http_api: false,
+      encryption_enabled: false,
+      max_notifications: 200
     });
   };
 
@@ -169,7 +203,7 @@ const SettingsPanel = () => {
                     Encryption enabled
                   </label>
                   <p className="text-xs text-dark-500 mt-1">
-                    Encrypt all persistent state data (requires MULTIAPP_PASSPHRASE environment variable)
+                    Encrypt all persistent state data with MULTIAPP_PASSPHRASE or the passphrase below
                   </p>
                 </div>
                 <label className="relative inline-flex items-center cursor-pointer">
@@ -182,6 +216,49 @@ const SettingsPanel = () => {
                   <div className="w-11 h-6 bg-dark-700 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-accent-800 rounded-full peer peer-checked:after:translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:left-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all peer-checked:bg-accent-600"></div>
                 </label>
               </div>
+
+              {config.encryption_enabled && !savedEncryption && (
+                <div>
+                  <label className="block text-sm font-medium text-dark-300 mb-2">
+                    Passphrase
+                  </label>
+                  <input
+                    type="password"
+                    value={encryptionPassphrase}
+                    onChange={(e) => setEncryptionPassphrase(e.target.value)}
+                    className="input-dark w-64"
+                    placeholder="Leave empty to use MULTIAPP_PASSPHRASE"
+                  />
+                  <p className="text-xs text-dark-500 mt-1">
+                    Used to encrypt the saved state when you save these settings
+                  </p>
+                </div>
+              )}
+
+              <div>
+                <label className="block text-sm font-medium text-dark-300 mb-2">
+                  Recover saved state
+                </label>
+                <div className="flex items-center space-x-2">
+                  <input
+                    type="password"
+                    value={recoveryPassphrase}
+                    onChange={(e) => setRecoveryPassphrase(e.target.value)}
+                    className="input-dark w-64"
+                    placeholder="Passphrase"
+                  />
+                  <button
+                    onClick={handleRecover}
+                    disabled={!recoveryPassphrase}
+                    className="button-secondary text-sm"
+                  >
+                    Recover
+                  </button>
+                </div>
+                <p className="text-xs text-dark-500 mt-1">
+                  {recoveryStatus || 'Retry loading state that was quarantined because it could not be read'}
+                </p>
+              </div>
             </div>
           </section>
 
@@ -208,6 +285,90 @@ const SettingsPanel = () => {
                   Path to the shell executable for new terminal sessions
                 </p>
               </div>
+
+              <div className="flex items-center justify-between">
+                <div>
+                  <label className="text-sm font-medium text-dark-300">
+                    Keep output across restarts
+                  </label>
+                  <p className="text-xs text-dark-500 mt-1">
+                    Save recent terminal output on exit and show it above restored sessions.
+                    It may contain secrets; enable encryption to store it encrypted.
+                  </p>
+                </div>
+                <label className="relative inline-flex items-center cursor-pointer">
+                  <input
+                    type="checkbox"
+                    checked={config.terminal_persist_scrollback}
+                    onChange={(e) => setConfig({ ...config, terminal_persist_scrollback: e.target.checked })}
+                    className="sr-only peer"
+                  />
+                  <div className="w-11 h-6 bg-dark-700 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-accent-800 rounded-full peer peer-checked:after:translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:left-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all peer-checked:bg-accent-600"></div>
+                </label>
+              </div>
+
+              <div className="flex items-center justify-between">
+                <div>
+                  <label className="text-sm font-medium text-dark-300">
+                    Shell integration
+                  </label>
+                  <p className="text-xs text-dark-500 mt-1">
+                    Have bash and zsh report each command, its output and exit code, so they
+                    can be given to the AI as context. Applies to new sessions.
+                  </p>
+                </div>
+                <label className="relative inline-flex items-center cursor-pointer">
+                  <input
+                    type="checkbox"
+                    checked={config.terminal_shell_integration}
+                    onChange={(e) => setConfig({ ...config, terminal_shell_integration: e.target.checked })}
+                    className="sr-only peer"
+                  />
+                  <div className="w-11 h-6 bg-dark-700 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-accent-800 rounded-full peer peer-checked:after:translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:left-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all peer-checked:bg-accent-600"></div>
+                </label>
+              </div>
+
+              <div className="flex items-center justify-between">
+                <div>
+                  <label className="text-sm font-medium text-dark-300">
+                    Record sessions
+                  </label>
+                  <p className="text-xs text-dark-500 mt-1">
+                    Save each session as an asciicast recording in the app data directory,
+                    encrypted when encryption is on. Applies to new sessions.
+                  </p>
+                </div>
+                <label className="relative inline-flex items-center cursor-pointer">
+                  <input
+                    type="checkbox"
+                    checked={config.terminal_record_sessions}
+                    onChange={(e) => setConfig({ ...config, terminal_record_sessions: e.target.checked })}
+                    className="sr-only peer"
+                  />
+                  <div className="w-11 h-6 bg-dark-700 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-accent-800 rounded-full peer peer-checked:after:translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:left-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all peer-checked:bg-accent-600"></div>
+                </label>
+              </div>
+
+              <div className="flex items-center justify-between">
+                <div>
+                  <label className="text-sm font-medium text-dark-300">
+                    Terminal access for GeanyLua
+                  </label>
+                  <p className="text-xs text-dark-500 mt-1">
+                    Let GeanyLua open sessions and run commands over the local HTTP API.
+                    Requests must carry the token the sidecar writes at each launch.
+                  </p>
+                </div>
+                <label className="relative inline-flex items-center cursor-pointer">
+                  <input
+                    type="checkbox"
+                    checked={config.terminal_http_api}
+                    onChange={(e) => setConfig({ ...config, terminal_http_api: e.target.checked })}
+                    className="sr-only peer"
+                  />
+                  <div className="w-11 h-6 bg-dark-700 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-accent-800 rounded-full peer peer-checked:after:translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:left-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all peer-checked:bg-accent-600"></div>
+                </label>
+              </div>
             </div>
           </section>
 
@@ -238,6 +399,22 @@ const SettingsPanel = () => {
                   <div className="w-11 h-6 bg-dark-700 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-accent-800 rounded-full peer peer-checked:after:translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:left-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all peer-checked:bg-accent-600"></div>
                 </label>
               </div>
+
+              <div>
+                <label className="block text-sm font-medium text-dark-300 mb-2">
+                  History limit
+                </label>
+                <input
+                  type="number"
+                  min="10"
+                  value={config.max_notifications}
+                  onChange={(e) => setConfig({ ...config, max_notifications: parseInt(e.target.value, 10) || 10 })}
+                  className="input-dark w-32"
+                />
+                <p className="text-xs text-dark-500 mt-1">
+                  Oldest notifications are removed once this many are stored
+                </p>
+              </div>
             </div>
           </section>
 
@@ -1,18 +1,55 @@
 import React, { useEffect, useRef, useState } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
-import { Terminal as TerminalIcon, Plus, X, Square } from 'lucide-react';
+import { Terminal as TerminalIcon, Plus, X, Square, RotateCcw, Film, Play, Download, Trash2, Lock } from 'lucide-react';
 import { Terminal } from 'xterm';
 import { FitAddon } from 'xterm-addon-fit';
 import { WebLinksAddon } from 'xterm-addon-web-links';
 import 'xterm/css/xterm.css';
 
+const encoder = new TextEncoder();
+const decoder = new TextDecoder();
+
+// Escape sequences (CSI, OSC, charset and other ESC) stripped from saved output before it is greyed out
+const ANSI_PATTERN = /\x1b\[[0-?]*[ -\/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[()*+#][0-~]|\x1b[0-~]/g;
+
+// Replay requested but its id not known yet; its events are queued meanwhile
+const REPLAY_STARTING = 'starting';
+
+const formatSize = (bytes) => {
+  if (bytes < 1024) return `${bytes} B`;
+  if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KiB`;
+  return `${(bytes / 1024 / 1024).toFixed(1)} MiB`;
+};
+
 const TerminalPanel = () => {
   const terminalRef = useRef(null);
   const [terminal, setTerminal] = useState(null);
   const [fitAddon, setFitAddon] = useState(null);
   const [sessionId, setSessionId] = useState(null);
   const [isConnected, setIsConnected] = useState(false);
+  // Newest session interrupted by a sidecar restart, offered for restore
+  const [restorable, setRestorable] = useState(null);
+  // onData is registered once, so it reads the current session through a ref
+  const sessionIdRef = useRef(null);
+  // Session whose history must be replayed before its live output (after a reload)
+  const replayRef = useRef(null);
+  // Output before this stream offset is already on screen
+  const seenOffsetRef = useRef(0);
+  // Recordings list, shown while not null
+  const [recordings, setRecordings] = useState(null);
+  // Recording being replayed in place of the live session: { id, title, marker, finished }
+  const [replay, setReplay] = useState(null);
+  // Replay whose events are drawn; live output and input pause while it is set
+  const replayingRef = useRef(null);
+  const replayQueueRef = useRef([]);
+  const replayHandlerRef = useRef(null);
+  // Bumped to replay the live session's history again after a recording replay
+  const [reattach, setReattach] = useState(0);
+
+  useEffect(() => {
+    sessionIdRef.current = sessionId;
+  }, [sessionId]);
 
   // Initialize terminal
   useEffect(() => {
@@ -61,11 +98,32 @@ const TerminalPanel = () => {
       setTerminal(term);
       setFitAddon(fit);
 
+      // Reattach to the newest shell still running in the backend
+      invoke('list_terminals')
+        .then(sessions => {
+          const session = sessions[sessions.length - 1];
+          if (session) {
+            replayRef.current = session.id;
+            setSessionId(session.id);
+            setIsConnected(true);
+            invoke('resize_terminal', {
+              sessionId: session.id,
+              rows: term.rows,
+              cols: term.cols
+            }).catch(console.error);
+            return;
+          }
+          return invoke('list_restorable_terminals').then(interrupted => {
+            setRestorable(interrupted[interrupted.length - 1] || null);
+          });
+        })
+        .catch(console.error);
+
       // Handle user input
       term.onData(data => {
-        if (sessionId) {
+        if (sessionIdRef.current && !replayingRef.current) {
           invoke('write_to_terminal', {
-            sessionId,
+            sessionId: sessionIdRef.current,
             data
           }).catch(console.error);
         }
@@ -75,22 +133,138 @@ const TerminalPanel = () => {
         term.dispose();
       };
     }
-  }, [terminalRef.current]);
+  }, []);
 
   // Listen for terminal output events
   useEffect(() => {
     if (terminal) {
+      const writeFrame = ({ session_id, seq, offset, data, resync }) => {
+        // Acknowledge once xterm has rendered the frame, so the backend paces itself
+        const ack = () => invoke('ack_terminal_output', { sessionId: session_id, seq }).catch(() => {});
+        if (replayingRef.current) {
+          ack(); // shown from scrollback once the replay is closed
+          return;
+        }
+        const bytes = encoder.encode(data);
+        const end = offset + bytes.length;
+        if (end <= seenOffsetRef.current) {
+          ack(); // already replayed from scrollback
+          return;
+        }
+
+        if (resync) {
+          // The backend skipped output we fell behind on; start over from its tail
+          terminal.reset();
+          terminal.write(data, ack);
+        } else if (offset < seenOffsetRef.current) {
+          terminal.write(decoder.decode(bytes.subarray(seenOffsetRef.current - offset)), ack);
+        } else {
+          terminal.write(data, ack);
+        }
+        seenOffsetRef.current = end;
+      };
+
+      // While history is replayed, live frames wait their turn
+      let pending = replayRef.current === sessionId ? [] : null;
+      let cancelled = false;
+
       const unlisten = listen('terminal_output', (event) => {
         if (event.payload.session_id === sessionId) {
-          terminal.write(event.payload.data);
+          if (pending) {
+            pending.push(event.payload);
+          } else {
+            writeFrame(event.payload);
+          }
+        }
+      });
+
+      if (pending) {
+        // Fetch only once the listener is live, so no frame falls in between
+        unlisten
+          .then(() => invoke('get_terminal_scrollback', { sessionId, fromOffset: 0 }))
+          .then(scrollback => {
+            if (cancelled) return;
+            replayRef.current = null;
+            terminal.reset();
+            terminal.write(scrollback.data);
+            seenOffsetRef.current = scrollback.next_offset;
+            pending.splice(0).forEach(writeFrame);
+            pending = null;
+          })
+          .catch(error => {
+            console.error('Failed to replay terminal history:', error);
+            if (cancelled) return;
+            replayRef.current = null;
+            pending?.splice(0).forEach(writeFrame);
+            pending = null;
+          });
+      }
+
+      const unlistenExit = listen('terminal_exit', (event) => {
+        if (event.payload.session_id === sessionId) {
+          const code = event.payload.exit_code;
+          terminal.write(`\r\n\x1b[33mShell exited${code === null ? '' : ` with code ${code}`}\x1b[0m\r\n`);
+          setSessionId(null);
+          setIsConnected(false);
         }
       });
 
       return () => {
+        cancelled = true;
         unlisten.then(fn => fn());
+        unlistenExit.then(fn => fn());
       };
     }
-  }, [terminal, sessionId]);
+  }, [terminal, sessionId, reattach]);
+
+  // Draw the events of a recording replay
+  useEffect(() => {
+    if (!terminal) return;
+    const handleReplay = (kind, payload) => {
+      if (replayingRef.current === REPLAY_STARTING) {
+        replayQueueRef.current.push([kind, payload]);
+        return;
+      }
+      if (payload.replay_id !== replayingRef.current) return;
+
+      if (kind === 'end') {
+        const how = payload.stopped ? 'stopped' : 'finished';
+        terminal.write(`\r\n\x1b[33mReplay ${how}${payload.complete ? '' : ' (recording is incomplete)'}\x1b[0m\r\n`);
+        setReplay(current => current && { ...current, finished: true });
+      } else if (payload.code === 'o') {
+        terminal.write(payload.data);
+      } else if (payload.code === 'r') {
+        const [cols, rows] = payload.data.split('x').map(Number);
+        if (cols && rows) terminal.resize(cols, rows);
+      } else if (payload.code === 'm') {
+        setReplay(current => current && { ...current, marker: payload.data });
+      }
+    };
+    replayHandlerRef.current = handleReplay;
+
+    const unlisten = listen('recording_replay', (event) => handleReplay('frame', event.payload));
+    const unlistenEnd = listen('recording_replay_end', (event) => handleReplay('end', event.payload));
+    return () => {
+      unlisten.then(fn => fn());
+      unlistenEnd.then(fn => fn());
+    };
+  }, [terminal]);
+
+  // Show sessions GeanyLua opens over HTTP, unless the user already has one open
+  useEffect(() => {
+    if (!terminal) return;
+    const unlisten = listen('terminal_created', (event) => {
+      if (!sessionIdRef.current) {
+        replayRef.current = event.payload.id;
+        setRestorable(null);
+        setSessionId(event.payload.id);
+        setIsConnected(true);
+      }
+    });
+    return () => {
+      unlisten.then(fn => fn());
+    };
+  }, [terminal]);
 
   // Handle window resize
   useEffect(() => {
@@ -113,22 +287,22 @@ const TerminalPanel = () => {
 
   const createNewSession = async () => {
     try {
+      // The shell comes from the terminal_shell setting
+      fitAddon?.fit();
       const response = await invoke('create_terminal', {
-        shell: '/bin/bash',
         rows: terminal?.rows || 24,
         cols: terminal?.cols || 80
       });
 
-      setSessionId(response.session_id);
-      setIsConnected(true);
-
+      seenOffsetRef.current = 0;
       if (terminal) {
         terminal.clear();
-        terminal.write(`\r\n\x1b[32mTerminal session started (${response.session_id})\x1b[0m\r\n`);
-        terminal.write(`\x1b[90mShell: ${response.shell}\x1b[0m\r\n`);
-        terminal.write(`\x1b[90mSize: ${response.cols}x${response.rows}\x1b[0m\r\n\r\n`);
-        terminal.write('$ ');
+        terminal.write(`\x1b[90mShell: ${response.shell} (${response.cols}x${response.rows})\x1b[0m\r\n`);
+        terminal.focus();
       }
+
+      setSessionId(response.session_id);
+      setIsConnected(true);
     } catch (error) {
       console.error('Failed to create terminal session:', error);
       if (terminal) {
@@ -137,6 +311,111 @@ const TerminalPanel = () => {
     }
   };
 
+  const restoreSession = async () => {
+    if (!restorable) return;
+    try {
+      const response = await invoke('restore_terminal', { sessionId: restorable.id });
+
+      seenOffsetRef.current = 0;
+      if (terminal) {
+        terminal.reset();
+        if (response.scrollback) {
+          // Output from before the restart, greyed out above the new shell
+          const previous = response.scrollback.replace(ANSI_PATTERN, '');
+          terminal.write(`\x1b[90m${previous}\x1b[0m\r\n`);
+        }
+        terminal.write(`\x1b[90mRestored: ${response.shell} in ${response.cwd || '~'}\x1b[0m\r\n`);
+        terminal.focus();
+      }
+
+      setRestorable(null);
+      setSessionId(response.session_id);
+      setIsConnected(true);
+      if (terminal && (terminal.rows !== response.rows || terminal.cols !== response.cols)) {
+        invoke('resize_terminal', {
+          sessionId: response.session_id,
+          rows: terminal.rows,
+          cols: terminal.cols
+        }).catch(console.error);
+      }
+    } catch (error) {
+      console.error('Failed to restore terminal session:', error);
+      setRestorable(null);
+      if (terminal) {
+        terminal.write(`\r\n\x1b[31mFailed to restore terminal session: ${error}\x1b[0m\r\n`);
+      }
+    }
+  };
+
+  const showRecordings = async () => {
+    if (recordings) {
+      setRecordings(null);
+      return;
+    }
+    try {
+      const list = await invoke('list_recordings');
+      setRecordings(list.reverse());
+    } catch (error) {
+      console.error('Failed to list recordings:', error);
+    }
+  };
+
+  const startReplay = async (recording) => {
+    if (!terminal) return;
+    setRecordings(null);
+    terminal.reset();
+    replayQueueRef.current.length = 0;
+    replayingRef.current = REPLAY_STARTING;
+    setReplay({ id: null, title: recording.title || recording.id, marker: null, finished: false });
+    try {
+      const replayId = await invoke('replay_recording', { recordingId: recording.id });
+      replayingRef.current = replayId;
+      setReplay(current => current && { ...current, id: replayId });
+      replayQueueRef.current.splice(0).forEach(([kind, payload]) => replayHandlerRef.current(kind, payload));
+    } catch (error) {
+      console.error('Failed to replay recording:', error);
+      replayingRef.current = -1; // keeps live output paused until the replay is closed
+      terminal.write(`\r\n\x1b[31mFailed to replay recording: ${error}\x1b[0m\r\n`);
+      setReplay(current => current && { ...current, finished: true });
+    }
+  };
+
+  // Back to the live session, redrawn from its scrollback
+  const closeReplay = () => {
+    if (replay?.id && !replay.finished) {
+      invoke('stop_replay', { replayId: replay.id }).catch(console.error);
+    }
+    replayingRef.current = null;
+    setReplay(null);
+    if (!terminal) return;
+    terminal.reset();
+    fitAddon?.fit();
+    if (sessionId) {
+      replayRef.current = sessionId;
+      setReattach(n => n + 1);
+      invoke('resize_terminal', { sessionId, rows: terminal.rows, cols: terminal.cols }).catch(console.error);
+    }
+  };
+
+  const exportRecording = async (recording) => {
+    try {
+      // Written to the downloads directory by the sidecar
+      const artifact = await invoke('export_recording', { recordingId: recording.id });
+      setRecordings(current => current && current.map(r => (r.id === recording.id ? { ...r, exported_to: artifact.path } : r)));
+    } catch (error) {
+      console.error('Failed to export recording:', error);
+    }
+  };
+
+  const deleteRecording = async (recording) => {
+    try {
+      await invoke('delete_recording', { recordingId: recording.id });
+      setRecordings(current => current && current.filter(r => r.id !== recording.id));
+    } catch (error) {
+      console.error('Failed to delete recording:', error);
+    }
+  };
+
   const killSession = async () => {
     if (sessionId) {
       try {
@@ -165,8 +444,8 @@ const TerminalPanel = () => {
             <div>
               <h2 className="text-lg font-semibold text-dark-100">Terminal</h2>
               <p className="text-sm text-dark-400">
-                Embedded terminal emulator
-                {sessionId && (
+                {replay ? `Replaying ${replay.title}${replay.marker ? ` — ${replay.marker}` : ''}` : 'Embedded terminal emulator'}
+                {sessionId && !replay && (
                   <span className="ml-2 inline-flex items-center px-2 py-0.5 rounded text-xs font-medium bg-green-100 text-green-800 dark:bg-green-900 dark:text-green-200">
                     <div className="w-1.5 h-1.5 bg-green-500 rounded-full mr-1"></div>
                     Connected
@@ -177,6 +456,23 @@ const TerminalPanel = () => {
           </div>
 
           <div className="flex items-center space-x-2">
+            {replay ? (
+              <button
+                onClick={closeReplay}
+                className="button-secondary flex items-center space-x-2 text-sm"
+              >
+                <X className="w-4 h-4" />
+                <span>{replay.finished ? 'Close Replay' : 'Stop Replay'}</span>
+              </button>
+            ) : (
+              <button
+                onClick={showRecordings}
+                className="button-secondary flex items-center space-x-2 text-sm"
+              >
+                <Film className="w-4 h-4" />
+                <span>Recordings</span>
+              </button>
+            )}
             {!isConnected ? (
               <button
                 onClick={createNewSession}
@@ -199,33 +495,107 @@ const TerminalPanel = () => {
       </div>
 
       {/* Terminal Content */}
-      <div className="flex-1 overflow-hidden">
-        {!isConnected ? (
-          <div className="h-full flex items-center justify-center">
+      <div className="flex-1 overflow-hidden relative">
+        {/* Always mounted so xterm keeps its buffer between sessions */}
+        <div className={`h-full p-4 ${isConnected || replay ? '' : 'invisible'}`}>
+          <div className="terminal-container h-full">
+            <div
+              ref={terminalRef}
+              className="h-full w-full"
+              style={{ minHeight: '400px' }}
+            />
+          </div>
+        </div>
+
+        {!isConnected && !replay && (
+          <div className="absolute inset-0 flex items-center justify-center">
             <div className="text-center">
               <TerminalIcon className="w-12 h-12 text-dark-600 mx-auto mb-4" />
               <h3 className="text-lg font-medium text-dark-300 mb-2">No terminal session</h3>
               <p className="text-dark-500 mb-6">
                 Create a new terminal session to get started
               </p>
-              <button
-                onClick={createNewSession}
-                className="button-primary flex items-center space-x-2 mx-auto"
-              >
-                <Plus className="w-4 h-4" />
-                <span>New Terminal</span>
-              </button>
+              <div className="flex items-center justify-center space-x-2">
+                <button
+                  onClick={createNewSession}
+                  className="button-primary flex items-center space-x-2"
+                >
+                  <Plus className="w-4 h-4" />
+                  <span>New Terminal</span>
+                </button>
+                {restorable && (
+                  <button
+                    onClick={restoreSession}
+                    className="button-secondary flex items-center space-x-2"
+                    title={`${restorable.shell} in ${restorable.cwd || '~'}`}
+                  >
+                    <RotateCcw className="w-4 h-4" />
+                    <span>Restore Session</span>
+                  </button>
+                )}
+              </div>
             </div>
           </div>
-        ) : (
-          <div className="h-full p-4">
-            <div className="terminal-container h-full">
-              <div
-                ref={terminalRef}
-                className="h-full w-full"
-                style={{ minHeight: '400px' }}
-              />
-            </div>
+        )}
+
+        {recordings && (
+          <div className="absolute inset-0 overflow-y-auto bg-dark-950 p-4">
+            {recordings.length === 0 ? (
+              <p className="text-center text-dark-500 mt-8">
+                No recordings yet. Turn on session recording in Settings.
+              </p>
+            ) : (
+              <ul className="space-y-2">
+                {recordings.map(recording => (
+                  <li key={recording.id} className="flex items-center justify-between p-3 bg-dark-900 border border-dark-700 rounded-lg">
+                    <div className="min-w-0">
+                      <div className="flex items-center space-x-2 text-sm text-dark-200">
+                        {recording.encrypted && <Lock className="w-3 h-3 text-dark-400" />}
+                        <span className="truncate">{recording.title || recording.id}</span>
+                        {!recording.complete && (
+                          <span className="px-1.5 py-0.5 rounded text-xs bg-dark-700 text-dark-300">incomplete</span>
+                        )}
+                      </div>
+                      <p className="text-xs text-dark-500 mt-1">
+                        {new Date(recording.started_at || recording.updated_at).toLocaleString()}
+                        {' · '}{formatSize(recording.size_bytes)}
+                        {recording.width && ` · ${recording.width}x${recording.height}`}
+                        {recording.error && ` · ${recording.error}`}
+                      </p>
+                      {recording.exported_to && (
+                        <p className="text-xs text-dark-400 mt-1 truncate">Saved to {recording.exported_to}</p>
+                      )}
+                    </div>
+                    <div className="flex items-center space-x-1 ml-4">
+                      <button
+                        onClick={() => startReplay(recording)}
+                        disabled={!!recording.error}
+                        className="p-2 rounded hover:bg-dark-700 text-dark-300 disabled:opacity-40"
+                        title="Replay"
+                      >
+                        <Play className="w-4 h-4" />
+                      </button>
+                      <button
+                        onClick={() => exportRecording(recording)}
+                        disabled={!!recording.error}
+                        className="p-2 rounded hover:bg-dark-700 text-dark-300 disabled:opacity-40"
+                        title="Export as asciicast"
+                      >
+                        <Download className="w-4 h-4" />
+                      </button>
+                      <button
+                        onClick={() => deleteRecording(recording)}
+                        disabled={recording.id === sessionId}
+                        className="p-2 rounded hover:bg-dark-700 text-dark-300 disabled:opacity-40"
+                        title="Delete"
+                      >
+                        <Trash2 className="w-4 h-4" />
+                      </button>
+                    </div>
+                  </li>
+                ))}
+              </ul>
+            )}
           </div>
         )}
       </div>